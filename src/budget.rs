@@ -0,0 +1,90 @@
+//! Memory budget for dense unitaries.
+//!
+//! The unitary of a term on n qubits is a dense 2^n x 2^n matrix, so the memory it needs grows
+//! quickly with n. Its size is estimated before it is built, and checked against a
+//! configurable limit.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use miette::Diagnostic;
+use num_complex::Complex;
+use thiserror::Error;
+
+/// Default memory limit for a dense unitary, in bytes.
+pub const DEFAULT_LIMIT: usize = 1 << 30;
+
+static LIMIT: AtomicUsize = AtomicUsize::new(DEFAULT_LIMIT);
+
+/// Set the memory limit for a dense unitary, in bytes.
+pub fn set_limit(bytes: usize) {
+    LIMIT.store(bytes, Ordering::Relaxed);
+}
+
+/// Returns the memory limit for a dense unitary, in bytes.
+pub fn limit() -> usize {
+    LIMIT.load(Ordering::Relaxed)
+}
+
+/// Returns an estimate of the memory needed to build the unitary of a term on the given
+/// number of qubits, or `None` if it does not fit in a `usize`.
+/// The unitary is built in chunks of columns which are then copied into the result, so
+/// space for two copies is needed.
+pub fn unitary_bytes(qubits: usize) -> Option<usize> {
+    let entries = 1usize.checked_shl(u32::try_from(qubits.checked_mul(2)?).ok()?)?;
+    entries.checked_mul(2 * size_of::<Complex<f64>>())
+}
+
+/// Returns true if the unitary of a term on the given number of qubits fits in the limit.
+pub fn fits(qubits: usize) -> bool {
+    unitary_bytes(qubits).is_some_and(|bytes| bytes <= limit())
+}
+
+/// Error for a dense matrix which would need more memory than the limit allows.
+#[derive(Error, Diagnostic, Debug, Clone)]
+#[error("Unitary on {qubits} qubits needs {required} of memory, over the limit of {limit}.")]
+#[diagnostic(
+    code("Unitary too large."),
+    help("Raise the memory limit with --memory-limit, or work with fewer qubits.")
+)]
+pub struct TooLargeError {
+    /// Number of qubits of the unitary
+    pub qubits: usize,
+    /// Estimated memory needed for the unitary
+    pub required: String,
+    /// Memory limit
+    pub limit: String,
+}
+
+/// Check that the unitary of a term on the given number of qubits fits in the limit.
+/// Used by the builders of dense matrices.
+pub fn check_fits(qubits: usize) -> Result<(), TooLargeError> {
+    if fits(qubits) {
+        Ok(())
+    } else {
+        Err(TooLargeError {
+            qubits,
+            required: format_bytes(unitary_bytes(qubits)),
+            limit: format_bytes(Some(limit())),
+        })
+    }
+}
+
+/// Format a number of bytes using binary units, or "more than 16EiB" if it does not fit in a
+/// `usize`.
+pub fn format_bytes(bytes: Option<usize>) -> String {
+    const UNITS: [&str; 7] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+    let Some(bytes) = bytes else {
+        return "more than 16EiB".to_owned();
+    };
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes}B")
+    } else {
+        format!("{size:.1}{}", UNITS[unit])
+    }
+}
@@ -0,0 +1,201 @@
+//! Lowering of circuit-normal terms to one- and two-qubit gates.
+//!
+//! Each clause is a phase on the subspace picked out by its pattern, i.e. a multi-controlled
+//! phase whose controls may be in the computational or Hadamard basis.
+//! Controls are first moved to |1> by X and H gates, and the remaining phase on |1...1>
+//! is then decomposed according to a `Decomposition`.
+
+use crate::{
+    circuit_syntax::{TermC, term::ClauseC},
+    lowered_syntax::{CircuitL, GateL},
+};
+
+/// Strategy for decomposing phases with many controls.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Decomposition {
+    /// Expand the phase into a phase polynomial over parities of the controls.
+    /// Uses 2^k - 1 rotations for k controls and no extra qubits.
+    NoAncilla,
+    /// Compute the conjunction of the controls into clean ancillas with Toffoli gates.
+    /// Uses k - 2 ancillas and 2(k - 2) Toffoli gates for k controls.
+    Ancilla,
+}
+
+/// Two-qubit gate used in lowered circuits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Entangler {
+    /// Controlled not gates
+    CX,
+    /// Controlled Z gates, conjugating by Hadamards where a controlled not is needed
+    CZ,
+}
+
+impl TermC {
+    /// Decompose each clause of a circuit-normal term into one- and two-qubit gates.
+    /// The resulting circuit uses Hadamard, X, Rz, and the chosen entangling gate.
+    /// Clauses applying numerical powers have no decomposition and are skipped, so terms
+    /// with `opaque_powers` should not be lowered.
+    pub fn lower(&self, decomposition: Decomposition, entangler: Entangler) -> CircuitL {
+        let max_controls = self
+            .clauses
+            .iter()
+            .map(|c| c.pattern.parts.iter().flatten().count())
+            .max()
+            .unwrap_or(0);
+        let ancillas = match decomposition {
+            Decomposition::NoAncilla => 0,
+            Decomposition::Ancilla => max_controls.saturating_sub(2),
+        };
+        let mut lowering = Lowering {
+            circuit: CircuitL::new(self.ty, ancillas),
+            decomposition,
+            entangler,
+        };
+        for clause in self.clauses.iter().filter(|c| c.power.is_none()) {
+            lowering.clause(clause);
+        }
+        lowering.circuit
+    }
+}
+
+struct Lowering {
+    circuit: CircuitL,
+    decomposition: Decomposition,
+    entangler: Entangler,
+}
+
+impl Lowering {
+    fn push(&mut self, gate: GateL) {
+        self.circuit.gates.push(gate);
+    }
+
+    fn cx(&mut self, control: usize, target: usize) {
+        match self.entangler {
+            Entangler::CX => self.push(GateL::CX(control, target)),
+            Entangler::CZ => {
+                self.push(GateL::H(target));
+                self.push(GateL::CZ(control, target));
+                self.push(GateL::H(target));
+            }
+        }
+    }
+
+    /// Phase gate diag(1, e^(i pi angle)), as an Rz rotation and a global phase.
+    fn phase_gate(&mut self, wire: usize, angle: f64) {
+        self.push(GateL::Rz(wire, angle));
+        self.circuit.phase += angle / 2.0;
+    }
+
+    fn clause(&mut self, clause: &ClauseC) {
+        let mut basis_change = vec![];
+        let mut controls = vec![];
+        for (wire, part) in clause.pattern.parts.iter().enumerate() {
+            if let Some(state) = part {
+                controls.push(wire);
+                if state.is_hadamard_basis() {
+                    basis_change.push(GateL::H(wire));
+                }
+                if !state.bit() {
+                    basis_change.push(GateL::X(wire));
+                }
+            }
+        }
+        self.circuit.gates.extend(basis_change.iter().cloned());
+        self.controlled_phase(&controls, clause.phase);
+        self.circuit.gates.extend(basis_change.into_iter().rev());
+    }
+
+    /// Apply the given phase to the state where all `controls` are |1>.
+    fn controlled_phase(&mut self, controls: &[usize], angle: f64) {
+        match controls.len() {
+            0 => self.circuit.phase += angle,
+            k if k > 2 && self.decomposition == Decomposition::Ancilla => {
+                self.ancilla_phase(controls, angle)
+            }
+            _ => self.parity_phase(controls, angle),
+        }
+    }
+
+    /// Uses x_1 ... x_k = 2^(1-k) * sum over non-empty S of (-1)^(|S|+1) (xor of x_i for i in S),
+    /// computing each parity onto the last wire of S with controlled nots.
+    fn parity_phase(&mut self, controls: &[usize], angle: f64) {
+        let k = controls.len();
+        let scale = angle / (1u64 << (k - 1)) as f64;
+        for subset in 1..(1usize << k) {
+            let wires: Vec<usize> = controls
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| subset >> i & 1 == 1)
+                .map(|(_, w)| *w)
+                .collect();
+            let (&target, rest) = wires.split_last().unwrap();
+            let sign = if wires.len() % 2 == 1 { 1.0 } else { -1.0 };
+            for &c in rest {
+                self.cx(c, target);
+            }
+            self.phase_gate(target, sign * scale);
+            for &c in rest.iter().rev() {
+                self.cx(c, target);
+            }
+        }
+    }
+
+    /// Computes the conjunction of all but the last control into ancillas,
+    /// leaving a phase with two controls.
+    fn ancilla_phase(&mut self, controls: &[usize], angle: f64) {
+        let (&last, rest) = controls.split_last().unwrap();
+        let mut ladder = vec![];
+        let mut acc = rest[0];
+        for (i, &c) in rest[1..].iter().enumerate() {
+            let ancilla = self.circuit.ty.0 + i;
+            ladder.push((acc, c, ancilla));
+            acc = ancilla;
+        }
+        for &(a, b, t) in &ladder {
+            self.toffoli(a, b, t);
+        }
+        self.parity_phase(&[acc, last], angle);
+        for &(a, b, t) in ladder.iter().rev() {
+            self.toffoli(a, b, t);
+        }
+    }
+
+    fn toffoli(&mut self, a: usize, b: usize, target: usize) {
+        self.push(GateL::H(target));
+        self.parity_phase(&[a, b, target], 1.0);
+        self.push(GateL::H(target));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::check_source;
+
+    #[test]
+    fn decomposition_matches_unitary_for_small_k() {
+        let kets = ["1", "0+", "1-0", "+01-", "11011"];
+        for ket in kets {
+            let k = ket.len();
+            let (_, term) = check_source(&format!("if let |{ket}> then ph(0.3pi)"));
+            let unitary = term.to_unitary().unwrap();
+            let circuit = term.eval_circ();
+            for decomposition in [Decomposition::NoAncilla, Decomposition::Ancilla] {
+                for entangler in [Entangler::CX, Entangler::CZ] {
+                    let lowered = circuit.lower(decomposition, entangler);
+                    assert!(lowered.gates.iter().all(|g| g.wires().len() <= 2));
+                    let ancillas = match decomposition {
+                        Decomposition::NoAncilla => 0,
+                        Decomposition::Ancilla => k.saturating_sub(2),
+                    };
+                    assert_eq!(lowered.ancillas, ancillas);
+                    let distance = lowered.distance(&unitary);
+                    assert!(
+                        distance < 1e-9,
+                        "|{ket}> with {decomposition:?} and {entangler:?} deviates by {distance}"
+                    );
+                }
+            }
+        }
+    }
+}
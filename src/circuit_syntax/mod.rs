@@ -6,3 +6,14 @@ pub mod term;
 pub use term::TermC;
 
 pub mod pattern;
+
+pub mod lower;
+
+pub mod optimise;
+
+pub mod phase_polynomial;
+
+pub mod repeat;
+pub use repeat::RepeatC;
+
+pub mod schedule;
@@ -0,0 +1,111 @@
+//! Optimisation of circuit-normal terms.
+//!
+//! Evaluating an "if let" to circuit-normal form surrounds its body with clauses computing
+//! and uncomputing the pattern, and these frequently cancel against each other. Each clause
+//! is a controlled phase on the projector given by its pattern, so two clauses commute
+//! whenever their projectors do. Clauses are moved past commuting clauses to be merged
+//! with an earlier clause with the same pattern, and clauses whose phase is trivial are
+//! dropped.
+
+use crate::circuit_syntax::{TermC, term::ClauseC};
+
+/// Phases within this distance of a multiple of 2 are treated as trivial.
+const PHASE_TOLERANCE: f64 = 1e-12;
+
+/// Summary of an optimisation pass.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OptimiseReport {
+    /// Number of clauses before optimisation.
+    pub before: usize,
+    /// Number of clauses after optimisation.
+    pub after: usize,
+}
+
+impl TermC {
+    /// Merge clauses with identical patterns, commuting clauses past each other where
+    /// possible, and remove clauses with trivial phase.
+    /// The unitary of the term is unchanged.
+    pub fn optimise(&mut self) -> OptimiseReport {
+        let before = self.clauses.len();
+        // Removing a clause can allow further merges, so repeat until nothing changes.
+        loop {
+            let len = self.clauses.len();
+            let old_clauses = std::mem::take(&mut self.clauses);
+            for clause in old_clauses {
+                merge_clause(&mut self.clauses, clause);
+            }
+            if self.clauses.len() == len {
+                break;
+            }
+        }
+        OptimiseReport {
+            before,
+            after: self.clauses.len(),
+        }
+    }
+}
+
+/// Add `clause` to the end of `clauses`, merging it with an earlier clause if one with the
+/// same pattern can be reached by commuting past the clauses in between.
+fn merge_clause(clauses: &mut Vec<ClauseC>, clause: ClauseC) {
+    if clause.power.is_none() && is_trivial(clause.phase) {
+        return;
+    }
+    for i in (0..clauses.len()).rev() {
+        if clauses[i].power.is_none()
+            && clause.power.is_none()
+            && clauses[i].pattern == clause.pattern
+        {
+            clauses[i].phase += clause.phase;
+            if is_trivial(clauses[i].phase) {
+                clauses.remove(i);
+            }
+            return;
+        }
+        if !clauses[i].commutes_with(&clause) {
+            break;
+        }
+    }
+    clauses.push(clause);
+}
+
+fn is_trivial(phase: f64) -> bool {
+    let r = phase.rem_euclid(2.0);
+    r < PHASE_TOLERANCE || 2.0 - r < PHASE_TOLERANCE
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command::check_source;
+
+    /// Optimise the circuit of a term, checking that its unitary is unchanged, and return
+    /// the number of clauses before and after.
+    fn optimise(src: &str) -> (usize, usize) {
+        let (_, term) = check_source(src);
+        let mut circuit = term.eval_circ();
+        let unitary = circuit.to_unitary().unwrap();
+        let report = circuit.optimise();
+        assert_eq!(report.after, circuit.num_clauses());
+        let deviation = (circuit.to_unitary().unwrap() - unitary).norm_max();
+        assert!(deviation < 1e-9, "Optimising {src} changed its unitary");
+        (report.before, report.after)
+    }
+
+    #[test]
+    fn merges_clauses_past_commuting_clauses() {
+        let src = "if let |10> then ph(0.25pi) ; if let |01> then -1 ; if let |10> then ph(0.5pi)";
+        assert_eq!(optimise(src), (3, 2));
+    }
+
+    #[test]
+    fn drops_clauses_with_trivial_phase() {
+        let src = "if let |1> x id then ph(0.5pi) x id ; if let |1> x id then ph(1.5pi) x id";
+        assert_eq!(optimise(src), (2, 0));
+    }
+
+    #[test]
+    fn keeps_clauses_which_do_not_commute() {
+        let src = "if let |0> then -1 ; if let |+> then -1 ; if let |0> then -1";
+        assert_eq!(optimise(src), (3, 3));
+    }
+}
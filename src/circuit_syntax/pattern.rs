@@ -35,4 +35,88 @@ impl PatternC {
             parts: vec![None; l],
         }
     }
+
+    /// Returns true if the projectors given by the two patterns commute, so that clauses
+    /// with these patterns commute.
+    /// This holds when the patterns are orthogonal, or when they agree on the basis of every
+    /// wire they both match on.
+    pub(crate) fn commutes_with(&self, other: &PatternC) -> bool {
+        let shared = self
+            .parts
+            .iter()
+            .zip(&other.parts)
+            .filter_map(|(a, b)| Some(((*a)?, (*b)?)));
+        let mut same_basis = true;
+        for (a, b) in shared {
+            if a.is_hadamard_basis() != b.is_hadamard_basis() {
+                same_basis = false;
+            } else if a != b {
+                return true;
+            }
+        }
+        same_basis
+    }
+
+    /// Returns true if the subspaces matched by the two patterns are orthogonal, as they match
+    /// complementary states on a common wire.
+    pub(crate) fn is_orthogonal_to(&self, other: &PatternC) -> bool {
+        self.parts
+            .iter()
+            .zip(&other.parts)
+            .any(|(a, b)| matches!((a, b), (Some(a), Some(b)) if *a == b.compl()))
+    }
+
+    /// Returns disjoint patterns matching the same subspace as this pattern, with a ket on
+    /// every wire where `states` has one, splitting any identity on such a wire into the
+    /// given state and its complement.
+    pub(crate) fn split_on(&self, states: &[Option<KetState>]) -> Vec<PatternC> {
+        let mut pieces = vec![self.clone()];
+        for (wire, state) in states.iter().enumerate() {
+            let Some(state) = *state else {
+                continue;
+            };
+            if self.parts[wire].is_some() {
+                continue;
+            }
+            pieces = pieces
+                .into_iter()
+                .flat_map(|piece| {
+                    [state, state.compl()].map(|s| {
+                        let mut piece = piece.clone();
+                        piece.parts[wire] = Some(s);
+                        piece
+                    })
+                })
+                .collect();
+        }
+        pieces
+    }
+
+    /// Returns disjoint patterns matching the part of the subspace matched by this pattern
+    /// which is orthogonal to the subspace matched by `other`.
+    /// Returns `None` if the patterns match states in different bases on some wire, in which
+    /// case this part can not be written as patterns of kets.
+    pub(crate) fn subtract(&self, other: &PatternC) -> Option<Vec<PatternC>> {
+        if self.is_orthogonal_to(other) {
+            return Some(vec![self.clone()]);
+        }
+        let mut rest = self.clone();
+        let mut pieces = vec![];
+        for (wire, part) in other.parts.iter().enumerate() {
+            let Some(state) = *part else {
+                continue;
+            };
+            match rest.parts[wire] {
+                Some(s) if s == state => {}
+                Some(_) => return None,
+                None => {
+                    let mut piece = rest.clone();
+                    piece.parts[wire] = Some(state.compl());
+                    pieces.push(piece);
+                    rest.parts[wire] = Some(state);
+                }
+            }
+        }
+        Some(pieces)
+    }
 }
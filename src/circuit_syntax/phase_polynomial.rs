@@ -0,0 +1,239 @@
+//! Phase polynomials of diagonal runs of circuit-normal clauses.
+//!
+//! A clause whose pattern only uses |0> and |1> is diagonal in the computational basis,
+//! applying the phase theta to basis states x with x_i = v_i for each matched wire i.
+//! Such clauses commute, and a run of them is equivalent to the phase function
+//! f(x) = sum_M c_M prod_{i in M} x_i (taken modulo 2, in units of pi), obtained by
+//! expanding each indicator [x_i = 0] as 1 - x_i. Terms with the same monomial M are
+//! folded together, and each remaining term is resynthesised as a single clause matching
+//! |1> on the wires of M.
+//!
+//! A run is extended by moving later diagonal clauses back past the non-diagonal clauses
+//! in between, whenever they commute.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    circuit_syntax::{TermC, pattern::PatternC, term::ClauseC},
+    ket::KetState,
+};
+
+/// Clauses matching |0> on more wires than this are not expanded.
+const MAX_ZERO_WIRES: usize = 12;
+
+/// Coefficients within this distance of a multiple of 2 are treated as zero.
+const PHASE_TOLERANCE: f64 = 1e-12;
+
+/// A phase polynomial over the computational basis, sending x to
+/// e^(i pi sum_M c_M prod_{i in M} x_i).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PhasePolynomial {
+    /// Coefficients, divided by pi, indexed by the sorted wires of their monomial.
+    terms: BTreeMap<Vec<usize>, f64>,
+}
+
+/// Summary of a phase folding pass.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PhaseFoldReport {
+    /// Number of maximal runs of diagonal clauses.
+    pub runs: usize,
+    /// Number of clauses before folding.
+    pub before: usize,
+    /// Number of clauses after folding.
+    pub after: usize,
+}
+
+impl PhasePolynomial {
+    /// Build the phase polynomial of a sequence of diagonal clauses.
+    /// Returns `None` if a clause is not diagonal, or matches |0> on too many wires.
+    pub(crate) fn from_clauses<'a>(clauses: impl IntoIterator<Item = &'a ClauseC>) -> Option<Self> {
+        let mut poly = PhasePolynomial::default();
+        for clause in clauses {
+            poly.add_clause(clause)?;
+        }
+        Some(poly)
+    }
+
+    fn add_clause(&mut self, clause: &ClauseC) -> Option<()> {
+        let mut ones = vec![];
+        let mut zeros = vec![];
+        for (wire, part) in clause.pattern.parts.iter().enumerate() {
+            match part {
+                None => {}
+                Some(KetState::One) => ones.push(wire),
+                Some(KetState::Zero) => zeros.push(wire),
+                Some(KetState::Plus | KetState::Minus) => return None,
+            }
+        }
+        if zeros.len() > MAX_ZERO_WIRES {
+            return None;
+        }
+        // prod_{i in ones} x_i prod_{j in zeros} (1 - x_j)
+        //   = sum_{A subset of zeros} (-1)^|A| prod_{i in ones + A} x_i
+        for subset in 0..1usize << zeros.len() {
+            let mut monomial = ones.clone();
+            monomial.extend(
+                zeros
+                    .iter()
+                    .enumerate()
+                    .filter(|(k, _)| subset & (1 << k) != 0)
+                    .map(|(_, w)| *w),
+            );
+            monomial.sort_unstable();
+            let sign = if subset.count_ones() % 2 == 0 {
+                1.0
+            } else {
+                -1.0
+            };
+            *self.terms.entry(monomial).or_insert(0.0) += sign * clause.phase;
+        }
+        Some(())
+    }
+
+    /// Returns the non-trivial terms of the polynomial, as the wires of each monomial
+    /// together with its coefficient divided by pi, reduced to lie in (-1, 1].
+    pub fn terms(&self) -> impl Iterator<Item = (&[usize], f64)> {
+        self.terms.iter().filter_map(|(monomial, c)| {
+            let c = c - 2.0 * (c / 2.0).round();
+            let c = if c <= -1.0 { c + 2.0 } else { c };
+            (c.abs() > PHASE_TOLERANCE).then_some((monomial.as_slice(), c))
+        })
+    }
+
+    /// Resynthesise the polynomial as clauses on the given number of wires, one for each
+    /// non-trivial term.
+    pub(crate) fn to_clauses(&self, qubits: usize) -> Vec<ClauseC> {
+        let mut terms: Vec<_> = self.terms().collect();
+        terms.sort_by_key(|(monomial, _)| monomial.len());
+        terms
+            .into_iter()
+            .map(|(monomial, phase)| {
+                let mut pattern = PatternC::id(qubits);
+                for &wire in monomial {
+                    pattern.parts[wire] = Some(KetState::One);
+                }
+                ClauseC {
+                    pattern,
+                    phase,
+                    power: None,
+                }
+            })
+            .collect()
+    }
+}
+
+fn is_diagonal(clause: &ClauseC) -> bool {
+    clause.power.is_none()
+        && clause
+            .pattern
+            .parts
+            .iter()
+            .all(|p| !p.is_some_and(KetState::is_hadamard_basis))
+}
+
+/// Cost of a sequence of clauses: the number of clauses, then the total number of matched
+/// wires.
+fn cost(clauses: &[ClauseC]) -> (usize, usize) {
+    (
+        clauses.len(),
+        clauses
+            .iter()
+            .map(|c| c.pattern.parts.len() - c.pattern.id_qubits())
+            .sum(),
+    )
+}
+
+impl TermC {
+    /// Replace each maximal run of diagonal clauses by the resynthesis of its phase
+    /// polynomial, when this has lower cost.
+    /// The unitary of the term is unchanged.
+    pub fn fold_phases(&mut self) -> PhaseFoldReport {
+        let before = self.clauses.len();
+        let mut runs = 0;
+        let mut run: Vec<ClauseC> = vec![];
+        // Non-diagonal clauses after the start of the current run.
+        let mut passed: Vec<ClauseC> = vec![];
+        for clause in std::mem::take(&mut self.clauses) {
+            if !is_diagonal(&clause) {
+                if run.is_empty() {
+                    self.clauses.push(clause);
+                } else {
+                    passed.push(clause);
+                }
+                continue;
+            }
+            if !passed.iter().all(|c| c.commutes_with(&clause)) {
+                self.push_run(&mut run, &mut passed);
+                runs += 1;
+            }
+            run.push(clause);
+        }
+        if !run.is_empty() {
+            self.push_run(&mut run, &mut passed);
+            runs += 1;
+        }
+        PhaseFoldReport {
+            runs,
+            before,
+            after: self.clauses.len(),
+        }
+    }
+
+    /// Add a run of diagonal clauses, folded if this lowers its cost, followed by the
+    /// non-diagonal clauses it was moved past.
+    fn push_run(&mut self, run: &mut Vec<ClauseC>, passed: &mut Vec<ClauseC>) {
+        let folded = PhasePolynomial::from_clauses(run.iter())
+            .map(|poly| poly.to_clauses(self.ty.0))
+            .filter(|folded| cost(folded) < cost(run));
+        match folded {
+            Some(folded) => {
+                self.clauses.extend(folded);
+                run.clear();
+            }
+            None => self.clauses.append(run),
+        }
+        self.clauses.append(passed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::check_source;
+
+    /// Fold the phases of the circuit of a term, checking that its unitary is unchanged.
+    fn fold(src: &str) -> PhaseFoldReport {
+        let (_, term) = check_source(src);
+        let mut circuit = term.eval_circ();
+        let unitary = circuit.to_unitary().unwrap();
+        let report = circuit.fold_phases();
+        assert_eq!(report.after, circuit.num_clauses());
+        let deviation = (circuit.to_unitary().unwrap() - unitary).norm_max();
+        assert!(deviation < 1e-9, "Folding {src} changed its unitary");
+        report
+    }
+
+    #[test]
+    fn zero_controls_expand_to_monomials() {
+        let (_, term) = check_source("if let |0> x |1> then ph(0.5pi)");
+        let circuit = term.eval_circ();
+        let poly = PhasePolynomial::from_clauses(&circuit.clauses).unwrap();
+        let terms: Vec<_> = poly.terms().collect();
+        assert_eq!(terms, [(&[0, 1][..], -0.5), (&[1][..], 0.5)]);
+    }
+
+    #[test]
+    fn folds_a_run_of_diagonal_clauses() {
+        // The four clauses together apply a global phase of -1.
+        let src = "if let |00> then -1 ; if let |01> then -1 ; if let |10> then -1 ; \
+                   if let |11> then -1";
+        let report = fold(src);
+        assert_eq!((report.runs, report.before, report.after), (1, 4, 1));
+    }
+
+    #[test]
+    fn hadamard_clauses_split_runs() {
+        let report = fold("if let |0> then -1 ; if let |+> then -1 ; if let |1> then -1");
+        assert_eq!((report.runs, report.before, report.after), (2, 3, 3));
+    }
+}
@@ -0,0 +1,158 @@
+//! Circuit-normal terms with repeated blocks of clauses.
+//!
+//! A repetition "repeat n { t }", or an integer power of a term with compositions, expands
+//! to n copies of the clauses of its body in a `TermC`. A `RepeatC` instead keeps a single
+//! copy of the body of each repetition, so that its unitary can be simulated without
+//! materialising the repeated clauses.
+
+use faer::Mat;
+use num_complex::Complex;
+
+use crate::{
+    budget::{self, TooLargeError},
+    circuit_syntax::{
+        TermC,
+        term::{ClauseC, ClauseOp},
+    },
+    parallel::{build_columns, identity_columns},
+    typed_syntax::{TermT, TermType},
+};
+
+/// A circuit-normal term whose clauses may be grouped into repeated blocks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RepeatC {
+    pub(crate) items: Vec<ItemC>,
+    pub(crate) ty: TermType,
+}
+
+/// A clause, or a block of items applied a number of times.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum ItemC {
+    Clause(ClauseC),
+    Repeat(Vec<ItemC>, usize),
+}
+
+/// An item with the unitaries of its opaque powers, which can be shared between threads.
+enum ItemOp<'a> {
+    Clause(ClauseOp<'a>),
+    Repeat(Vec<ItemOp<'a>>, usize),
+}
+
+impl RepeatC {
+    /// Returns the type of the term.
+    pub fn get_type(&self) -> TermType {
+        self.ty
+    }
+
+    /// Returns the number of clauses, counting the body of each repetition once.
+    pub fn num_clauses(&self) -> usize {
+        count_clauses(&self.items, false)
+    }
+
+    /// Returns the number of clauses once every repetition is expanded, saturating at
+    /// `usize::MAX`.
+    pub fn num_expanded_clauses(&self) -> usize {
+        count_clauses(&self.items, true)
+    }
+
+    /// Expand every repetition, giving the equivalent circuit-normal term.
+    pub fn expand(&self) -> TermC {
+        let mut clauses = vec![];
+        expand_items(&self.items, &mut clauses);
+        TermC {
+            clauses,
+            ty: self.ty,
+        }
+    }
+
+    /// Return a `TermT` which is the "quotation" of this term, with each repeated block
+    /// quoted as "repeat n { ... }".
+    pub fn quote(&self) -> TermT {
+        quote_items(&self.items, self.ty)
+    }
+
+    /// Returns the unitary of this term.
+    /// Each column is simulated by applying the body of a repetition as many times as it is
+    /// repeated, so the memory used does not depend on the number of repetitions.
+    /// Returns an error if the unitary does not fit in the memory limit of `crate::budget`.
+    pub fn to_unitary(&self) -> Result<Mat<Complex<f64>>, TooLargeError> {
+        let qubits = self.ty.0;
+        budget::check_fits(qubits)?;
+        let size = 1 << qubits;
+        let ops = item_ops(&self.items)?;
+        Ok(build_columns(size, size, |cols| {
+            let mut u = identity_columns(size, cols);
+            apply_ops(&ops, &mut u, qubits);
+            u
+        }))
+    }
+}
+
+fn count_clauses(items: &[ItemC], expanded: bool) -> usize {
+    items
+        .iter()
+        .map(|item| match item {
+            ItemC::Clause(_) => 1,
+            ItemC::Repeat(body, count) if expanded => {
+                count_clauses(body, expanded).saturating_mul(*count)
+            }
+            ItemC::Repeat(body, _) => count_clauses(body, expanded),
+        })
+        .fold(0, usize::saturating_add)
+}
+
+fn expand_items(items: &[ItemC], clauses: &mut Vec<ClauseC>) {
+    for item in items {
+        match item {
+            ItemC::Clause(clause) => clauses.push(clause.clone()),
+            ItemC::Repeat(body, count) => {
+                for _ in 0..*count {
+                    expand_items(body, clauses);
+                }
+            }
+        }
+    }
+}
+
+fn quote_items(items: &[ItemC], ty: TermType) -> TermT {
+    let mut terms: Vec<TermT> = items
+        .iter()
+        .map(|item| match item {
+            ItemC::Clause(clause) => clause.quote(),
+            ItemC::Repeat(body, count) => TermT::Repeat {
+                inner: Box::new(quote_items(body, ty)),
+                count: *count,
+            },
+        })
+        .collect();
+    match terms.len() {
+        0 => TermT::Id(ty),
+        1 => terms.pop().unwrap(),
+        _ => TermT::Comp(terms),
+    }
+}
+
+fn item_ops(items: &[ItemC]) -> Result<Vec<ItemOp<'_>>, TooLargeError> {
+    items
+        .iter()
+        .map(|item| {
+            Ok(match item {
+                ItemC::Clause(clause) => ItemOp::Clause(clause.op()?),
+                ItemC::Repeat(body, count) => ItemOp::Repeat(item_ops(body)?, *count),
+            })
+        })
+        .collect()
+}
+
+fn apply_ops(ops: &[ItemOp], state: &mut Mat<Complex<f64>>, qubits: usize) {
+    for op in ops {
+        match op {
+            ItemOp::Clause(clause) => clause.apply(state, qubits),
+            ItemOp::Repeat(body, count) => {
+                for _ in 0..*count {
+                    apply_ops(body, state, qubits);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! Layered scheduling of circuit-normal clauses.
+//!
+//! A clause depends on an earlier clause when both act on a common wire and they do not
+//! commute. Clauses are placed in the earliest layer after all of their
+//! dependencies which has no clause acting on their wires, so any two clauses in the same
+//! layer are independent and act on disjoint wires, and the clauses of a layer can be
+//! applied in any order.
+
+use crate::circuit_syntax::{TermC, term::ClauseC};
+
+/// Dependency graph and layering of the clauses of a circuit-normal term.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Schedule {
+    /// Indices of the clauses in each layer, in increasing order.
+    pub layers: Vec<Vec<usize>>,
+    /// For each clause, the indices of the earlier clauses it depends on.
+    pub dependencies: Vec<Vec<usize>>,
+    /// Indices of the clauses along a longest chain of dependencies, in order.
+    pub critical_path: Vec<usize>,
+}
+
+impl Schedule {
+    /// Returns the number of layers.
+    pub fn depth(&self) -> usize {
+        self.layers.len()
+    }
+}
+
+/// Returns true if two clauses must be applied in the order they are given.
+fn depends(earlier: &ClauseC, later: &ClauseC) -> bool {
+    let support: Vec<_> = earlier.support().collect();
+    let overlap = later.support().any(|w| support.contains(&w));
+    overlap && !earlier.commutes_with(later)
+}
+
+impl TermC {
+    /// Build the dependency graph of the clauses of this term and group them into layers.
+    pub fn schedule(&self) -> Schedule {
+        let mut dependencies = vec![];
+        let mut level: Vec<usize> = vec![];
+        let mut layers: Vec<Vec<usize>> = vec![];
+        // Wires acted on by the clauses of each layer.
+        let mut used: Vec<Vec<bool>> = vec![];
+        // Number of clauses in a longest chain of dependencies ending at each clause.
+        let mut chain: Vec<usize> = vec![];
+        for (i, clause) in self.clauses.iter().enumerate() {
+            let deps: Vec<usize> = (0..i)
+                .filter(|&j| depends(&self.clauses[j], clause))
+                .collect();
+            let support: Vec<_> = clause.support().collect();
+            let earliest = deps.iter().map(|&j| level[j] + 1).max().unwrap_or(0);
+            let l = (earliest..)
+                .find(|&l| used.get(l).is_none_or(|u| support.iter().all(|&w| !u[w])))
+                .unwrap();
+            if l == layers.len() {
+                layers.push(vec![]);
+                used.push(vec![false; self.ty.0]);
+            }
+            layers[l].push(i);
+            for &w in &support {
+                used[l][w] = true;
+            }
+            level.push(l);
+            chain.push(deps.iter().map(|&j| chain[j] + 1).max().unwrap_or(1));
+            dependencies.push(deps);
+        }
+        let mut critical_path = vec![];
+        if let Some(last) = (0..chain.len()).max_by_key(|&i| chain[i]) {
+            let mut i = last;
+            critical_path.push(i);
+            while let Some(&j) = dependencies[i].iter().find(|&&j| chain[j] + 1 == chain[i]) {
+                critical_path.push(j);
+                i = j;
+            }
+            critical_path.reverse();
+        }
+        Schedule {
+            layers,
+            dependencies,
+            critical_path,
+        }
+    }
+
+    /// Returns the clauses of each layer of the schedule of this term, as circuit-normal
+    /// terms.
+    pub fn layers(&self) -> Vec<TermC> {
+        self.schedule()
+            .layers
+            .iter()
+            .map(|layer| TermC {
+                clauses: layer.iter().map(|&i| self.clauses[i].clone()).collect(),
+                ty: self.ty,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::check_source;
+
+    /// Schedule the circuit of a term, checking that the clauses of each layer act on
+    /// disjoint wires and come after the clauses they depend on.
+    fn schedule(src: &str) -> Schedule {
+        let (_, term) = check_source(src);
+        let circuit = term.eval_circ();
+        let schedule = circuit.schedule();
+        let mut level = vec![0; circuit.clauses.len()];
+        for (l, layer) in schedule.layers.iter().enumerate() {
+            let mut used = vec![false; circuit.ty.0];
+            for &i in layer {
+                level[i] = l;
+                for w in circuit.clauses[i].support() {
+                    assert!(!used[w], "Layer {l} acts on wire {w} twice");
+                    used[w] = true;
+                }
+            }
+        }
+        for (i, deps) in schedule.dependencies.iter().enumerate() {
+            assert!(deps.iter().all(|&j| level[j] < level[i]));
+        }
+        schedule
+    }
+
+    #[test]
+    fn commuting_clauses_on_a_shared_wire_are_in_different_layers() {
+        let schedule =
+            schedule("(if let |1> then i) x id ; if let |11> then -1 ; id x (if let |1> then i)");
+        assert_eq!(schedule.layers, [vec![0, 2], vec![1]]);
+        assert!(schedule.dependencies.iter().all(Vec::is_empty));
+        assert_eq!(schedule.critical_path.len(), 1);
+    }
+
+    #[test]
+    fn dependent_clauses_form_the_critical_path() {
+        let schedule = schedule(
+            "(if let |1> then -1) x id ; (if let |-> then -1) x id ; id x (if let |1> then i) ; \
+             if let |11> then -1",
+        );
+        assert_eq!(schedule.layers, [vec![0, 2], vec![1], vec![3]]);
+        assert_eq!(schedule.critical_path, [0, 1, 3]);
+    }
+}
@@ -1,8 +1,17 @@
 //! Circuit-normal terms.
 
+use std::f64::consts::PI;
+
+use faer::Mat;
+use num_complex::Complex;
+
 use crate::{
+    budget::{self, TooLargeError},
     circuit_syntax::pattern::PatternC,
+    normal_syntax::term::PowerN,
+    parallel::{build_columns, identity_columns},
     phase::Phase,
+    simulate::{HADAMARD, apply_controlled, apply_phase, apply_single, wire_bit},
     typed_syntax::{TermT, TermType},
 };
 
@@ -12,6 +21,9 @@ use crate::{
 /// if let q_11 x ... x q_1n then Phase(theta_1) x id(m_1);
 /// ... ;
 /// if let q_l1 x ... x q_ln then Phase(theta_l) x id(m_l)
+///
+/// except that a clause may apply an opaque power to some of its unmatched wires in place of
+/// its phase.
 #[derive(Clone, Debug, PartialEq)]
 pub struct TermC {
     pub(crate) clauses: Vec<ClauseC>,
@@ -22,9 +34,44 @@ pub struct TermC {
 pub(crate) struct ClauseC {
     pub(crate) pattern: PatternC,
     pub(crate) phase: f64,
+    /// Opaque power applied in place of the phase, if any
+    pub(crate) power: Option<PowerC>,
+}
+
+/// An opaque power applied to some of the unmatched wires of a clause.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct PowerC {
+    /// Wires the power acts on, in increasing order
+    pub(crate) wires: Vec<usize>,
+    /// The power
+    pub(crate) power: PowerN,
+}
+
+/// A clause with the unitary of its opaque power, if any, which can be shared between
+/// threads.
+pub(super) struct ClauseOp<'a> {
+    pattern: &'a PatternC,
+    phase: f64,
+    power: Option<(&'a [usize], Mat<Complex<f64>>)>,
 }
 
 impl TermC {
+    /// Returns the type of this term
+    pub fn get_type(&self) -> TermType {
+        self.ty
+    }
+
+    /// Returns the number of clauses
+    pub fn num_clauses(&self) -> usize {
+        self.clauses.len()
+    }
+
+    /// Returns the number of clauses applying an opaque power.
+    /// These clauses can not be lowered to gates or converted to ZX-diagrams.
+    pub fn opaque_powers(&self) -> usize {
+        self.clauses.iter().filter(|c| c.power.is_some()).count()
+    }
+
     /// Return a `TermT` which is the "quotation" of this circuit-normal-form term.
     /// Realises that all circuit-normal-form terms are also terms.
     pub fn quote(&self) -> TermT {
@@ -34,15 +81,53 @@ impl TermC {
             _ => TermT::Comp(self.clauses.iter().map(ClauseC::quote).collect()),
         }
     }
+
+    /// Convert a circuit-normal term of type qn <-> qn to an n x n unitary matrix,
+    /// by applying each clause in turn to the identity.
+    /// The columns are simulated in parallel.
+    /// Returns an error if the unitary does not fit in the memory limit of `crate::budget`.
+    pub fn to_unitary(&self) -> Result<Mat<Complex<f64>>, TooLargeError> {
+        let qubits = self.ty.0;
+        budget::check_fits(qubits)?;
+        let size = 1 << qubits;
+        let ops = self
+            .clauses
+            .iter()
+            .map(ClauseC::op)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(build_columns(size, size, |cols| {
+            let mut u = identity_columns(size, cols);
+            for op in &ops {
+                op.apply(&mut u, qubits);
+            }
+            u
+        }))
+    }
+
+    /// Apply this circuit-normal term to each column of `state`, a matrix with 2^n rows,
+    /// for a term on n qubits.
+    /// Returns an error if the unitary of an opaque power does not fit in the memory limit.
+    pub(crate) fn apply(&self, state: &mut Mat<Complex<f64>>) -> Result<(), TooLargeError> {
+        for clause in &self.clauses {
+            clause.op()?.apply(state, self.ty.0);
+        }
+        Ok(())
+    }
 }
 
 impl ClauseC {
     pub(crate) fn quote(&self) -> TermT {
-        let id_qubits = self.pattern.id_qubits();
-        let mut inner = TermT::Phase(Phase::Angle(self.phase));
-        if id_qubits != 0 {
-            inner = TermT::Tensor(vec![inner, TermT::Id(TermType(id_qubits))])
-        }
+        let inner = match &self.power {
+            Some(power) => power.quote(&self.pattern),
+            None => {
+                let id_qubits = self.pattern.id_qubits();
+                let mut inner = TermT::Phase(Phase::Angle(self.phase));
+                if id_qubits != 0 {
+                    inner = TermT::Tensor(vec![inner, TermT::Id(TermType(id_qubits))])
+                }
+                inner
+            }
+        };
 
         TermT::IfLet {
             pattern: self.pattern.quote(),
@@ -50,10 +135,107 @@ impl ClauseC {
         }
     }
 
+    /// Returns the wires matched by the pattern of this clause, or acted on by its power.
+    pub(crate) fn support(&self) -> impl Iterator<Item = usize> + '_ {
+        let matched = self.pattern.parts.iter().enumerate();
+        matched
+            .filter_map(|(wire, part)| part.map(|_| wire))
+            .chain(self.power.iter().flat_map(|p| p.wires.iter().copied()))
+    }
+
+    /// Returns true if this clause commutes with `other`.
+    /// Clauses applying phases commute when their projectors do, and clauses applying powers
+    /// commute with clauses whose support is disjoint from theirs.
+    pub(crate) fn commutes_with(&self, other: &ClauseC) -> bool {
+        if self.power.is_none() && other.power.is_none() {
+            self.pattern.commutes_with(&other.pattern)
+        } else {
+            let support: Vec<_> = self.support().collect();
+            other.support().all(|w| !support.contains(&w))
+        }
+    }
+
+    pub(super) fn op(&self) -> Result<ClauseOp<'_>, TooLargeError> {
+        Ok(ClauseOp {
+            pattern: &self.pattern,
+            phase: self.phase,
+            power: match &self.power {
+                Some(p) => Some((p.wires.as_slice(), p.power.unitary()?)),
+                None => None,
+            },
+        })
+    }
+
     pub(crate) fn invert(&self) -> ClauseC {
         ClauseC {
             pattern: self.pattern.clone(),
             phase: -self.phase,
+            power: self.power.as_ref().map(|p| PowerC {
+                wires: p.wires.clone(),
+                power: p.power.inverse(),
+            }),
+        }
+    }
+}
+
+impl PowerC {
+    /// Quote this power as the body of an "if let" with the given pattern, acting on the
+    /// unmatched wires of the pattern.
+    fn quote(&self, pattern: &PatternC) -> TermT {
+        let mut parts = vec![];
+        let mut ids = 0;
+        let unmatched = pattern
+            .parts
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.is_none());
+        for (wire, _) in unmatched {
+            if !self.wires.contains(&wire) {
+                ids += 1;
+            } else if wire == self.wires[0] {
+                if ids != 0 {
+                    parts.push(TermT::Id(TermType(ids)));
+                    ids = 0;
+                }
+                parts.push(self.power.quote());
+            }
+        }
+        if ids != 0 {
+            parts.push(TermT::Id(TermType(ids)));
+        }
+        if parts.len() == 1 {
+            parts.pop().unwrap()
+        } else {
+            TermT::Tensor(parts)
+        }
+    }
+}
+
+impl ClauseOp<'_> {
+    /// Apply this clause to each column of `state`, a matrix with 2^qubits rows.
+    pub(super) fn apply(&self, state: &mut Mat<Complex<f64>>, qubits: usize) {
+        let mut mask = 0;
+        let mut value = 0;
+        for (wire, part) in self.pattern.parts.iter().enumerate() {
+            if let Some(s) = part {
+                let bit = wire_bit(qubits, wire);
+                mask |= bit;
+                if s.bit() {
+                    value |= bit;
+                }
+                if s.is_hadamard_basis() {
+                    apply_single(state, qubits, wire, &HADAMARD);
+                }
+            }
+        }
+        match &self.power {
+            Some((wires, u)) => apply_controlled(state, qubits, mask, value, wires, u),
+            None => apply_phase(state, mask, value, Complex::cis(self.phase * PI)),
+        }
+        for (wire, part) in self.pattern.parts.iter().enumerate() {
+            if part.is_some_and(|s| s.is_hadamard_basis()) {
+                apply_single(state, qubits, wire, &HADAMARD);
+            }
         }
     }
 }
@@ -1,22 +1,27 @@
 //! A syntax of commands and their parsing.
 //!
 //! A `Command` is the top level structure accepted by the executable
-//! They allow a sequence of gates to be defined before taking a term to evaluate.
+//! They allow a sequence of gates to be defined, and rewrite rules and proofs to be declared,
+//! before taking a term to evaluate.
 
-use std::ops::Range;
+use std::{ops::Range, rc::Rc};
 
+use pretty::RcDoc;
 use winnow::{
     LocatingSlice, ModalResult, Parser,
     ascii::{multispace0, multispace1},
-    combinator::{cut_err, preceded, repeat, seq, terminated},
+    combinator::{alt, cut_err, preceded, repeat, seq, terminated},
     error::{StrContext, StrContextValue},
 };
 
 use crate::{
+    budget,
+    normal_syntax::{TermN, rules::Rule},
+    proof::{Certificate, ProofError, ProofR},
     raw_syntax::TermR,
-    text::{HasParser, Name, Span, comment_parser},
-    typecheck::{Env, TypeCheckError},
-    typed_syntax::TermT,
+    text::{HasParser, Name, Span, ToDoc, comment_parser},
+    typecheck::{Env, GateEntry, TypeCheckError},
+    typed_syntax::{TermT, TermType},
 };
 
 /// The Command structure: a runnable program.
@@ -24,21 +29,147 @@ use crate::{
 pub struct Command<S> {
     /// List of gates to define, with the name to bind them to.
     pub gates: Vec<(Name, TermR<S>)>,
+    /// List of rewrite rules to declare, as pairs of left- and right-hand sides.
+    pub rules: Vec<(TermR<S>, TermR<S>)>,
+    /// List of equational proofs to check.
+    pub proofs: Vec<ProofR<S>>,
     /// Final term to evaluate.
     pub term: TermR<S>,
 }
 
 impl<S: Span> Command<S> {
-    /// Typecheck a command, building an `Env` with gate definitions.
+    /// Typecheck a command, building an `Env` with gate definitions and rewrite rules.
+    /// Each rule is checked to be sound by comparing the unitaries of its sides.
     pub fn check(&self) -> Result<(Env, TermT), TypeCheckError<S>> {
         let mut env = Env::default();
         for (name, tm) in &self.gates {
-            let t = tm.check(&env, None)?;
-            env.0.insert(name.clone(), t);
+            let entry = GateEntry {
+                def: Rc::new(tm.check(&env, None)?),
+                composition: tm.composition(&env),
+            };
+            if env.gates.insert(name.clone(), entry).is_none() {
+                env.names.push(name.clone());
+            }
+        }
+        for (lhs, rhs) in &self.rules {
+            let rule = check_rule(&env, lhs, rhs)?;
+            if rule.lhs != rule.rhs {
+                env.rules.push(rule);
+            }
         }
         let tm = self.term.check(&env, None)?;
         Ok((env, tm))
     }
+
+    /// Check that the unitary of the final term, with type `ty`, fits in the memory limit
+    /// given by `budget`.
+    pub fn check_unitary_size(&self, ty: TermType) -> Result<(), TypeCheckError<S>> {
+        check_size(&self.term, ty)
+    }
+
+    /// Check the proofs of a command in an environment built by `check`, returning a
+    /// certificate for each proof.
+    pub fn check_proofs(&self, env: &Env) -> Result<Vec<Certificate>, ProofError<S>> {
+        self.proofs.iter().map(|proof| proof.check(env)).collect()
+    }
+}
+
+/// Maximum deviation between the unitaries of the sides of a sound rule.
+const RULE_TOLERANCE: f64 = 1e-9;
+
+fn check_size<S: Span>(tm: &TermR<S>, ty: TermType) -> Result<(), TypeCheckError<S>> {
+    if budget::fits(ty.0) {
+        Ok(())
+    } else {
+        Err(TypeCheckError::TooLarge {
+            tm: tm.clone(),
+            ty,
+            required: budget::format_bytes(budget::unitary_bytes(ty.0)),
+            limit: budget::format_bytes(Some(budget::limit())),
+        })
+    }
+}
+
+fn check_rule<S: Span>(
+    env: &Env,
+    lhs: &TermR<S>,
+    rhs: &TermR<S>,
+) -> Result<Rule, TypeCheckError<S>> {
+    let l = lhs.check(env, None)?;
+    let r = rhs.check(env, None)?;
+    let (lty, rty) = (l.get_type(), r.get_type());
+    if lty != rty {
+        return Err(TypeCheckError::RuleTypeMismatch {
+            lhs: lhs.clone(),
+            lty,
+            rhs: rhs.clone(),
+            rty,
+        });
+    }
+    let mut l: TermN = l.eval();
+    l.squash();
+    let mut r: TermN = r.eval();
+    r.squash();
+    if l.is_identity() {
+        return Err(TypeCheckError::IdentityRule { lhs: lhs.clone() });
+    }
+    check_size(lhs, lty)?;
+    let unitary = |t: &TermN| {
+        t.to_unitary().map_err(|e| TypeCheckError::TooLarge {
+            tm: lhs.clone(),
+            ty: lty,
+            required: e.required,
+            limit: e.limit,
+        })
+    };
+    let deviation = (unitary(&l)? - unitary(&r)?).norm_max();
+    if deviation > RULE_TOLERANCE {
+        return Err(TypeCheckError::UnsoundRule {
+            lhs: lhs.clone(),
+            rhs: rhs.clone(),
+            deviation,
+        });
+    }
+    Ok(Rule { lhs: l, rhs: r })
+}
+
+impl<S> ToDoc for Command<S> {
+    fn to_doc(&self) -> RcDoc<'_> {
+        RcDoc::concat(self.gates.iter().map(|(name, tm)| {
+            RcDoc::text("gate ")
+                .append(name.to_doc())
+                .append(" =")
+                .append(RcDoc::line().append(tm.to_doc()).nest(2))
+                .append(",")
+                .group()
+                .append(RcDoc::hardline())
+        }))
+        .append(RcDoc::concat(self.rules.iter().map(|(lhs, rhs)| {
+            RcDoc::text("rule")
+                .append(RcDoc::line().append(lhs.to_doc()).nest(2))
+                .append(RcDoc::line())
+                .append("=>")
+                .append(RcDoc::line().append(rhs.to_doc()).nest(2))
+                .append(",")
+                .group()
+                .append(RcDoc::hardline())
+        })))
+        .append(RcDoc::concat(self.proofs.iter().map(|proof| {
+            RcDoc::text("proof")
+                .append(RcDoc::line().append(proof.to_doc()).nest(2))
+                .append(",")
+                .group()
+                .append(RcDoc::hardline())
+        })))
+        .append(self.term.to_doc())
+    }
+}
+
+/// A top level declaration, before the final term.
+enum Declaration<S> {
+    Gate(Name, TermR<S>),
+    Rule(TermR<S>, TermR<S>),
+    Proof(ProofR<S>),
 }
 
 impl HasParser for Command<Range<usize>> {
@@ -51,13 +182,63 @@ impl HasParser for Command<Range<usize>> {
 		     TermR::parser,
 		     _: (multispace0, ","))).context(StrContext::Label("gate definition"))
 	);
+        let rule = preceded(
+            "rule",
+            cut_err(seq!(_: multispace1,
+                         TermR::parser,
+                         _: (multispace0, "=>", multispace0).context(StrContext::Expected(StrContextValue::StringLiteral("=>"))),
+                         TermR::parser,
+                         _: (multispace0, ",")))
+            .context(StrContext::Label("rule declaration")),
+        );
+        let proof = preceded(
+            "proof",
+            cut_err(seq!(_: multispace1, ProofR::parser, _: (multispace0, ",")))
+                .context(StrContext::Label("proof")),
+        );
 
         comment_parser.parse_next(input)?;
-        let gates = repeat(0.., terminated(gate, comment_parser)).parse_next(input)?;
+        let declarations: Vec<_> = repeat(
+            0..,
+            terminated(
+                alt((
+                    gate.map(|(name, tm)| Declaration::Gate(name, tm)),
+                    rule.map(|(lhs, rhs)| Declaration::Rule(lhs, rhs)),
+                    proof.map(|(proof,)| Declaration::Proof(proof)),
+                )),
+                comment_parser,
+            ),
+        )
+        .parse_next(input)?;
+        let mut gates = vec![];
+        let mut rules = vec![];
+        let mut proofs = vec![];
+        for declaration in declarations {
+            match declaration {
+                Declaration::Gate(name, tm) => gates.push((name, tm)),
+                Declaration::Rule(lhs, rhs) => rules.push((lhs, rhs)),
+                Declaration::Proof(proof) => proofs.push(proof),
+            }
+        }
         let term = TermR::parser
             .context(StrContext::Label("Term"))
             .parse_next(input)?;
         comment_parser.parse_next(input)?;
-        Ok(Command { gates, term })
+        Ok(Command {
+            gates,
+            rules,
+            proofs,
+            term,
+        })
     }
 }
+
+/// Parse and typecheck a program, panicking if either fails. Used by tests.
+#[cfg(test)]
+pub(crate) fn check_source(src: &str) -> (Env, TermT) {
+    Command::parser
+        .parse(LocatingSlice::new(src))
+        .expect("Program should parse")
+        .check()
+        .expect("Program should typecheck")
+}
@@ -0,0 +1,266 @@
+//! Circuit diagrams of circuit-normal terms.
+//!
+//! A `Diagram` places the clauses of a term into columns. Each clause is drawn as a set of
+//! controls, one for each wire its pattern matches on, joined by a vertical line and labelled
+//! with its phase below the lowest control. Columns are built from the layers of the
+//! schedule of the term, so that clauses in the same column are independent, and clauses
+//! whose controls would overlap are split into separate columns.
+//!
+//! Diagrams can also be built from typed terms, drawing named gates as labelled boxes
+//! rather than expanding their definitions.
+
+pub mod quantikz;
+pub mod svg;
+pub mod text;
+
+use crate::{
+    circuit_syntax::{TermC, pattern::PatternC, term::ClauseC},
+    ket::KetState,
+    typed_syntax::TermT,
+};
+
+/// An operation placed in a diagram.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Element {
+    /// A clause of a circuit-normal term.
+    Clause {
+        /// Wires matched by the pattern of the clause, with the state they are matched
+        /// against.
+        controls: Vec<(usize, KetState)>,
+        /// Phase of the clause, divided by pi.
+        phase: f64,
+    },
+    /// A named gate drawn as a box.
+    Gate {
+        /// Name of the gate.
+        name: String,
+        /// First wire the gate acts on.
+        top: usize,
+        /// Last wire the gate acts on.
+        bottom: usize,
+        /// Power the gate is raised to, from inverses, square roots, and powers.
+        power: f64,
+    },
+}
+
+impl Element {
+    fn from_clause(clause: &ClauseC) -> Self {
+        if let Some(power) = &clause.power {
+            let support: Vec<usize> = clause.support().collect();
+            return Element::Gate {
+                name: base_name(power.power.base()),
+                top: support.iter().copied().min().unwrap_or(0),
+                bottom: support.iter().copied().max().unwrap_or(0),
+                power: power.power.exponent,
+            };
+        }
+        Element::Clause {
+            controls: clause
+                .pattern
+                .parts
+                .iter()
+                .enumerate()
+                .filter_map(|(wire, part)| Some((wire, (*part)?)))
+                .collect(),
+            phase: clause.phase,
+        }
+    }
+
+    /// Returns the first and last wires covered by this element.
+    /// Global phases, which match on no wires, cover every wire.
+    pub fn span(&self, wires: usize) -> (usize, usize) {
+        match self {
+            Element::Clause { controls, .. } => match (controls.first(), controls.last()) {
+                (Some((first, _)), Some((last, _))) => (*first, *last),
+                _ => (0, wires.saturating_sub(1)),
+            },
+            Element::Gate { top, bottom, .. } => (*top, *bottom),
+        }
+    }
+}
+
+/// A circuit diagram, as a sequence of columns of elements with disjoint spans.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagram {
+    /// Number of wires.
+    pub wires: usize,
+    /// Elements in each column, from left to right.
+    pub columns: Vec<Vec<Element>>,
+}
+
+impl Diagram {
+    /// Place `element` in the first column from `start` whose elements have spans disjoint
+    /// from it, adding a new column if there is none.
+    fn place(&mut self, start: usize, element: Element) {
+        let (top, bottom) = element.span(self.wires);
+        let free = self.columns[start..].iter().position(|column| {
+            column.iter().all(|e| {
+                let (t, b) = e.span(self.wires);
+                bottom < t || b < top
+            })
+        });
+        match free {
+            Some(c) => self.columns[start + c].push(element),
+            None => self.columns.push(vec![element]),
+        }
+    }
+
+    /// Lay out a sequence of elements, placing each one in the column after the last column
+    /// with an element overlapping it.
+    fn from_sequence(wires: usize, elements: Vec<Element>) -> Self {
+        let mut diagram = Diagram {
+            wires,
+            columns: vec![],
+        };
+        for element in elements {
+            let (top, bottom) = element.span(wires);
+            let start = diagram
+                .columns
+                .iter()
+                .rposition(|column| {
+                    column.iter().any(|e| {
+                        let (t, b) = e.span(wires);
+                        t <= bottom && top <= b
+                    })
+                })
+                .map_or(0, |c| c + 1);
+            diagram.place(start, element);
+        }
+        diagram
+    }
+}
+
+impl TermC {
+    /// Lay out this term as a circuit diagram.
+    pub fn to_diagram(&self) -> Diagram {
+        let mut diagram = Diagram {
+            wires: self.ty.0,
+            columns: vec![],
+        };
+        for layer in self.schedule().layers {
+            let start = diagram.columns.len();
+            for i in layer {
+                diagram.place(start, Element::from_clause(&self.clauses[i]));
+            }
+        }
+        diagram
+    }
+}
+
+impl TermT {
+    /// Lay out this term as a circuit diagram, drawing each named gate acting on at least
+    /// one wire as a labelled box. The rest of the term is drawn as its circuit-normal form.
+    pub fn to_diagram(&self) -> Diagram {
+        let wires = self.get_type().0;
+        let inj: Vec<usize> = (0..wires).collect();
+        let mut elements = vec![];
+        self.collect_elements(&inj, 1.0, &mut elements);
+        Diagram::from_sequence(wires, elements)
+    }
+
+    fn collect_elements(&self, inj: &[usize], power: f64, elements: &mut Vec<Element>) {
+        match self {
+            TermT::Comp(terms) => {
+                if power < 0.0 {
+                    for t in terms.iter().rev() {
+                        t.collect_elements(inj, power, elements);
+                    }
+                } else {
+                    for t in terms {
+                        t.collect_elements(inj, power, elements);
+                    }
+                }
+            }
+            TermT::Tensor(terms) => {
+                let mut start = 0;
+                for t in terms {
+                    let end = start + t.get_type().0;
+                    t.collect_elements(&inj[start..end], power, elements);
+                    start = end;
+                }
+            }
+            TermT::Gate { name, .. } if !inj.is_empty() => elements.push(Element::Gate {
+                name: name.to_string(),
+                top: inj[0],
+                bottom: inj[inj.len() - 1],
+                power,
+            }),
+            TermT::Inverse(inner) => inner.collect_elements(inj, -power, elements),
+            TermT::Sqrt(inner) => inner.collect_elements(inj, power / 2.0, elements),
+            TermT::Power {
+                inner,
+                exponent,
+                rootable: true,
+            } => inner.collect_elements(inj, power * exponent.eval(), elements),
+            TermT::Repeat { inner, count } => {
+                for _ in 0..*count {
+                    inner.collect_elements(inj, power, elements);
+                }
+            }
+            TermT::Power {
+                inner, exponent, ..
+            } if !inj.is_empty() => elements.push(Element::Gate {
+                name: base_name(inner),
+                top: inj[0],
+                bottom: inj[inj.len() - 1],
+                power: power * exponent.eval(),
+            }),
+            _ => {
+                let wires = inj.iter().max().map_or(0, |w| w + 1);
+                let mut clauses = vec![];
+                self.eval_circ_clause(&PatternC::id(wires), inj, power, &mut clauses);
+                elements.extend(clauses.iter().map(Element::from_clause));
+            }
+        }
+    }
+}
+
+/// Label of the base of a numerical power: its name if it is a named gate, and "U"
+/// otherwise.
+fn base_name(base: &TermT) -> String {
+    match base {
+        TermT::Gate { name, .. } => name.to_string(),
+        _ => "U".to_owned(),
+    }
+}
+
+/// Format an angle, given divided by pi, as a multiple of pi, using a fraction with a small
+/// power of two denominator where possible.
+pub fn format_angle(phase: f64, pi: &str) -> String {
+    let phase = phase - 2.0 * (phase / 2.0).round();
+    let phase = if phase <= -1.0 { phase + 2.0 } else { phase };
+    for denominator in [1, 2, 4, 8, 16, 32, 64] {
+        let numerator = phase * denominator as f64;
+        if (numerator - numerator.round()).abs() < 1e-9 {
+            let numerator = numerator.round() as i64;
+            let sign = if numerator < 0 { "-" } else { "" };
+            let numerator = numerator.abs();
+            return match (numerator, denominator) {
+                (0, _) => "0".to_owned(),
+                (1, 1) => format!("{sign}{pi}"),
+                (n, 1) => format!("{sign}{n}{pi}"),
+                (1, d) => format!("{sign}{pi}/{d}"),
+                (n, d) => format!("{sign}{n}{pi}/{d}"),
+            };
+        }
+    }
+    format!("{}{pi}", format_number(phase))
+}
+
+/// Format a number with at most four decimal places, dropping trailing zeros.
+fn format_number(x: f64) -> String {
+    let value = format!("{x:.4}");
+    value.trim_end_matches('0').trim_end_matches('.').to_owned()
+}
+
+/// Label of a named gate raised to a power, using `dagger` for its inverse and `exponent`
+/// to write any other power.
+fn gate_label(name: &str, power: f64, dagger: &str, exponent: impl Fn(&str) -> String) -> String {
+    if (power - 1.0).abs() < 1e-12 {
+        name.to_owned()
+    } else if (power + 1.0).abs() < 1e-12 {
+        format!("{name}{dagger}")
+    } else {
+        format!("{name}{}", exponent(&format_number(power)))
+    }
+}
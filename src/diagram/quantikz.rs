@@ -0,0 +1,96 @@
+//! Rendering of circuit diagrams as LaTeX using the quantikz package.
+//!
+//! Controls matching |1> and |0> are drawn with `\ctrl` and `\octrl`, and the phase of a
+//! clause labels its lowest control using `\phase` or `\ophase`. Controls matching |+> and
+//! |-> are drawn as boxes joined to the next control with `\vqw`, and named gates as
+//! multi-wire `\gate` boxes.
+
+use crate::{
+    diagram::{Diagram, Element, format_angle, gate_label},
+    ket::KetState,
+};
+
+impl Diagram {
+    /// Render the diagram as a quantikz environment, for inclusion in a LaTeX document
+    /// using the quantikz package.
+    pub fn render_quantikz(&self) -> String {
+        let mut cells = vec![vec![]; self.wires];
+        for column in &self.columns {
+            let mut column_cells = vec![r"\qw".to_owned(); self.wires];
+            for e in column {
+                match e {
+                    Element::Clause { controls, phase } => {
+                        let angle = format_angle(*phase, r"\pi");
+                        if controls.is_empty()
+                            && let Some(cell) = column_cells.first_mut()
+                        {
+                            *cell = format!(r"\gate[style={{draw=none}}]{{e^{{i{angle}}}}}");
+                        }
+                        for (k, (wire, state)) in controls.iter().enumerate() {
+                            let next = controls.get(k + 1).map(|(w, _)| w - wire);
+                            column_cells[*wire] = match (state, next) {
+                                (KetState::One, Some(d)) => format!(r"\ctrl{{{d}}}"),
+                                (KetState::Zero, Some(d)) => format!(r"\octrl{{{d}}}"),
+                                (KetState::One, None) => format!(r"\phase{{{angle}}}"),
+                                (KetState::Zero, None) => format!(r"\ophase{{{angle}}}"),
+                                (KetState::Plus | KetState::Minus, Some(d)) => {
+                                    format!(r"\gate{{{}}}\vqw{{{d}}}", ket(*state))
+                                }
+                                (KetState::Plus | KetState::Minus, None) => {
+                                    format!(r"\gate{{{},\ {angle}}}", ket(*state))
+                                }
+                            };
+                        }
+                    }
+                    Element::Gate {
+                        name,
+                        top,
+                        bottom,
+                        power,
+                    } => {
+                        let label = gate_label(
+                            &format!(r"\mathrm{{{}}}", name.replace('_', r"\_")),
+                            *power,
+                            r"^\dagger",
+                            |p| format!("^{{{p}}}"),
+                        );
+                        column_cells[*top] = if top == bottom {
+                            format!(r"\gate{{{label}}}")
+                        } else {
+                            format!(r"\gate[{}]{{{label}}}", bottom - top + 1)
+                        };
+                    }
+                }
+            }
+            for (row, cell) in cells.iter_mut().zip(column_cells) {
+                row.push(cell);
+            }
+        }
+        let rows: Vec<String> = cells
+            .iter()
+            .enumerate()
+            .map(|(wire, row)| {
+                let mut line = format!(r"\lstick{{$q_{{{wire}}}$}}");
+                for cell in row {
+                    line.push_str(" & ");
+                    line.push_str(cell);
+                }
+                line.push_str(r" & \qw");
+                line
+            })
+            .collect();
+        format!(
+            "\\begin{{quantikz}}\n{}\n\\end{{quantikz}}\n",
+            rows.join(" \\\\\n")
+        )
+    }
+}
+
+fn ket(state: KetState) -> &'static str {
+    match state {
+        KetState::Zero => r"\ket{0}",
+        KetState::One => r"\ket{1}",
+        KetState::Plus => r"\ket{+}",
+        KetState::Minus => r"\ket{-}",
+    }
+}
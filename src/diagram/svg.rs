@@ -0,0 +1,155 @@
+//! Rendering of circuit diagrams as standalone SVG images.
+//!
+//! Controls matching |1> and |0> are drawn as closed and open dots, and controls matching
+//! |-> and |+> as closed and open dots in a box, for the Hadamard gates changing basis.
+//! Phases are written below the lowest control of their clause, and named gates are drawn
+//! as labelled boxes.
+
+use std::fmt::Write;
+
+use crate::{
+    diagram::{Diagram, Element, format_angle, gate_label},
+    ket::KetState,
+};
+
+/// Vertical distance between wires.
+const WIRE_SPACING: f64 = 40.0;
+/// Space above the first wire and below the last.
+const PADDING: f64 = 30.0;
+/// Width of the wire labels.
+const MARGIN: f64 = 40.0;
+/// Approximate width of a character of the monospace font used for labels.
+const CHAR_WIDTH: f64 = 7.2;
+
+/// Escape text for use in XML.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn text_width(text: &str) -> f64 {
+    text.chars().count() as f64 * CHAR_WIDTH
+}
+
+fn label(element: &Element) -> String {
+    match element {
+        Element::Clause { phase, .. } => format_angle(*phase, "π"),
+        Element::Gate { name, power, .. } => gate_label(&escape(name), *power, "†", |p| {
+            format!("<tspan baseline-shift=\"super\" font-size=\"9\">{p}</tspan>")
+        }),
+    }
+}
+
+impl Diagram {
+    /// Render the diagram as a standalone SVG image.
+    pub fn render_svg(&self) -> String {
+        let y = |wire: usize| PADDING + wire as f64 * WIRE_SPACING;
+        let widths: Vec<f64> = self
+            .columns
+            .iter()
+            .map(|column| {
+                column
+                    .iter()
+                    .map(|e| match e {
+                        Element::Clause { phase, .. } => {
+                            text_width(&format_angle(*phase, "π")) + 12.0
+                        }
+                        Element::Gate { name, power, .. } => {
+                            text_width(&gate_label(name, *power, "†", |p| format!("^{p}"))) + 24.0
+                        }
+                    })
+                    .fold(30.0, f64::max)
+            })
+            .collect();
+        let width = MARGIN + widths.iter().sum::<f64>() + 20.0;
+        let height = 2.0 * PADDING + self.wires.saturating_sub(1) as f64 * WIRE_SPACING;
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+             viewBox=\"0 0 {width} {height}\" font-family=\"monospace\" font-size=\"12\">"
+        );
+        let _ = writeln!(
+            out,
+            "<rect width=\"{width}\" height=\"{height}\" fill=\"white\"/>"
+        );
+        for wire in 0..self.wires {
+            let _ = writeln!(
+                out,
+                "<text x=\"4\" y=\"{}\">q{wire}</text>\n\
+                 <line x1=\"{}\" y1=\"{y}\" x2=\"{}\" y2=\"{y}\" stroke=\"black\"/>",
+                y(wire) + 4.0,
+                MARGIN - 10.0,
+                width - 10.0,
+                y = y(wire),
+            );
+        }
+        let mut x = MARGIN;
+        for (column, w) in self.columns.iter().zip(&widths) {
+            let cx = x + w / 2.0;
+            for e in column {
+                let (top, bottom) = e.span(self.wires);
+                match e {
+                    Element::Clause { controls, .. } => {
+                        if top < bottom {
+                            let _ = writeln!(
+                                out,
+                                "<line x1=\"{cx}\" y1=\"{}\" x2=\"{cx}\" y2=\"{}\" stroke=\"black\"/>",
+                                y(top),
+                                y(bottom)
+                            );
+                        }
+                        for (wire, state) in controls {
+                            let cy = y(*wire);
+                            if state.is_hadamard_basis() {
+                                let _ = writeln!(
+                                    out,
+                                    "<rect x=\"{}\" y=\"{}\" width=\"14\" height=\"14\" \
+                                     fill=\"white\" stroke=\"black\"/>",
+                                    cx - 7.0,
+                                    cy - 7.0
+                                );
+                            }
+                            let (r, fill) = match state {
+                                KetState::One => (5.0, "black"),
+                                KetState::Zero => (5.0, "white"),
+                                KetState::Minus => (4.0, "black"),
+                                KetState::Plus => (4.0, "white"),
+                            };
+                            let _ = writeln!(
+                                out,
+                                "<circle cx=\"{cx}\" cy=\"{cy}\" r=\"{r}\" fill=\"{fill}\" \
+                                 stroke=\"black\"/>"
+                            );
+                        }
+                        let _ = writeln!(
+                            out,
+                            "<text x=\"{cx}\" y=\"{}\" text-anchor=\"middle\" font-size=\"11\">{}</text>",
+                            y(bottom) + 20.0,
+                            escape(&label(e))
+                        );
+                    }
+                    Element::Gate { .. } => {
+                        let top_y = y(top) - 14.0;
+                        let box_height = y(bottom) - y(top) + 28.0;
+                        let _ = writeln!(
+                            out,
+                            "<rect x=\"{}\" y=\"{top_y}\" width=\"{}\" height=\"{box_height}\" \
+                             fill=\"white\" stroke=\"black\"/>\n\
+                             <text x=\"{cx}\" y=\"{}\" text-anchor=\"middle\">{}</text>",
+                            x + 6.0,
+                            w - 12.0,
+                            top_y + box_height / 2.0 + 4.0,
+                            label(e)
+                        );
+                    }
+                }
+            }
+            x += w;
+        }
+        out.push_str("</svg>\n");
+        out
+    }
+}
@@ -0,0 +1,210 @@
+//! Rendering of circuit diagrams as text.
+//!
+//! Each wire is drawn as a horizontal line, with a row below it for vertical lines joining
+//! the controls of a clause and for phase labels. Controls matching |1> and |0> are drawn
+//! as closed and open dots, and controls matching |-> and |+> as closed and open dots
+//! preceded by an H, as they match |1> and |0> after a Hadamard gate. Named gates are
+//! drawn as boxes across the wires they act on.
+
+use crate::{
+    diagram::{Diagram, Element, format_angle, gate_label},
+    ket::KetState,
+};
+
+/// Set of characters used to draw a diagram.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Charset {
+    /// Box drawing characters and dots.
+    Unicode,
+    /// Characters which can be displayed on any terminal.
+    Ascii,
+}
+
+impl Charset {
+    fn wire(self) -> char {
+        match self {
+            Charset::Unicode => '─',
+            Charset::Ascii => '-',
+        }
+    }
+
+    fn vertical(self) -> char {
+        match self {
+            Charset::Unicode => '│',
+            Charset::Ascii => '|',
+        }
+    }
+
+    fn box_sides(self) -> (char, char) {
+        match self {
+            Charset::Unicode => ('┤', '├'),
+            Charset::Ascii => ('|', '|'),
+        }
+    }
+
+    fn dagger(self) -> &'static str {
+        match self {
+            Charset::Unicode => "†",
+            Charset::Ascii => "^-1",
+        }
+    }
+
+    fn crossing(self) -> char {
+        match self {
+            Charset::Unicode => '┼',
+            Charset::Ascii => '+',
+        }
+    }
+
+    fn pi(self) -> &'static str {
+        match self {
+            Charset::Unicode => "π",
+            Charset::Ascii => "pi",
+        }
+    }
+
+    fn control(self, state: KetState) -> &'static str {
+        match (self, state) {
+            (Charset::Unicode, KetState::One) => "●",
+            (Charset::Unicode, KetState::Zero) => "○",
+            (Charset::Unicode, KetState::Minus) => "H●",
+            (Charset::Unicode, KetState::Plus) => "H○",
+            (Charset::Ascii, KetState::One) => "@",
+            (Charset::Ascii, KetState::Zero) => "o",
+            (Charset::Ascii, KetState::Minus) => "H@",
+            (Charset::Ascii, KetState::Plus) => "Ho",
+        }
+    }
+}
+
+/// Write `text` centred in a cell of the given width, padded with `fill`.
+fn centre(line: &mut String, text: &str, width: usize, fill: char) {
+    let len = text.chars().count();
+    let left = (width - len) / 2;
+    line.extend(std::iter::repeat_n(fill, left));
+    line.push_str(text);
+    line.extend(std::iter::repeat_n(fill, width - len - left));
+}
+
+impl Diagram {
+    /// Render the diagram as text, wrapping it into blocks no wider than `width`
+    /// characters where possible.
+    pub fn render_text(&self, charset: Charset, width: usize) -> String {
+        let names: Vec<String> = (0..self.wires).map(|w| format!("q{w}: ")).collect();
+        let margin = names.iter().map(String::len).max().unwrap_or(0);
+        let cells: Vec<Vec<String>> = self
+            .columns
+            .iter()
+            .map(|column| column.iter().map(|e| label(e, charset)).collect())
+            .collect();
+        let widths: Vec<usize> = self
+            .columns
+            .iter()
+            .zip(&cells)
+            .map(|(column, labels)| {
+                let controls = column
+                    .iter()
+                    .filter_map(|e| match e {
+                        Element::Clause { controls, .. } => Some(controls),
+                        Element::Gate { .. } => None,
+                    })
+                    .flatten()
+                    .map(|(_, s)| charset.control(*s).chars().count());
+                let labels = column.iter().zip(labels).map(|(e, l)| match e {
+                    Element::Clause { .. } => l.chars().count(),
+                    Element::Gate { .. } => l.chars().count() + 2,
+                });
+                controls.chain(labels).max().unwrap_or(1) + 2
+            })
+            .collect();
+
+        // Split the columns into blocks which fit in the given width.
+        let mut blocks: Vec<std::ops::Range<usize>> = vec![];
+        let mut start = 0;
+        let mut used = margin;
+        for (i, w) in widths.iter().enumerate() {
+            if i > start && used + w > width {
+                blocks.push(start..i);
+                start = i;
+                used = margin;
+            }
+            used += w;
+        }
+        blocks.push(start..widths.len());
+
+        let mut out = vec![];
+        for block in blocks {
+            let mut lines: Vec<String> = (0..2 * self.wires)
+                .map(|row| {
+                    if row % 2 == 0 {
+                        format!("{:>margin$}", names[row / 2])
+                    } else {
+                        " ".repeat(margin)
+                    }
+                })
+                .collect();
+            for c in block {
+                for (row, line) in lines.iter_mut().enumerate() {
+                    let wire = row / 2;
+                    let on_wire = row % 2 == 0;
+                    let mut text = String::new();
+                    for (e, label) in self.columns[c].iter().zip(&cells[c]) {
+                        let (top, bottom) = e.span(self.wires);
+                        match e {
+                            Element::Clause { controls, .. } => {
+                                let joined = !controls.is_empty() && top <= wire && wire < bottom;
+                                if on_wire {
+                                    if let Some((_, s)) = controls.iter().find(|(w, _)| *w == wire)
+                                    {
+                                        text = charset.control(*s).to_owned();
+                                    } else if joined && top < wire {
+                                        text = charset.crossing().to_string();
+                                    }
+                                } else if wire == bottom {
+                                    text.clone_from(label);
+                                } else if joined {
+                                    text = charset.vertical().to_string();
+                                }
+                            }
+                            Element::Gate { .. } if top <= wire && wire <= bottom => {
+                                let inner = if on_wire && wire == top {
+                                    label.clone()
+                                } else {
+                                    " ".repeat(label.chars().count())
+                                };
+                                if on_wire {
+                                    let (left, right) = charset.box_sides();
+                                    text = format!("{left}{inner}{right}");
+                                } else if wire < bottom {
+                                    let side = charset.vertical();
+                                    text = format!("{side}{inner}{side}");
+                                }
+                            }
+                            Element::Gate { .. } => {}
+                        }
+                    }
+                    let fill = if on_wire { charset.wire() } else { ' ' };
+                    centre(line, &text, widths[c], fill);
+                }
+            }
+            out.push(
+                lines
+                    .iter()
+                    .map(|l| l.trim_end())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            );
+        }
+        out.join("\n\n")
+    }
+}
+
+/// Label giving the phase of a clause or the name of a gate.
+fn label(element: &Element, charset: Charset) -> String {
+    match element {
+        Element::Clause { phase, .. } => format!("[{}]", format_angle(*phase, charset.pi())),
+        Element::Gate { name, power, .. } => {
+            gate_label(name, *power, charset.dagger(), |p| format!("^{p}"))
+        }
+    }
+}
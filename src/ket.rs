@@ -39,6 +39,17 @@ impl KetState {
         }
     }
 
+    /// Returns true if this state is in the Hadamard basis, i.e. is |+> or |->.
+    pub fn is_hadamard_basis(self) -> bool {
+        matches!(self, KetState::Plus | KetState::Minus)
+    }
+
+    /// Returns the bit this state corresponds to within its own basis.
+    /// |0> and |+> give `false`, while |1> and |-> give `true`.
+    pub fn bit(self) -> bool {
+        matches!(self, KetState::One | KetState::Minus)
+    }
+
     /// Returns the character needed to print this ket state.
     pub fn to_char(&self) -> char {
         match self {
@@ -93,22 +104,23 @@ impl ToDoc for CompKetState {
     }
 }
 
-impl HasParser for CompKetState {
+impl HasParser for KetState {
+    /// Parser for the character of a ket state, without the surrounding "|" and ">".
     fn parser(input: &mut LocatingSlice<&str>) -> ModalResult<Self> {
-        delimited(
-            "|",
-            repeat(
-                1..,
-                alt((
-                    "0".value(KetState::Zero),
-                    "1".value(KetState::One),
-                    "+".value(KetState::Plus),
-                    "-".value(KetState::Minus),
-                )),
-            ),
-            ">",
-        )
-        .map(CompKetState)
+        alt((
+            "0".value(KetState::Zero),
+            "1".value(KetState::One),
+            "+".value(KetState::Plus),
+            "-".value(KetState::Minus),
+        ))
         .parse_next(input)
     }
 }
+
+impl HasParser for CompKetState {
+    fn parser(input: &mut LocatingSlice<&str>) -> ModalResult<Self> {
+        delimited("|", repeat(1.., KetState::parser), ">")
+            .map(CompKetState)
+            .parse_next(input)
+    }
+}
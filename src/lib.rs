@@ -1,8 +1,12 @@
 //! The repository contains an implementation of the combinator variant of the "it's just a phase" language.
 //! Given an input program, the interpreter:
-//! - performs some basic normalisation (associativity etc.)
+//! - performs some basic normalisation (associativity etc.) and simplification (cancelling inverses, merging phases and "if let" statements)
 //! - performs macro expansion of inverses, square roots, and gate definitions
 //! - compiles the term to a circuit definition.
+//! - optionally optimises the circuit by merging and cancelling its clauses, and folding diagonal runs through their phase polynomial.
+//! - optionally simplifies the circuit as a ZX-diagram and extracts it back to a circuit.
+//! - optionally lowers the circuit to one- and two-qubit gates, and synthesises rotations as Clifford+T gates.
+//! - optionally routes the lowered circuit onto a coupling map of physical qubits.
 //! - Builds the unitary for the output.
 //!
 //! ## Running
@@ -20,12 +24,24 @@
 
 // #![warn(missing_docs)]
 
+pub mod budget;
 pub mod circuit_syntax;
 pub mod command;
+pub mod diagram;
 pub mod ket;
+pub mod lowered_syntax;
 pub mod normal_syntax;
+pub mod parallel;
 pub mod phase;
+pub mod power;
+pub mod proof;
 pub mod raw_syntax;
+pub mod resources;
+pub mod resugar;
+pub mod routing;
+mod simulate;
+pub mod synthesis;
 pub mod text;
 pub mod typecheck;
 pub mod typed_syntax;
+pub mod zx;
@@ -0,0 +1,144 @@
+//! Lowered circuits.
+
+use std::f64::consts::PI;
+
+use faer::Mat;
+use num_complex::Complex;
+use pretty::RcDoc;
+
+use crate::{
+    lowered_syntax::GateL,
+    parallel::{build_columns, identity_columns},
+    simulate::apply_phase,
+    text::ToDoc,
+    typed_syntax::TermType,
+};
+
+/// Angles within this distance of a multiple of pi/4 (after dividing by pi) are treated as
+/// exact multiples.
+const ANGLE_TOLERANCE: f64 = 1e-12;
+
+/// A circuit of one- and two-qubit gates.
+///
+/// The first `ty` wires hold the data qubits and the remaining `ancillas` wires are
+/// ancillas, which start in |0> and are returned to |0> by the circuit.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CircuitL {
+    /// Gates in the order they are applied.
+    pub gates: Vec<GateL>,
+    /// Global phase of the circuit, divided by pi.
+    pub phase: f64,
+    /// Type of the data qubits.
+    pub ty: TermType,
+    /// Number of ancilla qubits.
+    pub ancillas: usize,
+}
+
+impl CircuitL {
+    /// Create an empty circuit acting on the given data qubits and ancillas.
+    pub fn new(ty: TermType, ancillas: usize) -> Self {
+        CircuitL {
+            gates: vec![],
+            phase: 0.0,
+            ty,
+            ancillas,
+        }
+    }
+
+    /// Total number of wires, including ancillas.
+    pub fn qubits(&self) -> usize {
+        self.ty.0 + self.ancillas
+    }
+
+    /// Returns the number of gates acting on two qubits.
+    pub fn two_qubit_count(&self) -> usize {
+        self.gates.iter().filter(|g| g.wires().len() == 2).count()
+    }
+
+    /// Returns the number of T gates needed by the circuit: T and T^dagger gates, together
+    /// with rotations by odd multiples of pi/4.
+    pub fn t_count(&self) -> usize {
+        self.gates
+            .iter()
+            .filter(|g| match g {
+                GateL::T(_) | GateL::Tdg(_) => true,
+                GateL::Rz(_, angle) => {
+                    let m = angle * 4.0;
+                    (m - m.round()).abs() < ANGLE_TOLERANCE && m.round().rem_euclid(2.0) == 1.0
+                }
+                _ => false,
+            })
+            .count()
+    }
+
+    /// Returns the number of rotations by angles which are not multiples of pi/4, and so
+    /// must be approximated by Clifford+T gates.
+    pub fn rotation_count(&self) -> usize {
+        self.gates
+            .iter()
+            .filter(|g| {
+                if let GateL::Rz(_, angle) = g {
+                    let m = angle * 4.0;
+                    (m - m.round()).abs() >= ANGLE_TOLERANCE
+                } else {
+                    false
+                }
+            })
+            .count()
+    }
+
+    /// Build the unitary of the whole circuit, including ancilla wires.
+    /// The columns are simulated in parallel.
+    pub fn to_unitary(&self) -> Mat<Complex<f64>> {
+        let size = 1 << self.qubits();
+        build_columns(size, size, |cols| {
+            let mut u = identity_columns(size, cols);
+            for g in &self.gates {
+                g.apply(&mut u, self.qubits());
+            }
+            apply_phase(&mut u, 0, 0, Complex::cis(self.phase * PI));
+            u
+        })
+    }
+
+    /// Build the unitary of the circuit restricted to ancillas starting (and ending) in |0>.
+    /// This should agree with the unitary of the term the circuit was lowered from.
+    pub fn to_data_unitary(&self) -> Mat<Complex<f64>> {
+        let size = 1 << self.ty.0;
+        let u = build_columns(1 << self.qubits(), size, |cols| {
+            let mut u = Mat::zeros(1 << self.qubits(), cols.len());
+            for (j, i) in cols.enumerate() {
+                u[(i << self.ancillas, j)] = Complex::ONE;
+            }
+            for g in &self.gates {
+                g.apply(&mut u, self.qubits());
+            }
+            u
+        });
+        let phase = Complex::cis(self.phase * PI);
+        Mat::from_fn(size, size, |i, j| u[(i << self.ancillas, j)] * phase)
+    }
+
+    /// Returns the largest entrywise difference between the data unitary of this circuit
+    /// and the given matrix.
+    pub fn distance(&self, unitary: &Mat<Complex<f64>>) -> f64 {
+        let u = self.to_data_unitary();
+        (0..u.nrows())
+            .flat_map(|i| (0..u.ncols()).map(move |j| (i, j)))
+            .map(|(i, j)| (u[(i, j)] - unitary[(i, j)]).norm())
+            .fold(0.0, f64::max)
+    }
+}
+
+impl ToDoc for CircuitL {
+    fn to_doc(&self) -> RcDoc<'_> {
+        let phase = (self.phase != 0.0).then(|| RcDoc::text(format!("ph({}pi)", self.phase)));
+        RcDoc::intersperse(
+            phase
+                .into_iter()
+                .chain(self.gates.iter().map(GateL::to_doc)),
+            RcDoc::text(";").append(RcDoc::line()),
+        )
+        .group()
+    }
+}
@@ -0,0 +1,186 @@
+//! Conversion of lowered circuits back to the phase language.
+
+use std::{cell::RefCell, collections::HashSet};
+
+use crate::{
+    command::Command,
+    ket::{CompKetState, KetState},
+    lowered_syntax::{CircuitL, GateL, prelude::Prelude},
+    phase::Phase,
+    typed_syntax::{PatternT, TermT, TermType},
+};
+
+impl CircuitL {
+    /// Convert the circuit to a command of the phase language, acting on all wires
+    /// including ancillas.
+    ///
+    /// Gates are given by the definitions of the prelude, and only the gates the circuit
+    /// uses are defined. Two-qubit gates on wires which are not adjacent, or in the wrong
+    /// order, are written as "if let" terms with a control pattern.
+    pub fn to_command(&self) -> Command<()> {
+        let prelude = Prelude::new();
+        let used = RefCell::new(HashSet::new());
+        let gate = |name: &'static str| {
+            used.borrow_mut().insert(name);
+            prelude.gate(name)
+        };
+        let emitter = Emitter {
+            qubits: self.qubits(),
+            gate: &gate,
+        };
+
+        let mut phase = self.phase;
+        let mut terms = vec![];
+        for g in &self.gates {
+            match g {
+                GateL::H(q) => terms.push(emitter.place(*q, gate("H"))),
+                GateL::X(q) => terms.push(emitter.place(*q, gate("X"))),
+                GateL::Z(q) => terms.push(emitter.place(*q, gate("Z"))),
+                GateL::S(q) => terms.push(emitter.place(*q, gate("S"))),
+                GateL::Sdg(q) => terms.push(emitter.place(*q, gate("Sdag"))),
+                GateL::T(q) => terms.push(emitter.place(*q, gate("T"))),
+                GateL::Tdg(q) => terms.push(emitter.place(*q, TermT::Inverse(Box::new(gate("T"))))),
+                GateL::Rz(q, angle) => {
+                    // Rz(a) = ph(-a/2 pi) (if let |1> then ph(a pi))
+                    phase -= angle / 2.0;
+                    terms.push(emitter.place(
+                        *q,
+                        TermT::IfLet {
+                            pattern: PatternT::Ket(CompKetState::new(vec![KetState::One])),
+                            inner: Box::new(TermT::Phase(Phase::from_angle(*angle))),
+                        },
+                    ))
+                }
+                GateL::CX(c, t) => terms.push(emitter.cx(*c, *t)),
+                GateL::CZ(a, b) => terms.push(emitter.cz(*a, *b)),
+                GateL::Swap(a, b) => {
+                    let (lo, hi) = (*a.min(b), *a.max(b));
+                    if hi == lo + 1 {
+                        terms.push(emitter.place(lo, gate("Swap")));
+                    } else {
+                        terms.extend([emitter.cx(lo, hi), emitter.cx(hi, lo), emitter.cx(lo, hi)]);
+                    }
+                }
+            }
+        }
+        let phase = phase.rem_euclid(2.0);
+        if phase != 0.0 {
+            terms.insert(
+                0,
+                TermT::Tensor(vec![
+                    TermT::Phase(Phase::from_angle(phase)),
+                    TermT::Id(TermType(self.qubits())),
+                ]),
+            );
+        }
+        if terms.is_empty() {
+            terms.push(TermT::Id(TermType(self.qubits())));
+        }
+
+        Command {
+            gates: prelude.definitions(used.into_inner()),
+            rules: vec![],
+            proofs: vec![],
+            term: TermT::Comp(terms).to_raw(),
+        }
+    }
+}
+
+/// Helper for placing gates on a subset of the wires of a circuit.
+struct Emitter<'a> {
+    qubits: usize,
+    gate: &'a dyn Fn(&'static str) -> TermT,
+}
+
+impl Emitter<'_> {
+    /// Tensor `term` with identities so that it acts from wire `start` of `qubits` wires.
+    fn place_within(qubits: usize, start: usize, term: TermT) -> TermT {
+        let end = start + term.get_type().0;
+        let mut terms = vec![];
+        if start > 0 {
+            terms.push(TermT::Id(TermType(start)));
+        }
+        terms.push(term);
+        if end < qubits {
+            terms.push(TermT::Id(TermType(qubits - end)));
+        }
+        TermT::Tensor(terms)
+    }
+
+    fn place(&self, start: usize, term: TermT) -> TermT {
+        Self::place_within(self.qubits, start, term)
+    }
+
+    /// "if let" term applying the single-qubit `term` to wire `target` when wire `control`
+    /// is in state |1>.
+    fn controlled(&self, control: usize, target: usize, term: TermT) -> TermT {
+        let mut patterns = vec![];
+        if control > 0 {
+            patterns.push(PatternT::Unitary(Box::new(TermT::Id(TermType(control)))));
+        }
+        patterns.push(PatternT::Ket(CompKetState::new(vec![KetState::One])));
+        if control + 1 < self.qubits {
+            patterns.push(PatternT::Unitary(Box::new(TermT::Id(TermType(
+                self.qubits - control - 1,
+            )))));
+        }
+        // The control wire is removed from the wires seen by the body.
+        let target = if target > control { target - 1 } else { target };
+        TermT::IfLet {
+            pattern: PatternT::Tensor(patterns),
+            inner: Box::new(Self::place_within(self.qubits - 1, target, term)),
+        }
+    }
+
+    fn cx(&self, control: usize, target: usize) -> TermT {
+        if target == control + 1 {
+            self.place(control, (self.gate)("CX"))
+        } else {
+            self.controlled(control, target, (self.gate)("X"))
+        }
+    }
+
+    fn cz(&self, a: usize, b: usize) -> TermT {
+        let (lo, hi) = (a.min(b), a.max(b));
+        if hi == lo + 1 {
+            self.place(lo, (self.gate)("CZ"))
+        } else {
+            self.controlled(lo, hi, (self.gate)("Z"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use winnow::{LocatingSlice, Parser};
+
+    use super::*;
+    use crate::text::{HasParser, ToDoc};
+
+    #[test]
+    fn emitted_command_defines_used_gates_and_matches_unitary() {
+        let mut circuit = CircuitL::new(TermType(3), 0);
+        circuit.gates = vec![
+            GateL::T(0),
+            GateL::CX(2, 0),
+            GateL::Swap(1, 2),
+            GateL::Rz(1, 0.3),
+        ];
+        let command = circuit.to_command();
+        let names: Vec<_> = command.gates.iter().map(|(n, _)| n.to_string()).collect();
+        assert_eq!(names, ["Z", "X", "S", "T", "CX", "XC", "Swap"]);
+
+        let src = command.to_doc().pretty(60).to_string();
+        let (_, term) = Command::parser
+            .parse(LocatingSlice::new(src.as_str()))
+            .expect("Emitted command should parse")
+            .check()
+            .expect("Emitted command should typecheck");
+        let (a, b) = (term.to_unitary().unwrap(), circuit.to_unitary());
+        for i in 0..a.nrows() {
+            for j in 0..a.ncols() {
+                assert!((a[(i, j)] - b[(i, j)]).norm() < 1e-9);
+            }
+        }
+    }
+}
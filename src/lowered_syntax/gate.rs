@@ -0,0 +1,133 @@
+//! Lowered gates.
+
+use std::f64::consts::PI;
+
+use faer::Mat;
+use num_complex::Complex;
+use pretty::RcDoc;
+
+use crate::{
+    simulate::{HADAMARD, PAULI_X, apply_cx, apply_phase, apply_single, wire_bit},
+    text::ToDoc,
+};
+
+/// A one- or two-qubit gate, acting on the given wires.
+/// Angles are given divided by pi, as for `Phase`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GateL {
+    /// Hadamard gate
+    H(usize),
+    /// Pauli X gate
+    X(usize),
+    /// Pauli Z gate
+    Z(usize),
+    /// Phase gate "S", equal to diag(1, i)
+    S(usize),
+    /// Inverse of the phase gate
+    Sdg(usize),
+    /// "T" gate, equal to diag(1, e^(i pi / 4))
+    T(usize),
+    /// Inverse of the T gate
+    Tdg(usize),
+    /// Z rotation "Rz(a)", equal to diag(e^(-i pi a/2), e^(i pi a/2))
+    Rz(usize, f64),
+    /// Controlled not with given control and target
+    CX(usize, usize),
+    /// Controlled Z, which is symmetric in its wires
+    CZ(usize, usize),
+    /// Swap of two wires
+    Swap(usize, usize),
+}
+
+impl GateL {
+    /// Returns the wires this gate acts on.
+    pub fn wires(&self) -> Vec<usize> {
+        match self {
+            GateL::H(q)
+            | GateL::X(q)
+            | GateL::Z(q)
+            | GateL::S(q)
+            | GateL::Sdg(q)
+            | GateL::T(q)
+            | GateL::Tdg(q)
+            | GateL::Rz(q, _) => vec![*q],
+            GateL::CX(a, b) | GateL::CZ(a, b) | GateL::Swap(a, b) => vec![*a, *b],
+        }
+    }
+
+    /// Returns this gate with each wire `w` replaced by `f(w)`.
+    pub fn map_wires(&self, f: impl Fn(usize) -> usize) -> GateL {
+        match self {
+            GateL::H(q) => GateL::H(f(*q)),
+            GateL::X(q) => GateL::X(f(*q)),
+            GateL::Z(q) => GateL::Z(f(*q)),
+            GateL::S(q) => GateL::S(f(*q)),
+            GateL::Sdg(q) => GateL::Sdg(f(*q)),
+            GateL::T(q) => GateL::T(f(*q)),
+            GateL::Tdg(q) => GateL::Tdg(f(*q)),
+            GateL::Rz(q, angle) => GateL::Rz(f(*q), *angle),
+            GateL::CX(c, t) => GateL::CX(f(*c), f(*t)),
+            GateL::CZ(a, b) => GateL::CZ(f(*a), f(*b)),
+            GateL::Swap(a, b) => GateL::Swap(f(*a), f(*b)),
+        }
+    }
+
+    /// Apply this gate to each column of `state`, a matrix with 2^qubits rows.
+    pub(crate) fn apply(&self, state: &mut Mat<Complex<f64>>, qubits: usize) {
+        match self {
+            GateL::H(q) => apply_single(state, qubits, *q, &HADAMARD),
+            GateL::X(q) => apply_single(state, qubits, *q, &PAULI_X),
+            GateL::Z(q) => apply_phase_on_one(state, qubits, *q, 1.0),
+            GateL::S(q) => apply_phase_on_one(state, qubits, *q, 0.5),
+            GateL::Sdg(q) => apply_phase_on_one(state, qubits, *q, -0.5),
+            GateL::T(q) => apply_phase_on_one(state, qubits, *q, 0.25),
+            GateL::Tdg(q) => apply_phase_on_one(state, qubits, *q, -0.25),
+            GateL::Rz(q, angle) => {
+                let bit = wire_bit(qubits, *q);
+                apply_phase(state, bit, 0, Complex::cis(-angle * PI / 2.0));
+                apply_phase(state, bit, bit, Complex::cis(angle * PI / 2.0));
+            }
+            GateL::CX(c, t) => apply_cx(state, qubits, *c, *t),
+            GateL::CZ(a, b) => {
+                let mask = wire_bit(qubits, *a) | wire_bit(qubits, *b);
+                apply_phase(state, mask, mask, -Complex::ONE);
+            }
+            GateL::Swap(a, b) => {
+                apply_cx(state, qubits, *a, *b);
+                apply_cx(state, qubits, *b, *a);
+                apply_cx(state, qubits, *a, *b);
+            }
+        }
+    }
+}
+
+fn apply_phase_on_one(state: &mut Mat<Complex<f64>>, qubits: usize, wire: usize, angle: f64) {
+    let bit = wire_bit(qubits, wire);
+    apply_phase(state, bit, bit, Complex::cis(angle * PI));
+}
+
+fn wire_doc(q: &usize) -> RcDoc<'_> {
+    RcDoc::text(format!("q{q}"))
+}
+
+impl ToDoc for GateL {
+    fn to_doc(&self) -> RcDoc<'_> {
+        let (name, wires) = match self {
+            GateL::H(q) => (RcDoc::text("H"), vec![q]),
+            GateL::X(q) => (RcDoc::text("X"), vec![q]),
+            GateL::Z(q) => (RcDoc::text("Z"), vec![q]),
+            GateL::S(q) => (RcDoc::text("S"), vec![q]),
+            GateL::Sdg(q) => (RcDoc::text("Sdg"), vec![q]),
+            GateL::T(q) => (RcDoc::text("T"), vec![q]),
+            GateL::Tdg(q) => (RcDoc::text("Tdg"), vec![q]),
+            GateL::Rz(q, angle) => (RcDoc::text(format!("Rz({angle}pi)")), vec![q]),
+            GateL::CX(c, t) => (RcDoc::text("CX"), vec![c, t]),
+            GateL::CZ(a, b) => (RcDoc::text("CZ"), vec![a, b]),
+            GateL::Swap(a, b) => (RcDoc::text("Swap"), vec![a, b]),
+        };
+        name.append(RcDoc::space()).append(RcDoc::intersperse(
+            wires.into_iter().map(wire_doc),
+            RcDoc::space(),
+        ))
+    }
+}
@@ -0,0 +1,13 @@
+//! Lowered syntax definitions
+//!
+//! Circuits of one- and two-qubit gates, as produced by decomposing circuit-normal terms.
+//! Unlike the other syntaxes, these are not terms of the phase language.
+
+pub mod gate;
+pub use gate::GateL;
+
+pub mod circuit;
+pub use circuit::CircuitL;
+
+mod emit;
+mod prelude;
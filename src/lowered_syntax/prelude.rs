@@ -0,0 +1,128 @@
+//! Definitions of the gates used when writing lowered circuits in the phase language.
+
+use std::{collections::HashSet, ops::Range};
+
+use winnow::{LocatingSlice, Parser};
+
+use crate::{
+    command::Command,
+    raw_syntax::TermR,
+    text::{HasParser, Name},
+    typecheck::{Env, GateEntry},
+    typed_syntax::TermT,
+};
+
+/// Gates available to emitted terms, each with its definition and the earlier gates it uses.
+/// The definitions are those of Figure 3 in the paper and of `examples/gates.ph`.
+const PRELUDE: &[(&str, &str, &[&str])] = &[
+    ("Z", "if let |1> then -1", &[]),
+    ("X", "if let |-> then -1", &[]),
+    ("S", "sqrt(Z)", &["Z"]),
+    ("Sdag", "S ^ -1", &["S"]),
+    ("T", "sqrt(S)", &["S"]),
+    ("Y", "if let S . |-> then -1", &["S"]),
+    ("H", "if let sqrt(sqrt(Y)) . |1> then -1", &["Y"]),
+    ("CZ", "if let |11> then -1", &[]),
+    ("CX", "if let |1> x id then X", &["X"]),
+    ("XC", "if let |-1> then -1", &[]),
+    ("Swap", "if let CX then XC", &["CX", "XC"]),
+];
+
+/// Typechecked prelude gates.
+pub(crate) struct Prelude {
+    env: Env,
+}
+
+impl Prelude {
+    /// Parse and typecheck the prelude.
+    pub(crate) fn new() -> Self {
+        let (env, _) = prelude_source(PRELUDE.iter().map(|(name, _, _)| *name))
+            .check()
+            .expect("Prelude should typecheck");
+        Prelude { env }
+    }
+
+    fn entry(&self, name: &str) -> (&Name, &GateEntry) {
+        self.env
+            .gates
+            .iter()
+            .find(|(n, _)| n.to_string() == name)
+            .unwrap_or_else(|| panic!("Gate {name} should be in the prelude"))
+    }
+
+    /// Returns the named prelude gate.
+    pub(crate) fn gate(&self, name: &str) -> TermT {
+        let (name, entry) = self.entry(name);
+        TermT::Gate {
+            name: name.clone(),
+            def: entry.def.clone(),
+        }
+    }
+
+    /// Returns the definitions of the given gates and the gates they use, in prelude order.
+    pub(crate) fn definitions<'a>(
+        &self,
+        used: impl IntoIterator<Item = &'a str>,
+    ) -> Vec<(Name, TermR<()>)> {
+        needed(used)
+            .into_iter()
+            .map(|name| {
+                let (name, entry) = self.entry(name);
+                (name.clone(), entry.def.to_raw())
+            })
+            .collect()
+    }
+}
+
+/// Returns the names of the given gates and the gates they use, in prelude order.
+fn needed<'a>(used: impl IntoIterator<Item = &'a str>) -> Vec<&'static str> {
+    let mut needed: HashSet<&str> = used.into_iter().collect();
+    for (name, _, uses) in PRELUDE.iter().rev() {
+        if needed.contains(name) {
+            needed.extend(uses.iter().copied());
+        }
+    }
+    PRELUDE
+        .iter()
+        .map(|(name, _, _)| *name)
+        .filter(|name| needed.contains(name))
+        .collect()
+}
+
+/// Parse a program defining the given prelude gates, and the gates they use.
+fn prelude_source<'a>(names: impl IntoIterator<Item = &'a str>) -> Command<Range<usize>> {
+    let mut src = String::new();
+    for name in needed(names) {
+        let (_, def, _) = PRELUDE.iter().find(|(n, _, _)| *n == name).unwrap();
+        src += &format!("gate {name} = {def},\n");
+    }
+    src += "id";
+    Command::parser
+        .parse(LocatingSlice::new(&src))
+        .expect("Prelude should parse")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_gate_lists_the_gates_it_uses() {
+        for (name, _, _) in PRELUDE {
+            let checked = prelude_source([*name]).check();
+            assert!(
+                checked.is_ok(),
+                "Gate {name} should typecheck with the gates it uses"
+            );
+        }
+    }
+
+    #[test]
+    fn needed_gates_are_in_prelude_order() {
+        assert_eq!(
+            needed(["Swap", "T"]),
+            ["Z", "X", "S", "T", "CX", "XC", "Swap"]
+        );
+        assert_eq!(needed(["CZ"]), ["CZ"]);
+    }
+}
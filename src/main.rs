@@ -1,11 +1,20 @@
 use std::{io, io::Read, path::PathBuf};
 
+use faer::Mat;
 use float_pretty_print::PrettyPrintFloat;
 use miette::{Result, miette};
+use num_complex::Complex;
 use phase_rs::{
+    budget,
+    circuit_syntax::lower::{Decomposition, Entangler},
     command::Command,
+    diagram::text::Charset,
     normal_syntax::TermN,
+    parallel,
+    resugar::GateLibrary,
+    routing::CouplingMap,
     text::{HasParser, ToDoc},
+    typed_syntax::InlinePolicy,
 };
 use winnow::{LocatingSlice, Parser};
 
@@ -15,24 +24,485 @@ struct Args {
     /// File name to run
     #[arg(long, value_name = "FILE")]
     file: Option<PathBuf>,
+    /// Optimise the circuit by merging and cancelling clauses
+    #[arg(long)]
+    optimise: bool,
+    /// Fold runs of diagonal clauses through their phase polynomial
+    #[arg(long)]
+    fold_phases: bool,
+    /// Keep the named gates opaque when evaluating, rather than expanding them
+    #[arg(long, value_name = "GATE", value_delimiter = ',')]
+    opaque: Vec<String>,
+    /// Expand only this many levels of nested gate definitions when evaluating
+    #[arg(long, value_name = "DEPTH")]
+    inline_depth: Option<usize>,
+    /// Keep gates acting on more than this many qubits opaque when evaluating
+    #[arg(long, value_name = "QUBITS")]
+    opaque_above: Option<usize>,
+    /// Print the evaluated term and circuit using the names of defined gates where possible
+    #[arg(long)]
+    resugar: bool,
+    /// Simplify the circuit as a ZX-diagram and extract it back to a circuit, which replaces
+    /// the original circuit if it is smaller and checked equivalent
+    #[arg(long)]
+    zx: bool,
+    /// Write the ZX-diagram of the circuit to the given file in the PyZX ".qgraph" format,
+    /// simplified if "--zx" is given
+    #[arg(long, value_name = "FILE")]
+    qgraph: Option<PathBuf>,
+    /// Group the clauses of the circuit into layers of independent clauses
+    #[arg(long)]
+    schedule: bool,
+    /// Draw the circuit as a diagram of wires and controls
+    #[arg(long)]
+    diagram: bool,
+    /// Draw diagrams using only ASCII characters
+    #[arg(long)]
+    ascii: bool,
+    /// Width to wrap diagrams to. Defaults to the COLUMNS environment variable, or 80.
+    #[arg(long, value_name = "COLUMNS")]
+    width: Option<usize>,
+    /// Write the circuit diagram as an SVG image to the given file
+    #[arg(long, value_name = "FILE")]
+    svg: Option<PathBuf>,
+    /// Write the circuit diagram as a quantikz LaTeX environment to the given file
+    #[arg(long, value_name = "FILE")]
+    quantikz: Option<PathBuf>,
+    /// Draw named gates in diagrams as labelled boxes rather than expanding them.
+    /// The diagram is then drawn from the input term, before any optimisation.
+    #[arg(long)]
+    gate_boxes: bool,
+    /// Lower the circuit to one- and two-qubit gates using the given strategy
+    #[arg(long, value_name = "STRATEGY")]
+    lower: Option<LowerStrategy>,
+    /// Use controlled Z rather than controlled not gates when lowering
+    #[arg(long)]
+    cz: bool,
+    /// Synthesise rotations in the lowered circuit as Clifford+T gates within the given
+    /// precision. Lowers without ancillas if no strategy is given.
+    #[arg(long, value_name = "EPSILON")]
+    synthesise: Option<f64>,
+    /// Route the lowered circuit onto a coupling map: "line", "grid", "line:N", "grid:RxC",
+    /// or a file listing one edge per line. Lowers without ancillas if no strategy is given.
+    #[arg(long, value_name = "MAP")]
+    route: Option<String>,
+    /// Print resource estimates instead of the usual output, as text or JSON
+    #[arg(long, value_name = "FORMAT", num_args = 0..=1, default_missing_value = "text")]
+    stats: Option<StatsFormat>,
+    /// Number of threads used to build unitaries. Defaults to the number of available cores.
+    /// Results do not depend on the number of threads.
+    #[arg(long, value_name = "N")]
+    threads: Option<usize>,
+    /// Largest amount of memory, in MiB, to use for the unitary of a term
+    #[arg(long, value_name = "MIB", default_value_t = budget::DEFAULT_LIMIT >> 20)]
+    memory_limit: usize,
+}
+
+/// Output formats for resource estimates
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum StatsFormat {
+    /// Human readable text
+    Text,
+    /// A JSON object
+    Json,
+}
+
+/// Strategies for decomposing multi-controlled phases
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum LowerStrategy {
+    /// Use a phase polynomial over the controls, without extra qubits
+    NoAncilla,
+    /// Use Toffoli ladders into clean ancilla qubits
+    Ancilla,
+}
+
+/// Largest number of wires for which the lowered circuit is checked against the unitary.
+const MAX_CHECK_QUBITS: usize = 10;
+
+fn diagram_width(args: &Args) -> usize {
+    args.width
+        .or_else(|| std::env::var("COLUMNS").ok()?.parse().ok())
+        .unwrap_or(80)
+}
+
+fn inline_policy(args: &Args) -> InlinePolicy {
+    InlinePolicy {
+        opaque: args.opaque.clone(),
+        max_depth: args.inline_depth,
+        max_qubits: args.opaque_above,
+    }
 }
 
-fn parse_and_check(src: &str) -> Result<()> {
+fn decomposition(args: &Args) -> Decomposition {
+    match args.lower.unwrap_or(LowerStrategy::NoAncilla) {
+        LowerStrategy::NoAncilla => Decomposition::NoAncilla,
+        LowerStrategy::Ancilla => Decomposition::Ancilla,
+    }
+}
+
+fn entangler(args: &Args) -> Entangler {
+    if args.cz {
+        Entangler::CZ
+    } else {
+        Entangler::CX
+    }
+}
+
+/// Returns the largest entrywise difference between two matrices of the same size.
+fn max_deviation(a: &Mat<Complex<f64>>, b: &Mat<Complex<f64>>) -> f64 {
+    (0..a.nrows())
+        .flat_map(|i| (0..a.ncols()).map(move |j| (i, j)))
+        .map(|(i, j)| (a[(i, j)] - b[(i, j)]).norm())
+        .fold(0.0, f64::max)
+}
+
+/// Largest deviation between two matrices after removing a global phase from the first.
+fn max_deviation_up_to_phase(a: &Mat<Complex<f64>>, b: &Mat<Complex<f64>>) -> f64 {
+    let (i, j) = (0..b.nrows())
+        .flat_map(|i| (0..b.ncols()).map(move |j| (i, j)))
+        .max_by(|&x, &y| b[x].norm().total_cmp(&b[y].norm()))
+        .unwrap_or((0, 0));
+    let phase = if a[(i, j)].norm() == 0.0 {
+        Complex::new(1.0, 0.0)
+    } else {
+        let ratio = b[(i, j)] / a[(i, j)];
+        ratio / ratio.norm()
+    };
+    let mut rotated = a.clone();
+    for x in rotated.col_iter_mut() {
+        for y in x.iter_mut() {
+            *y *= phase;
+        }
+    }
+    max_deviation(&rotated, b)
+}
+
+/// Build the coupling map given by `spec`, for a circuit with the given number of wires.
+/// Lines and grids without explicit sizes are made just large enough for the circuit.
+fn coupling_map(spec: &str, qubits: usize) -> Result<CouplingMap> {
+    let size = |n: &str| {
+        n.parse::<usize>()
+            .map_err(|_| miette!("Invalid coupling map size \"{n}\""))
+    };
+    match spec.split_once(':') {
+        None if spec == "line" => Ok(CouplingMap::line(qubits)),
+        None if spec == "grid" => {
+            let cols = qubits.isqrt() + usize::from(qubits.isqrt().pow(2) < qubits);
+            Ok(CouplingMap::grid(qubits.div_ceil(cols.max(1)), cols))
+        }
+        Some(("line", n)) => Ok(CouplingMap::line(size(n)?)),
+        Some(("grid", dims)) => {
+            let (rows, cols) = dims
+                .split_once('x')
+                .ok_or_else(|| miette!("Grid size should be of the form RxC"))?;
+            Ok(CouplingMap::grid(size(rows)?, size(cols)?))
+        }
+        _ => {
+            let src = std::fs::read_to_string(spec)
+                .map_err(|e| miette!("Could not read coupling map {spec}: {e}"))?;
+            Ok(CouplingMap::parse_edge_list(&src)?)
+        }
+    }
+}
+
+fn parse_and_check(src: &str, args: &Args) -> Result<()> {
     let parsed = Command::parser
         .parse(LocatingSlice::new(src))
         .map_err(|e| miette!("{e}"))?;
-    let (_env, checked) = parsed.check()?;
+    let (env, checked) = parsed.check()?;
+    let certificates = parsed.check_proofs(&env)?;
+    if let Some(format) = args.stats {
+        let mut circuit = if checked.has_repetition() {
+            checked.eval_circ_repeated().expand()
+        } else {
+            let mut evalled: TermN = checked.eval_with_policy(&inline_policy(args));
+            evalled.squash();
+            evalled.apply_rules(env.rules());
+            evalled.simplify();
+            evalled.quote().eval_circ()
+        };
+        if args.optimise {
+            circuit.optimise();
+        }
+        if args.fold_phases {
+            circuit.fold_phases();
+        }
+        let estimate = circuit.estimate_resources(decomposition(args), entangler(args));
+        match format {
+            StatsFormat::Text => println!("{estimate}"),
+            StatsFormat::Json => println!("{}", estimate.to_json()),
+        }
+        return Ok(());
+    }
+    parsed.check_unitary_size(checked.get_type())?;
+    for certificate in &certificates {
+        println!("{certificate}");
+    }
     println!("Input term:\n{}\n", checked.to_raw().to_doc().pretty(60));
-    let mut evalled: TermN = checked.eval();
-    evalled.squash();
-    let quoted = evalled.quote();
-    let raw = quoted.to_raw();
-    println!("Evaluated:\n{}\n", raw.to_doc().pretty(60));
-    let circuit = quoted.eval_circ();
-    let circuit_quoted = circuit.quote();
-    let circuit_raw = circuit_quoted.to_raw();
-    println!("Circuit:\n{}\n", circuit_raw.to_doc().pretty(60));
-    let unitary = evalled.to_unitary();
+    let (mut circuit, unitary) = if checked.has_repetition() {
+        // Evaluating would unroll every repetition, so the repeat-aware circuit is used instead.
+        if !env.rules().is_empty() {
+            println!("Rewrite rules are not applied to terms with repetitions\n");
+        }
+        let repeated = checked.eval_circ_repeated();
+        println!(
+            "Repeated circuit ({} clauses, {} when expanded):\n{}\n",
+            repeated.num_clauses(),
+            repeated.num_expanded_clauses(),
+            repeated.quote().to_raw().to_doc().pretty(60)
+        );
+        let unitary = repeated.to_unitary()?;
+        (repeated.expand(), unitary)
+    } else {
+        let mut evalled: TermN = checked.eval_with_policy(&inline_policy(args));
+        evalled.squash();
+        let rewrites = evalled.apply_rules(env.rules());
+        if !env.rules().is_empty() {
+            println!("Applied rewrite rules {rewrites} times\n");
+        }
+        evalled.simplify();
+        let quoted = evalled.quote();
+        let library = args.resugar.then(|| GateLibrary::new(&env));
+        let raw = match &library {
+            Some(library) => evalled.resugar(library).to_raw(),
+            None => quoted.to_raw(),
+        };
+        println!("Evaluated:\n{}\n", raw.to_doc().pretty(60));
+        let circuit = quoted.eval_circ();
+        let circuit_quoted = match &library {
+            Some(library) => circuit.resugar(library),
+            None => circuit.quote(),
+        };
+        let circuit_raw = circuit_quoted.to_raw();
+        println!("Circuit:\n{}\n", circuit_raw.to_doc().pretty(60));
+        (circuit, checked.to_unitary()?)
+    };
+    if args.optimise {
+        let report = circuit.optimise();
+        println!(
+            "Optimised circuit ({} clauses, was {}):\n{}\n",
+            report.after,
+            report.before,
+            circuit.quote().to_raw().to_doc().pretty(60)
+        );
+        if circuit.get_type().0 <= MAX_CHECK_QUBITS {
+            let distance = max_deviation(&circuit.to_unitary()?, &unitary);
+            if distance > 1e-9 {
+                return Err(miette!(
+                    "Optimised circuit does not match unitary (deviation {distance:e})"
+                ));
+            }
+            println!("Optimisation check: unitary matches (deviation {distance:e})\n");
+        }
+    }
+    if args.fold_phases {
+        let report = circuit.fold_phases();
+        println!(
+            "Phase folded circuit ({} clauses, was {}, {} diagonal runs):\n{}\n",
+            report.after,
+            report.before,
+            report.runs,
+            circuit.quote().to_raw().to_doc().pretty(60)
+        );
+        if circuit.get_type().0 <= MAX_CHECK_QUBITS {
+            let distance = max_deviation(&circuit.to_unitary()?, &unitary);
+            if distance > 1e-9 {
+                return Err(miette!(
+                    "Phase folded circuit does not match unitary (deviation {distance:e})"
+                ));
+            }
+            println!("Phase folding check: unitary matches (deviation {distance:e})\n");
+        }
+    }
+    if args.zx || args.qgraph.is_some() {
+        let mut graph = circuit.to_zx()?;
+        if args.zx {
+            let report = graph.simplify();
+            println!(
+                "ZX-diagram simplified from {} to {} spiders ({} identities, {} local \
+                 complementations, {} pivots)\n",
+                report.before,
+                report.after,
+                report.identities,
+                report.local_complementations,
+                report.pivots
+            );
+        }
+        if let Some(path) = &args.qgraph {
+            std::fs::write(path, graph.to_qgraph())
+                .map_err(|e| miette!("Could not write {}: {e}", path.display()))?;
+            println!("Wrote ZX-diagram to {}\n", path.display());
+        }
+        if args.zx {
+            let extracted = graph.extract()?;
+            println!(
+                "ZX extracted circuit has {} clauses, was {}\n",
+                extracted.num_clauses(),
+                circuit.num_clauses()
+            );
+            if circuit.get_type().0 <= MAX_CHECK_QUBITS {
+                let distance = max_deviation_up_to_phase(&extracted.to_unitary()?, &unitary);
+                if distance > 1e-9 {
+                    return Err(miette!(
+                        "ZX extracted circuit does not match unitary (deviation {distance:e})"
+                    ));
+                }
+                println!(
+                    "ZX extraction check: unitary matches up to global phase (deviation \
+                     {distance:e})\n"
+                );
+            }
+            let equivalent = extracted.zx_equivalent(&circuit)?;
+            if equivalent {
+                println!("ZX equivalence check: extracted circuit is equal to the original\n");
+            } else {
+                println!("ZX equivalence check: inconclusive\n");
+            }
+            // Later passes use the extracted circuit only if it is an improvement.
+            if equivalent && extracted.num_clauses() < circuit.num_clauses() {
+                println!(
+                    "Continuing with the ZX extracted circuit:\n{}\n",
+                    extracted.quote().to_raw().to_doc().pretty(60)
+                );
+                circuit = extracted;
+            } else {
+                println!(
+                    "Continuing with the original circuit ({} clauses)\n",
+                    circuit.num_clauses()
+                );
+            }
+        }
+    }
+    if args.diagram || args.svg.is_some() || args.quantikz.is_some() {
+        let diagram = if args.gate_boxes {
+            checked.to_diagram()
+        } else {
+            circuit.to_diagram()
+        };
+        if args.diagram {
+            let charset = if args.ascii {
+                Charset::Ascii
+            } else {
+                Charset::Unicode
+            };
+            println!(
+                "Circuit diagram:\n{}\n",
+                diagram.render_text(charset, diagram_width(args))
+            );
+        }
+        if let Some(path) = &args.svg {
+            std::fs::write(path, diagram.render_svg())
+                .map_err(|e| miette!("Could not write {}: {e}", path.display()))?;
+            println!("Wrote SVG diagram to {}\n", path.display());
+        }
+        if let Some(path) = &args.quantikz {
+            std::fs::write(path, diagram.render_quantikz())
+                .map_err(|e| miette!("Could not write {}: {e}", path.display()))?;
+            println!("Wrote quantikz diagram to {}\n", path.display());
+        }
+    }
+    if args.schedule {
+        let schedule = circuit.schedule();
+        println!(
+            "Schedule (depth {}, critical path through clauses {}):",
+            schedule.depth(),
+            schedule
+                .critical_path
+                .iter()
+                .map(usize::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        for (i, layer) in circuit.layers().iter().enumerate() {
+            println!(
+                "Layer {i}:\n  {}",
+                layer.quote().to_raw().to_doc().nest(2).pretty(60)
+            );
+        }
+        println!();
+    }
+    if args.lower.is_some() || args.synthesise.is_some() || args.route.is_some() {
+        if circuit.opaque_powers() > 0 {
+            return Err(miette!(
+                "Circuit applies {} numerical powers, which can not be lowered to gates",
+                circuit.opaque_powers()
+            ));
+        }
+        let mut lowered = circuit.lower(decomposition(args), entangler(args));
+        println!(
+            "Lowered circuit ({} gates, {} two-qubit, {} ancillas):\n{}\n",
+            lowered.gates.len(),
+            lowered.two_qubit_count(),
+            lowered.ancillas,
+            lowered.to_doc().pretty(60)
+        );
+        if lowered.qubits() <= MAX_CHECK_QUBITS {
+            let distance = lowered.distance(&unitary);
+            if distance > 1e-9 {
+                return Err(miette!(
+                    "Lowered circuit does not match unitary (deviation {distance:e})"
+                ));
+            }
+            println!("Lowering check: unitary matches (deviation {distance:e})\n");
+        }
+        if let Some(epsilon) = args.synthesise {
+            let (synthesised, report) = lowered.synthesise(epsilon)?;
+            println!(
+                "Clifford+T circuit ({} gates, T-count {}):\n{}\n",
+                synthesised.gates.len(),
+                report.t_count,
+                synthesised.to_doc().pretty(60)
+            );
+            println!(
+                "Approximated {} rotations (max error {:e}, total error {:e})\n",
+                report.approximated, report.max_error, report.total_error
+            );
+            if synthesised.qubits() <= MAX_CHECK_QUBITS {
+                let distance = synthesised.distance(&unitary);
+                if distance > report.total_error + 1e-9 {
+                    return Err(miette!(
+                        "Clifford+T circuit exceeds its error bound (deviation {distance:e})"
+                    ));
+                }
+                println!("Synthesis check: unitary within error bound (deviation {distance:e})\n");
+            }
+            lowered = synthesised;
+        }
+        if let Some(spec) = &args.route {
+            let map = coupling_map(spec, lowered.qubits())?;
+            let routed = lowered.route(&map)?;
+            println!(
+                "Routed circuit ({} swaps, {} two-qubit gates before routing, {} after):\n{}\n",
+                routed.swaps,
+                lowered.two_qubit_count(),
+                routed.circuit.two_qubit_count(),
+                routed.circuit.to_doc().pretty(60)
+            );
+            println!(
+                "Final layout: {}\n",
+                routed
+                    .layout
+                    .iter()
+                    .enumerate()
+                    .map(|(w, q)| format!("q{w} -> q{q}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            if map.qubits() <= MAX_CHECK_QUBITS {
+                let distance = routed.restore_layout(&map).distance(&unitary);
+                if distance > 1e-9 {
+                    return Err(miette!(
+                        "Routed circuit does not match unitary (deviation {distance:e})"
+                    ));
+                }
+                println!("Routing check: unitary matches up to layout (deviation {distance:e})\n");
+            }
+            println!(
+                "Routed term:\n{}\n",
+                routed.circuit.to_command().to_doc().pretty(60)
+            );
+        }
+    }
     println!("Unitary:");
     for x in unitary.row_iter() {
         println!(
@@ -58,6 +528,13 @@ fn parse_and_check(src: &str) -> Result<()> {
 fn main() -> Result<()> {
     let args: Args = clap::Parser::parse();
 
+    budget::set_limit(args.memory_limit.saturating_mul(1 << 20));
+    parallel::set_threads(
+        args.threads
+            .or_else(|| std::thread::available_parallelism().ok().map(usize::from))
+            .unwrap_or(1),
+    );
+
     let src = if let Some(path) = &args.file {
         std::fs::read_to_string(path).unwrap()
     } else {
@@ -66,7 +543,7 @@ fn main() -> Result<()> {
         s
     };
 
-    parse_and_check(&src).map_err(|e| e.with_source_code(src))?;
+    parse_and_check(&src, &args).map_err(|e| e.with_source_code(src))?;
 
     Ok(())
 }
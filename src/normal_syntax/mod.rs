@@ -9,13 +9,20 @@ pub use term::TermN;
 pub mod pattern;
 pub use pattern::PatternN;
 
+pub mod operator;
+pub use operator::Operator;
+
+pub mod simplify;
+
+pub mod rules;
+
 use crate::{
     normal_syntax::term::AtomN,
     typed_syntax::{PatternType, TermType},
 };
 
 /// Trait for objects that can built with compositions, tensors, or from an `AtomN`.
-pub trait Buildable {
+pub trait Buildable: Clone + 'static {
     /// Build a composition object from a sequence of subobjects and a given type.
     /// Subobjects should be given in diagrammatic order, not function composition order.
     fn comp(iter: impl DoubleEndedIterator<Item = Self>, ty: &TermType) -> Self;
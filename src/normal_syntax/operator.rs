@@ -0,0 +1,297 @@
+//! Structured linear operators built from normal-form terms.
+//!
+//! An `Operator` keeps the structure of the term it was built from: tensors are kept as
+//! lazy Kronecker products, and an "if let" is kept as its body together with the isometry
+//! of its pattern. Operators are applied to matrices whose columns are state vectors, one
+//! factor at a time, so a factor acting on k of n qubits costs O(2^(n + k)) per column
+//! rather than the O(4^n) of multiplying by its dense Kronecker product. In particular the
+//! unitary of a term on n qubits is built in O(4^n) per layer of the term.
+//! Independent columns are computed in parallel, as described in `crate::parallel`.
+//!
+//! Qubit 0 corresponds to the most significant bit of a basis-state index, matching the
+//! ordering used by `kron`.
+
+use std::f64::consts::PI;
+
+use faer::Mat;
+use num_complex::Complex;
+
+use crate::{
+    budget::{self, TooLargeError},
+    normal_syntax::{PatternN, TermN, term::AtomN},
+    parallel::{build_columns, identity_columns},
+};
+
+/// A linear map from `inputs()` qubits to `outputs()` qubits.
+#[derive(Clone, Debug)]
+pub enum Operator {
+    /// The identity on the given number of qubits
+    Identity(usize),
+    /// A global phase, on no qubits
+    Phase(Complex<f64>),
+    /// A dense matrix, with rows indexed by outputs and columns by inputs
+    Dense {
+        /// Number of input qubits
+        inputs: usize,
+        /// Number of output qubits
+        outputs: usize,
+        /// The matrix
+        matrix: Mat<Complex<f64>>,
+    },
+    /// A product of operators, applied in the order given.
+    /// The number of qubits is used when there are no factors.
+    Product(Vec<Operator>, usize),
+    /// A Kronecker product, with the first factor on the most significant qubits
+    Kron(Vec<Operator>),
+    /// The unitary "proj + inj * inner * inj^dagger" of an "if let", where `inj` is the
+    /// isometry of its pattern and `proj` the projection onto the complement of its image
+    IfLet {
+        /// Isometry of the pattern
+        inj: Box<Operator>,
+        /// Operator of the body
+        inner: Box<Operator>,
+    },
+}
+
+impl Operator {
+    /// Returns the number of qubits this operator takes as input.
+    pub fn inputs(&self) -> usize {
+        match self {
+            Operator::Identity(n) => *n,
+            Operator::Product(factors, n) => factors.first().map_or(*n, Operator::inputs),
+            Operator::Phase(_) => 0,
+            Operator::Dense { inputs, .. } => *inputs,
+            Operator::Kron(factors) => factors.iter().map(Operator::inputs).sum(),
+            Operator::IfLet { inj, .. } => inj.outputs(),
+        }
+    }
+
+    /// Returns the number of qubits this operator outputs.
+    pub fn outputs(&self) -> usize {
+        match self {
+            Operator::Identity(n) => *n,
+            Operator::Product(factors, n) => factors.last().map_or(*n, Operator::outputs),
+            Operator::Phase(_) => 0,
+            Operator::Dense { outputs, .. } => *outputs,
+            Operator::Kron(factors) => factors.iter().map(Operator::outputs).sum(),
+            Operator::IfLet { inj, .. } => inj.outputs(),
+        }
+    }
+
+    /// Apply this operator to each column of `state`, a matrix with 2^inputs rows.
+    pub fn apply(&self, state: &Mat<Complex<f64>>) -> Mat<Complex<f64>> {
+        self.apply_dir(state, false)
+    }
+
+    /// Apply the adjoint of this operator to each column of `state`, a matrix with
+    /// 2^outputs rows.
+    pub fn apply_adjoint(&self, state: &Mat<Complex<f64>>) -> Mat<Complex<f64>> {
+        self.apply_dir(state, true)
+    }
+
+    /// Build the dense matrix of this operator, by applying it to the identity.
+    /// The columns are computed in parallel.
+    ///
+    /// Returns an error if the matrix does not fit in the memory limit of `crate::budget`.
+    pub fn to_matrix(&self) -> Result<Mat<Complex<f64>>, TooLargeError> {
+        budget::check_fits(self.inputs().max(self.outputs()))?;
+        let size = 1 << self.inputs();
+        Ok(build_columns(1 << self.outputs(), size, |cols| {
+            self.apply(&identity_columns(size, cols))
+        }))
+    }
+
+    fn apply_dir(&self, state: &Mat<Complex<f64>>, adjoint: bool) -> Mat<Complex<f64>> {
+        match self {
+            Operator::Identity(_) => state.clone(),
+            Operator::Phase(c) => {
+                let c = if adjoint { c.conj() } else { *c };
+                Mat::from_fn(state.nrows(), state.ncols(), |i, j| state[(i, j)] * c)
+            }
+            Operator::Dense { matrix, .. } => {
+                if adjoint {
+                    matrix.adjoint() * state
+                } else {
+                    matrix * state
+                }
+            }
+            Operator::Product(factors, _) => {
+                let mut state = state.clone();
+                if adjoint {
+                    for f in factors.iter().rev() {
+                        state = f.apply_dir(&state, true);
+                    }
+                } else {
+                    for f in factors {
+                        state = f.apply_dir(&state, false);
+                    }
+                }
+                state
+            }
+            Operator::Kron(factors) => {
+                let size = |f: &Operator, into: bool| {
+                    if into == adjoint {
+                        f.outputs()
+                    } else {
+                        f.inputs()
+                    }
+                };
+                // Qubits before the current factor have been mapped, qubits after have not.
+                let mut state = state.clone();
+                let mut hi = 0;
+                let mut lo: usize = factors.iter().map(|f| size(f, true)).sum();
+                for f in factors {
+                    lo -= size(f, true);
+                    state = apply_block(&state, hi, lo, f, adjoint);
+                    hi += size(f, false);
+                }
+                state
+            }
+            Operator::IfLet { inj, inner } => {
+                // proj + inj u inj^dagger = id + inj (u - id) inj^dagger
+                let inside = inj.apply_adjoint(state);
+                let mapped = inner.apply_dir(&inside, adjoint) - &inside;
+                state + inj.apply(&mapped)
+            }
+        }
+    }
+}
+
+/// Apply `op` (or its adjoint) to the qubits of `state` between the `hi` most significant and
+/// the `lo` least significant qubits.
+fn apply_block(
+    state: &Mat<Complex<f64>>,
+    hi: usize,
+    lo: usize,
+    op: &Operator,
+    adjoint: bool,
+) -> Mat<Complex<f64>> {
+    if hi == 0 && lo == 0 {
+        return op.apply_dir(state, adjoint);
+    }
+    let (from, to) = if adjoint {
+        (op.outputs(), op.inputs())
+    } else {
+        (op.inputs(), op.outputs())
+    };
+    let (his, los, cols) = (1 << hi, 1 << lo, state.ncols());
+    // Gather the block into the rows of a matrix, with one column for each choice of the
+    // other qubits and column of the state. These columns are mapped in parallel.
+    let mapped = build_columns(1 << to, his * los * cols, |range| {
+        let gathered = Mat::from_fn(1 << from, range.len(), |r, c| {
+            let c = range.start + c;
+            let (hl, j) = (c / cols, c % cols);
+            let (h, l) = (hl / los, hl % los);
+            state[((((h << from) | r) << lo) | l, j)]
+        });
+        op.apply_dir(&gathered, adjoint)
+    });
+    Mat::from_fn(his << to << lo, cols, |row, j| {
+        let l = row % los;
+        let r = (row >> lo) % (1 << to);
+        let h = row >> lo >> to;
+        mapped[(r, (h * los + l) * cols + j)]
+    })
+}
+
+impl TermN {
+    /// Build the structured operator of this normal-form term.
+    /// Returns an error if the unitary of an opaque gate or power in the term does not fit in
+    /// the memory limit.
+    pub fn to_operator(&self) -> Result<Operator, TooLargeError> {
+        Ok(match self {
+            TermN::Comp(terms, ty) => Operator::Product(
+                terms
+                    .iter()
+                    .map(TermN::to_operator)
+                    .collect::<Result<_, _>>()?,
+                ty.0,
+            ),
+            TermN::Tensor(terms) => Operator::Kron(
+                terms
+                    .iter()
+                    .map(TermN::to_operator)
+                    .collect::<Result<_, _>>()?,
+            ),
+            TermN::Atom(atom) => atom.to_operator()?,
+        })
+    }
+}
+
+impl AtomN {
+    /// Build the structured operator of this normal-form atom.
+    pub fn to_operator(&self) -> Result<Operator, TooLargeError> {
+        Ok(match self {
+            AtomN::Phase(angle) => Operator::Phase(Complex::cis(angle * PI)),
+            AtomN::IfLet(pattern, inner, _) => Operator::IfLet {
+                inj: Box::new(pattern.to_operator()?),
+                inner: Box::new(inner.to_operator()?),
+            },
+            AtomN::Gate(gate) => {
+                let qubits = gate.get_type().0;
+                Operator::Dense {
+                    inputs: qubits,
+                    outputs: qubits,
+                    matrix: gate.unitary()?.clone(),
+                }
+            }
+            AtomN::Power(power) => {
+                let qubits = power.base().get_type().0;
+                Operator::Dense {
+                    inputs: qubits,
+                    outputs: qubits,
+                    matrix: power.unitary()?,
+                }
+            }
+        })
+    }
+}
+
+impl PatternN {
+    /// Build the structured operator of the isometry of this normal-form pattern.
+    /// A pattern of type "qm < qn" gives an operator from m qubits to n qubits.
+    pub fn to_operator(&self) -> Result<Operator, TooLargeError> {
+        Ok(match self {
+            PatternN::Comp(patterns, ty) => {
+                // The first pattern of a composition is applied last.
+                Operator::Product(
+                    patterns
+                        .iter()
+                        .rev()
+                        .map(PatternN::to_operator)
+                        .collect::<Result<_, _>>()?,
+                    ty.0,
+                )
+            }
+            PatternN::Tensor(patterns) => Operator::Kron(
+                patterns
+                    .iter()
+                    .map(PatternN::to_operator)
+                    .collect::<Result<_, _>>()?,
+            ),
+            PatternN::Ket(state) => Operator::Dense {
+                inputs: 0,
+                outputs: 1,
+                matrix: state.to_state(),
+            },
+            PatternN::Unitary(atom) => atom.to_operator()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matrix_over_memory_limit_is_an_error() {
+        // The matrix on 20 qubits needs 32TiB, over the default limit.
+        let err = Operator::Identity(20).to_matrix().unwrap_err();
+        assert_eq!(err.qubits, 20);
+        assert_eq!(
+            err.to_string(),
+            "Unitary on 20 qubits needs 32.0TiB of memory, over the limit of 1.0GiB."
+        );
+        assert!(Operator::Identity(2).to_matrix().is_ok());
+    }
+}
@@ -4,6 +4,7 @@ use faer::Mat;
 use num_complex::Complex;
 
 use crate::{
+    budget::TooLargeError,
     ket::{CompKetState, KetState},
     normal_syntax::term::AtomN,
     typed_syntax::{PatternT, PatternType, TermT, TermType},
@@ -22,46 +23,19 @@ pub enum PatternN {
     Unitary(Box<AtomN>),
 }
 
+/// The isometry and projector of a pattern, as returned by `PatternN::to_inj_and_proj`.
+pub type InjAndProj = (Mat<Complex<f64>>, Mat<Complex<f64>>);
+
 impl PatternN {
     /// Convert a normal-form pattern of type qm < qn to an m x n isometry matrix `i`
     /// and an n x n projector `p` such that
     /// p + ii^dagger = id
-    pub fn to_inj_and_proj(&self) -> (Mat<Complex<f64>>, Mat<Complex<f64>>) {
-        match self {
-            PatternN::Comp(patterns, ty) => {
-                let mut patterns_iter = patterns.iter().map(PatternN::to_inj_and_proj);
-                if let Some(i) = patterns_iter.next() {
-                    patterns_iter.fold(i, |(i1, p1), (i2, p2)| {
-                        (&i1 * i2, p1 + &i1 * p2 * i1.adjoint())
-                    })
-                } else {
-                    (
-                        Mat::identity(1 << ty.0, 1 << ty.0),
-                        Mat::zeros(1 << ty.0, 1 << ty.0),
-                    )
-                }
-            }
-            PatternN::Tensor(patterns) => {
-                let mut patterns_iter = patterns.iter().map(PatternN::to_inj_and_proj);
-                let i = patterns_iter.next().unwrap();
-                patterns_iter.fold(i, |(i1, p1), (i2, p2)| {
-                    (
-                        i1.kron(i2),
-                        p1.kron(Mat::<Complex<f64>>::identity(p2.nrows(), p2.nrows()))
-                            + (&i1 * i1.adjoint()).kron(p2),
-                    )
-                })
-            }
-            PatternN::Ket(state) => {
-                let m = state.to_state();
-                let cm = state.compl().to_state();
-                (m, cm.as_ref() * cm.adjoint())
-            }
-            PatternN::Unitary(inner) => {
-                let size = inner.get_type().0;
-                (inner.to_unitary(), Mat::zeros(1 << size, 1 << size))
-            }
-        }
+    /// Returns an error if the projector does not fit in the memory limit of `crate::budget`.
+    pub fn to_inj_and_proj(&self) -> Result<InjAndProj, TooLargeError> {
+        let inj = self.to_operator()?.to_matrix()?;
+        let size = inj.nrows();
+        let proj = Mat::<Complex<f64>>::identity(size, size) - &inj * inj.adjoint();
+        Ok((inj, proj))
     }
 
     /// Return a `PatternT` which is the "quotation" of this normal-form pattern.
@@ -84,23 +58,19 @@ impl PatternN {
     }
 
     fn squash_comp(mut self, acc: &mut Vec<PatternN>) {
+        self.squash();
         if let PatternN::Comp(patterns, _) = self {
-            for p in patterns {
-                p.squash_comp(acc);
-            }
+            acc.extend(patterns);
         } else {
-            self.squash();
             acc.push(self);
         }
     }
 
     fn squash_tensor(mut self, acc: &mut Vec<PatternN>) {
+        self.squash();
         if let PatternN::Tensor(patterns) = self {
-            for p in patterns {
-                p.squash_tensor(acc);
-            }
+            acc.extend(patterns);
         } else {
-            self.squash();
             acc.push(self);
         }
     }
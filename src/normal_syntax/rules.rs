@@ -0,0 +1,169 @@
+//! User-defined rewrite rules on normal-form terms.
+//!
+//! A rule "rule lhs => rhs," equates two terms of the same type. Rules are evaluated to
+//! squashed normal-form terms when declared, and are matched against squashed terms, so that
+//! a rule whose left-hand side is a composition (or tensor) matches any consecutive run of
+//! factors in a composition (or tensor), regardless of how it was bracketed.
+
+use crate::normal_syntax::{TermN, term::AtomN};
+
+/// Maximum number of passes made over a term when rewriting, so that rules which can be
+/// applied forever (e.g. "rule A => B," and "rule B => A,") do not loop.
+const MAX_PASSES: usize = 64;
+
+/// A rewrite rule between two squashed normal-form terms with the same unitary.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    /// Term to match
+    pub lhs: TermN,
+    /// Term to replace matches with
+    pub rhs: TermN,
+}
+
+/// Replace every consecutive run of `lhs` in `terms` by `rhs`, returning the number of
+/// replacements made.
+fn replace_run(terms: &mut Vec<TermN>, lhs: &[TermN], rhs: &[TermN]) -> usize {
+    if lhs.is_empty() || terms.len() < lhs.len() {
+        return 0;
+    }
+    let mut count = 0;
+    let mut i = 0;
+    while i + lhs.len() <= terms.len() {
+        if terms[i..i + lhs.len()] == *lhs {
+            terms.splice(i..i + lhs.len(), rhs.iter().cloned());
+            i += rhs.len();
+            count += 1;
+        } else {
+            i += 1;
+        }
+    }
+    count
+}
+
+impl TermN {
+    /// Rewrite this term with the given rules until none of them apply, returning the number
+    /// of rewrites made. The term should be squashed.
+    pub fn apply_rules(&mut self, rules: &[Rule]) -> usize {
+        let mut count = 0;
+        for _ in 0..MAX_PASSES {
+            let rewrites = self.apply_rules_once(rules);
+            if rewrites == 0 {
+                break;
+            }
+            self.squash();
+            count += rewrites;
+        }
+        count
+    }
+
+    /// Make one bottom-up pass over this term, applying each rule where it matches.
+    fn apply_rules_once(&mut self, rules: &[Rule]) -> usize {
+        let mut count = match self {
+            TermN::Comp(terms, _) | TermN::Tensor(terms) => {
+                terms.iter_mut().map(|t| t.apply_rules_once(rules)).sum()
+            }
+            TermN::Atom(atom) => atom.apply_rules_once(rules),
+        };
+        for rule in rules {
+            if *self == rule.lhs {
+                *self = rule.rhs.clone();
+                return count + 1;
+            }
+            count += match (&mut *self, &rule.lhs) {
+                (TermN::Comp(terms, _), TermN::Comp(lhs, _)) => {
+                    let rhs = match &rule.rhs {
+                        TermN::Comp(rhs, _) => rhs.as_slice(),
+                        rhs => std::slice::from_ref(rhs),
+                    };
+                    replace_run(terms, lhs, rhs)
+                }
+                (TermN::Tensor(terms), TermN::Tensor(lhs)) => {
+                    let rhs = match &rule.rhs {
+                        TermN::Tensor(rhs) => rhs.as_slice(),
+                        rhs => std::slice::from_ref(rhs),
+                    };
+                    replace_run(terms, lhs, rhs)
+                }
+                _ => 0,
+            };
+        }
+        count
+    }
+}
+
+impl AtomN {
+    fn apply_rules_once(&mut self, rules: &[Rule]) -> usize {
+        match self {
+            AtomN::Phase(_) | AtomN::Gate(_) | AtomN::Power(_) => 0,
+            AtomN::IfLet(_, inner, _) => inner.apply_rules_once(rules),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use winnow::{LocatingSlice, Parser};
+
+    use crate::{
+        command::{Command, check_source},
+        normal_syntax::TermN,
+        text::{HasParser, ToDoc},
+        typecheck::TypeCheckError,
+    };
+
+    /// Evaluate a term and apply the rules of its program, returning the number of rewrites
+    /// and the rewritten term.
+    fn rewrite(src: &str) -> (usize, String) {
+        let (env, term) = check_source(src);
+        let mut evalled: TermN = term.eval();
+        evalled.squash();
+        let count = evalled.apply_rules(env.rules());
+        (
+            count,
+            evalled.quote().to_raw().to_doc().pretty(80).to_string(),
+        )
+    }
+
+    fn check_error(src: &str) -> TypeCheckError<std::ops::Range<usize>> {
+        let Err(err) = Command::parser
+            .parse(LocatingSlice::new(src))
+            .expect("Program should parse")
+            .check()
+        else {
+            panic!("Program should not typecheck");
+        };
+        err
+    }
+
+    #[test]
+    fn rule_matches_runs_regardless_of_bracketing() {
+        let src = "gate X = if let |-> then -1, rule X ; X => id, \
+                   if let |1> then -1 ; (X ; (X ; X))";
+        assert_eq!(
+            rewrite(src),
+            (1, "if let |1> then -1; if let |-> then -1".to_owned())
+        );
+    }
+
+    #[test]
+    fn rules_which_undo_each_other_terminate() {
+        let src = "rule if let |1> then -1 => (-1 x id) ; if let |0> then -1, \
+                   rule (-1 x id) ; if let |0> then -1 => if let |1> then -1, \
+                   if let |1> then -1";
+        let (count, _) = rewrite(src);
+        // One rule applies in each pass, until the passes run out.
+        assert_eq!(count, super::MAX_PASSES);
+    }
+
+    #[test]
+    fn unsound_and_identity_rules_are_rejected() {
+        assert!(matches!(
+            check_error("rule if let |1> then -1 => if let |0> then -1, id"),
+            TypeCheckError::UnsoundRule { .. }
+        ));
+        assert!(matches!(
+            check_error("rule id => if let |0> then ph(2pi), id"),
+            TypeCheckError::IdentityRule { .. }
+        ));
+    }
+}
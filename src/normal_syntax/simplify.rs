@@ -0,0 +1,235 @@
+//! Term rewriting on normal-form terms.
+//!
+//! Simplification rewrites a normal-form term to a smaller term with the same unitary by
+//! - removing identities, including "if let" statements whose body is an identity,
+//! - merging consecutive phases in a composition,
+//! - cancelling a term followed by its inverse, and
+//! - combining consecutive "if let" statements with the same pattern into one.
+
+use crate::{
+    normal_syntax::{PatternN, TermN, term::AtomN},
+    typed_syntax::TermType,
+};
+
+/// Tolerance used when deciding whether a phase is zero.
+const PHASE_TOLERANCE: f64 = 1e-12;
+
+/// Returns true if the given angle, divided by pi, is a multiple of 2.
+fn is_zero_phase(angle: f64) -> bool {
+    let r = angle.rem_euclid(2.0);
+    r < PHASE_TOLERANCE || 2.0 - r < PHASE_TOLERANCE
+}
+
+impl TermN {
+    /// Returns the type of this normal-form term.
+    pub(crate) fn get_type(&self) -> TermType {
+        match self {
+            TermN::Comp(_, ty) => *ty,
+            TermN::Tensor(terms) => terms.iter().map(TermN::get_type).sum(),
+            TermN::Atom(atom) => atom.get_type(),
+        }
+    }
+
+    /// Returns true if this term is syntactically an identity.
+    pub fn is_identity(&self) -> bool {
+        match self {
+            TermN::Comp(terms, _) => terms.iter().all(TermN::is_identity),
+            TermN::Tensor(terms) => terms.iter().all(TermN::is_identity),
+            TermN::Atom(atom) => atom.is_identity(),
+        }
+    }
+
+    /// Returns the inverse of this term.
+    pub fn inverse(&self) -> TermN {
+        match self {
+            TermN::Comp(terms, ty) => {
+                TermN::Comp(terms.iter().rev().map(TermN::inverse).collect(), *ty)
+            }
+            TermN::Tensor(terms) => TermN::Tensor(terms.iter().map(TermN::inverse).collect()),
+            TermN::Atom(atom) => TermN::Atom(atom.inverse()),
+        }
+    }
+
+    /// Simplify this normal-form term by rewriting, as described in the module documentation.
+    /// Compositions and tensors are squashed first.
+    pub fn simplify(&mut self) {
+        self.squash();
+        self.rewrite();
+    }
+
+    fn rewrite(&mut self) {
+        match self {
+            TermN::Comp(terms, _) => {
+                let mut stack: Vec<TermN> = vec![];
+                for mut t in std::mem::take(terms) {
+                    t.rewrite();
+                    if let TermN::Comp(inner, _) = t {
+                        // Rewriting may produce compositions, which are already simplified.
+                        for s in inner {
+                            push_comp(&mut stack, s);
+                        }
+                    } else {
+                        push_comp(&mut stack, t);
+                    }
+                }
+                *terms = stack;
+                if terms.len() == 1 {
+                    *self = terms.pop().unwrap();
+                }
+            }
+            TermN::Tensor(terms) => {
+                for mut t in std::mem::take(terms) {
+                    t.rewrite();
+                    if let TermN::Tensor(inner) = t {
+                        terms.extend(inner);
+                    } else {
+                        terms.push(t);
+                    }
+                }
+                if terms.iter().all(TermN::is_identity) {
+                    *self = TermN::Comp(vec![], self.get_type());
+                    return;
+                }
+                terms.retain(|t| t.get_type().0 != 0 || !t.is_identity());
+                if terms.len() == 1 {
+                    *self = terms.pop().unwrap();
+                }
+            }
+            TermN::Atom(atom) => {
+                atom.rewrite();
+                if atom.is_identity() {
+                    *self = TermN::Comp(vec![], atom.get_type());
+                }
+            }
+        }
+    }
+}
+
+/// Push a simplified term onto the end of a simplified composition, rewriting the end of the
+/// composition where possible.
+fn push_comp(stack: &mut Vec<TermN>, t: TermN) {
+    if t.is_identity() {
+        return;
+    }
+    let Some(top) = stack.pop() else {
+        stack.push(t);
+        return;
+    };
+    match (top, t) {
+        (TermN::Atom(AtomN::Phase(a)), TermN::Atom(AtomN::Phase(b))) => {
+            let angle = (a + b).rem_euclid(2.0);
+            if !is_zero_phase(angle) {
+                stack.push(TermN::Atom(AtomN::Phase(angle)));
+            }
+        }
+        (TermN::Atom(AtomN::IfLet(p1, inner1, ty)), TermN::Atom(AtomN::IfLet(p2, inner2, _)))
+            if p1 == p2 =>
+        {
+            let inner_ty = inner1.get_type();
+            let mut inner = TermN::Comp(vec![*inner1, *inner2], inner_ty);
+            inner.rewrite();
+            if !inner.is_identity() {
+                stack.push(TermN::Atom(AtomN::IfLet(p1, Box::new(inner), ty)));
+            }
+        }
+        (top, t) => {
+            if top != t.inverse() {
+                stack.push(top);
+                stack.push(t);
+            }
+        }
+    }
+}
+
+impl AtomN {
+    /// Returns true if this atom is syntactically an identity.
+    pub fn is_identity(&self) -> bool {
+        match self {
+            AtomN::Phase(angle) => is_zero_phase(*angle),
+            AtomN::IfLet(_, inner, _) => inner.is_identity(),
+            AtomN::Gate(gate) => gate.is_identity(),
+            AtomN::Power(power) => power.exponent == 0.0,
+        }
+    }
+
+    /// Returns the inverse of this atom.
+    pub fn inverse(&self) -> AtomN {
+        match self {
+            AtomN::Phase(angle) => AtomN::Phase(-angle),
+            AtomN::IfLet(pattern, inner, ty) => {
+                AtomN::IfLet(pattern.clone(), Box::new(inner.inverse()), *ty)
+            }
+            AtomN::Gate(gate) => AtomN::Gate(gate.inverse()),
+            AtomN::Power(power) => AtomN::Power(power.inverse()),
+        }
+    }
+
+    fn rewrite(&mut self) {
+        if let AtomN::IfLet(pattern, inner, _) = self {
+            pattern.rewrite();
+            inner.rewrite();
+        }
+    }
+}
+
+impl PatternN {
+    fn rewrite(&mut self) {
+        match self {
+            PatternN::Comp(patterns, _) | PatternN::Tensor(patterns) => {
+                for p in patterns {
+                    p.rewrite();
+                }
+            }
+            PatternN::Ket(_) => {}
+            PatternN::Unitary(atom) => atom.rewrite(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{command::check_source, normal_syntax::TermN, text::ToDoc};
+
+    /// Evaluate and simplify a term, checking that its unitary is unchanged, and print it.
+    fn simplify(src: &str) -> String {
+        let (_, term) = check_source(src);
+        let mut evalled: TermN = term.eval();
+        let unitary = evalled.to_unitary().unwrap();
+        evalled.simplify();
+        let deviation = (evalled.to_unitary().unwrap() - unitary).norm_max();
+        assert!(deviation < 1e-9, "Simplifying {src} changed its unitary");
+        evalled.quote().to_raw().to_doc().pretty(80).to_string()
+    }
+
+    #[test]
+    fn merges_phases() {
+        assert_eq!(simplify("ph(0.25pi) ; ph(0.5pi)"), "ph(0.75pi)");
+    }
+
+    #[test]
+    fn combines_if_lets_with_the_same_pattern() {
+        assert_eq!(
+            simplify("if let |1> then ph(0.5pi) ; if let |1> then ph(0.25pi)"),
+            "if let |1> then ph(0.75pi)"
+        );
+        assert_eq!(
+            simplify("if let |1> then ph(0.5pi) ; if let |1> then ph(1.5pi)"),
+            "id"
+        );
+    }
+
+    #[test]
+    fn removes_identities() {
+        assert_eq!(
+            simplify("id ; if let |0> then (ph(0.2pi) ; ph(1.8pi)) ; if let |1> then -1 ; id"),
+            "if let |1> then -1"
+        );
+    }
+
+    #[test]
+    fn cancels_a_term_followed_by_its_inverse() {
+        let src = "gate X = if let |-> then -1, gate Z = if let |1> then -1, \
+                   (X ; Z)^0.5 ; ((X ; Z)^0.5)^-1";
+        assert_eq!(simplify(src), "id");
+    }
+}
@@ -1,13 +1,16 @@
 //! Normal-form terms.
 
-use std::f64::consts::PI;
+use std::{cell::OnceCell, rc::Rc};
 
-use faer::{Mat, mat};
+use faer::Mat;
 use num_complex::Complex;
 
 use crate::{
+    budget::TooLargeError,
     normal_syntax::PatternN,
     phase::Phase,
+    power::{Exponent, Spectrum},
+    text::Name,
     typed_syntax::{TermT, TermType},
 };
 
@@ -29,28 +32,225 @@ pub enum AtomN {
     Phase(f64),
     /// An "if let" statement with given pattern, body term, and type
     IfLet(PatternN, Box<TermN>, TermType),
+    /// A gate kept opaque during evaluation
+    Gate(GateN),
+    /// A power of a term with compositions, computed numerically
+    Power(PowerN),
+}
+
+/// A gate kept opaque during evaluation, with its evaluated definition.
+/// Copies of a gate, and its inverse, share its definition and unitary. The inverse definition
+/// and the unitaries are computed when first needed, so inverting a gate is cheap even when its
+/// definition contains further opaque gates.
+#[derive(Clone, Debug)]
+pub struct GateN {
+    /// Name of the gate
+    pub name: Name,
+    /// Multiplier applied to the phases of the definition,
+    /// e.g. -1 for an inverse and 0.5 for a square root
+    pub phase_mul: f64,
+    /// Typed definition of the gate, used when quoting
+    def: Rc<TermT>,
+    /// Whether this is the inverse of the gate the evaluated definition was created for
+    inverted: bool,
+    /// Evaluated definitions and unitaries shared with the inverse gate
+    shared: Rc<GateShared>,
+}
+
+/// Data shared between copies of a gate and its inverse, indexed by `GateN::inverted`.
+#[derive(Debug, Default)]
+struct GateShared {
+    /// Evaluated definitions, with the phase multiplier applied
+    bodies: [OnceCell<TermN>; 2],
+    /// Unitaries of the evaluated definitions
+    unitaries: [OnceCell<Mat<Complex<f64>>>; 2],
+    /// Whether the gate is the identity, which is also whether its inverse is
+    identity: OnceCell<bool>,
+}
+
+impl PartialEq for GateN {
+    /// Gates are identified by their definition rather than their name, as a name can be
+    /// redefined.
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.def, &other.def) && self.phase_mul == other.phase_mul
+    }
+}
+
+impl GateN {
+    pub(crate) fn new(name: Name, phase_mul: f64, def: Rc<TermT>, body: TermN) -> Self {
+        let shared = GateShared::default();
+        let _ = shared.bodies[0].set(body);
+        GateN {
+            name,
+            phase_mul,
+            def,
+            inverted: false,
+            shared: Rc::new(shared),
+        }
+    }
+
+    fn side(&self) -> usize {
+        usize::from(self.inverted)
+    }
+
+    /// Returns the evaluated definition of this gate.
+    pub fn body(&self) -> &TermN {
+        let [forward, backward] = &self.shared.bodies;
+        let (body, other) = if self.inverted {
+            (backward, forward)
+        } else {
+            (forward, backward)
+        };
+        body.get_or_init(|| {
+            other
+                .get()
+                .expect("One side of a gate is always evaluated")
+                .inverse()
+        })
+    }
+
+    /// Returns the type of this gate, without evaluating the definition of its inverse.
+    pub fn get_type(&self) -> TermType {
+        let [forward, backward] = &self.shared.bodies;
+        forward
+            .get()
+            .or(backward.get())
+            .expect("One side of a gate is always evaluated")
+            .get_type()
+    }
+
+    /// Returns the unitary of this gate, computing it if it has not been already.
+    pub fn unitary(&self) -> Result<&Mat<Complex<f64>>, TooLargeError> {
+        let cell = &self.shared.unitaries[self.side()];
+        if let Some(unitary) = cell.get() {
+            return Ok(unitary);
+        }
+        let unitary = match self.shared.unitaries[1 - self.side()].get() {
+            Some(unitary) => unitary.adjoint().to_owned(),
+            None => self.body().to_unitary()?,
+        };
+        Ok(cell.get_or_init(|| unitary))
+    }
+
+    /// Returns true if this gate is the identity, checking its definition only once.
+    pub fn is_identity(&self) -> bool {
+        *self
+            .shared
+            .identity
+            .get_or_init(|| self.body().is_identity())
+    }
+
+    /// Returns the inverse of this gate.
+    pub fn inverse(&self) -> GateN {
+        GateN {
+            name: self.name.clone(),
+            phase_mul: -self.phase_mul,
+            def: self.def.clone(),
+            inverted: !self.inverted,
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Quote this gate, as the named gate with the inverses and square roots given by the
+    /// phase multiplier, or as a power of the named gate if the multiplier is not of the form
+    /// ±2^-k.
+    fn quote(&self) -> TermT {
+        let gate = TermT::Gate {
+            name: self.name.clone(),
+            def: self.def.clone(),
+        };
+        let mut mul = self.phase_mul.abs();
+        let mut roots = 0;
+        while mul > 0.0 && mul < 1.0 {
+            mul *= 2.0;
+            roots += 1;
+        }
+        if mul != 1.0 {
+            return TermT::Power {
+                inner: Rc::new(gate),
+                exponent: Exponent::from_value(self.phase_mul),
+                rootable: true,
+            };
+        }
+        let mut term = gate;
+        for _ in 0..roots {
+            term = TermT::Sqrt(Box::new(term));
+        }
+        if self.phase_mul < 0.0 {
+            term = TermT::Inverse(Box::new(term));
+        }
+        term
+    }
+}
+
+/// A power of a term with compositions, kept opaque and computed numerically from the
+/// eigendecomposition of the unitary of its base, as described in `crate::power`.
+/// Copies of a power share the eigendecomposition, which is computed when first needed.
+#[derive(Clone, Debug)]
+pub struct PowerN {
+    /// Exponent of the power, with any phase multiplier applied
+    pub exponent: f64,
+    /// Typed base of the power
+    base: Rc<TermT>,
+    /// Eigendecomposition of the unitary of the base
+    spectrum: Rc<OnceCell<Spectrum>>,
+}
+
+impl PartialEq for PowerN {
+    fn eq(&self, other: &Self) -> bool {
+        self.exponent == other.exponent && self.base == other.base
+    }
+}
+
+impl PowerN {
+    pub(crate) fn new(base: Rc<TermT>, exponent: f64) -> Self {
+        PowerN {
+            exponent,
+            base,
+            spectrum: Rc::new(OnceCell::new()),
+        }
+    }
+
+    /// Returns the base of this power.
+    pub fn base(&self) -> &TermT {
+        &self.base
+    }
+
+    /// Returns the unitary of this power, computing the eigendecomposition of its base if it
+    /// has not been already.
+    pub fn unitary(&self) -> Result<Mat<Complex<f64>>, TooLargeError> {
+        if self.spectrum.get().is_none() {
+            let spectrum = Spectrum::new(&self.base.to_unitary()?);
+            let _ = self.spectrum.set(spectrum);
+        }
+        Ok(self.spectrum.get().unwrap().power(self.exponent))
+    }
+
+    /// Returns the inverse of this power.
+    pub fn inverse(&self) -> PowerN {
+        PowerN {
+            exponent: -self.exponent,
+            ..self.clone()
+        }
+    }
+
+    /// Quote this power as a power of its base.
+    pub(crate) fn quote(&self) -> TermT {
+        TermT::Power {
+            inner: self.base.clone(),
+            exponent: Exponent::from_value(self.exponent),
+            rootable: false,
+        }
+    }
 }
 
 impl TermN {
     /// Convert a normal-form term of type qn <-> qn to an n x n unitary matrix.
-    pub fn to_unitary(&self) -> Mat<Complex<f64>> {
-        match self {
-            TermN::Comp(terms, ty) => {
-                let mut terms_iter = terms.iter().map(TermN::to_unitary);
-                match terms_iter.next() {
-                    None => Mat::identity(1 << ty.0, 1 << ty.0),
-                    Some(u) => terms_iter.fold(u, |x, y| y * x),
-                }
-            }
-            TermN::Tensor(terms) => {
-                let mut terms_iter = terms.iter().map(TermN::to_unitary);
-                match terms_iter.next() {
-                    None => Mat::identity(1, 1),
-                    Some(u) => terms_iter.fold(u, |x, y| x.kron(y)),
-                }
-            }
-            TermN::Atom(atom) => atom.to_unitary(),
-        }
+    /// The unitary is built through the structured operator of the term, so tensors and
+    /// "if let"s are never expanded to dense Kronecker products.
+    /// Returns an error if the unitary does not fit in the memory limit of `crate::budget`.
+    pub fn to_unitary(&self) -> Result<Mat<Complex<f64>>, TooLargeError> {
+        self.to_operator()?.to_matrix()
     }
 
     /// Return a `TermT` which is the "quotation" of this normal-form term.
@@ -70,23 +270,19 @@ impl TermN {
     }
 
     fn squash_comp(mut self, acc: &mut Vec<TermN>) {
+        self.squash();
         if let TermN::Comp(terms, _) = self {
-            for t in terms {
-                t.squash_comp(acc);
-            }
+            acc.extend(terms);
         } else {
-            self.squash();
             acc.push(self);
         }
     }
 
     fn squash_tensor(mut self, acc: &mut Vec<TermN>) {
+        self.squash();
         if let TermN::Tensor(terms) = self {
-            for t in terms {
-                t.squash_tensor(acc);
-            }
+            acc.extend(terms);
         } else {
-            self.squash();
             acc.push(self);
         }
     }
@@ -122,18 +318,17 @@ impl AtomN {
         match self {
             AtomN::Phase(_) => TermType(0),
             AtomN::IfLet(_, _, ty) => *ty,
+            AtomN::Gate(gate) => gate.get_type(),
+            AtomN::Power(power) => power.base.get_type(),
         }
     }
 
     /// Convert a normal-form atom of type qn <-> qn to an n x n unitary matrix.
-    pub fn to_unitary(&self) -> Mat<Complex<f64>> {
+    pub fn to_unitary(&self) -> Result<Mat<Complex<f64>>, TooLargeError> {
         match self {
-            AtomN::Phase(angle) => mat![[Complex::cis(angle * PI)]],
-            AtomN::IfLet(pattern, inner, _) => {
-                let (inj, proj) = pattern.to_inj_and_proj();
-                let u = inner.to_unitary();
-                proj + &inj * u * inj.adjoint()
-            }
+            AtomN::Gate(gate) => Ok(gate.unitary()?.clone()),
+            AtomN::Power(power) => power.unitary(),
+            _ => self.to_operator()?.to_matrix(),
         }
     }
 
@@ -144,6 +339,8 @@ impl AtomN {
                 pattern: pattern.quote(),
                 inner: Box::new(inner.quote()),
             },
+            AtomN::Gate(gate) => gate.quote(),
+            AtomN::Power(power) => power.quote(),
         }
     }
 
@@ -0,0 +1,93 @@
+//! Splitting the columns of dense matrices between threads.
+//!
+//! Matrices are always split into chunks of `CHUNK_COLUMNS` columns, whatever the number
+//! of threads, and each chunk is computed by a single call. Results therefore do not depend
+//! on the number of threads: running with one thread computes the same chunks in order.
+
+use std::{
+    cell::Cell,
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+use faer::Mat;
+use num_complex::Complex;
+
+/// Number of columns computed by each call of a chunk function.
+const CHUNK_COLUMNS: usize = 64;
+
+static THREADS: AtomicUsize = AtomicUsize::new(1);
+
+thread_local! {
+    /// Whether the current thread is a worker, in which case nested calls run sequentially.
+    static IN_WORKER: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Set the number of threads used to build unitaries and simulate circuits.
+/// A count of 0 is treated as 1.
+pub fn set_threads(threads: usize) {
+    THREADS.store(threads.max(1), Ordering::Relaxed);
+}
+
+/// Returns the number of threads used to build unitaries and simulate circuits.
+pub fn threads() -> usize {
+    THREADS.load(Ordering::Relaxed)
+}
+
+/// Build a matrix with `nrows` rows and `ncols` columns, where `chunk` computes the columns
+/// in the given range. Chunks are shared between `threads()` threads.
+pub(crate) fn build_columns(
+    nrows: usize,
+    ncols: usize,
+    chunk: impl Fn(Range<usize>) -> Mat<Complex<f64>> + Sync,
+) -> Mat<Complex<f64>> {
+    let chunks = ncols.div_ceil(CHUNK_COLUMNS);
+    let range = |c: usize| c * CHUNK_COLUMNS..((c + 1) * CHUNK_COLUMNS).min(ncols);
+    let workers = if IN_WORKER.get() {
+        1
+    } else {
+        threads().min(chunks)
+    };
+    let mut results: Vec<(usize, Mat<Complex<f64>>)> = if workers <= 1 {
+        (0..chunks).map(|c| (c, chunk(range(c)))).collect()
+    } else {
+        let next = AtomicUsize::new(0);
+        let work = || {
+            IN_WORKER.set(true);
+            let mut done = vec![];
+            loop {
+                let c = next.fetch_add(1, Ordering::Relaxed);
+                if c >= chunks {
+                    break;
+                }
+                done.push((c, chunk(range(c))));
+            }
+            IN_WORKER.set(false);
+            done
+        };
+        thread::scope(|s| {
+            let handles: Vec<_> = (1..workers).map(|_| s.spawn(work)).collect();
+            let mut results = work();
+            for h in handles {
+                results.extend(h.join().unwrap());
+            }
+            results
+        })
+    };
+    results.sort_by_key(|(c, _)| *c);
+    Mat::from_fn(nrows, ncols, |i, j| {
+        results[j / CHUNK_COLUMNS].1[(i, j % CHUNK_COLUMNS)]
+    })
+}
+
+/// Returns the columns of the `size` x `size` identity matrix in the given range.
+pub(crate) fn identity_columns(size: usize, cols: Range<usize>) -> Mat<Complex<f64>> {
+    Mat::from_fn(size, cols.len(), |i, j| {
+        if i == cols.start + j {
+            Complex::ONE
+        } else {
+            Complex::ZERO
+        }
+    })
+}
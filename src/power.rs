@@ -0,0 +1,188 @@
+//! Exponents of powers "t ^ r", and numerical powers of unitaries.
+//!
+//! Powers of terms without compositions are taken by scaling their phases, as for square
+//! roots, and integer powers of other terms are expanded to repeated compositions. Other
+//! powers are computed numerically from an eigendecomposition of the unitary of the term,
+//! using the principal branch: each eigenvalue is written as e^(i pi a) with
+//! -1 < a <= 1, and raised to e^(i pi a r). Eigenvalues within `BRANCH_TOLERANCE` of -1 are
+//! treated as exactly -1, so that for example the square root of a Pauli X has eigenvalues
+//! 1 and i.
+
+use std::f64::consts::PI;
+
+use faer::{Mat, Side};
+use num_complex::Complex;
+use pretty::RcDoc;
+use winnow::{
+    LocatingSlice, ModalResult, Parser,
+    ascii::{dec_int, dec_uint, float, multispace0},
+    combinator::{alt, delimited, separated_pair},
+};
+
+use crate::text::{HasParser, ToDoc};
+
+/// Distance, in angle divided by pi, from the branch cut at -1 within which eigenvalues are
+/// moved onto the positive side of the cut.
+const BRANCH_TOLERANCE: f64 = 1e-9;
+
+/// Angle, in radians, first used to separate the eigenvalues of a unitary.
+/// See `Spectrum::new`.
+const SEPARATION: f64 = 1.0;
+
+/// Step, in radians, between the separation angles tried in turn. This is the golden angle,
+/// so that the angles tried are spread out and never repeat.
+const SEPARATION_STEP: f64 = 2.399_963_229_728_653;
+
+/// Number of separation angles tried before settling for the most accurate decomposition.
+const SEPARATION_ATTEMPTS: usize = 8;
+
+/// Largest residual |u v - e v| accepted for an eigenvector v of u with eigenvalue e.
+const RESIDUAL_TOLERANCE: f64 = 1e-9;
+
+/// Largest denominator tried when writing an exponent as a fraction.
+const MAX_DENOMINATOR: u64 = 64;
+
+/// The exponent of a power "t ^ r".
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Exponent {
+    /// A fraction, e.g. "(1/3)", with non-zero denominator.
+    /// Integers, e.g. "3", have denominator 1.
+    Ratio(i64, u64),
+    /// A real number, e.g. "0.25"
+    Real(f64),
+}
+
+impl Exponent {
+    /// Construct an exponent from its value, as a fraction with a small denominator when this
+    /// represents the value exactly.
+    pub fn from_value(value: f64) -> Self {
+        (1..=MAX_DENOMINATOR)
+            .find_map(|d| {
+                let n = (value * d as f64).round();
+                (n.abs() < (1u64 << 53) as f64 && n / d as f64 == value)
+                    .then_some(Exponent::Ratio(n as i64, d))
+            })
+            .unwrap_or(Exponent::Real(value))
+    }
+
+    /// Returns the value of this exponent.
+    pub fn eval(&self) -> f64 {
+        match self {
+            Exponent::Ratio(n, d) => *n as f64 / *d as f64,
+            Exponent::Real(r) => *r,
+        }
+    }
+}
+
+impl ToDoc for Exponent {
+    fn to_doc(&self) -> RcDoc<'_> {
+        match self {
+            Exponent::Ratio(n, 1) => RcDoc::text(format!("{n}")),
+            Exponent::Ratio(n, d) => RcDoc::text(format!("({n}/{d})")),
+            Exponent::Real(r) => RcDoc::text(format!("{r}")),
+        }
+    }
+}
+
+impl HasParser for Exponent {
+    fn parser(input: &mut LocatingSlice<&str>) -> ModalResult<Self> {
+        let ratio = || {
+            separated_pair(
+                dec_int,
+                (multispace0, '/', multispace0),
+                dec_uint.verify(|d: &u64| *d != 0),
+            )
+            .map(|(n, d): (i64, u64)| Exponent::Ratio(n, d))
+        };
+        alt((
+            delimited(("(", multispace0), ratio(), (multispace0, ")")),
+            ratio(),
+            float.verify(|r: &f64| r.is_finite()).map(|r: f64| {
+                if r.fract() == 0.0 && r.abs() < (1u64 << 53) as f64 {
+                    Exponent::Ratio(r as i64, 1)
+                } else {
+                    Exponent::Real(r)
+                }
+            }),
+        ))
+        .parse_next(input)
+    }
+}
+
+/// Eigendecomposition of a unitary, from which its powers are computed.
+#[derive(Clone, Debug)]
+pub struct Spectrum {
+    /// Orthonormal eigenvectors, as columns
+    vectors: Mat<Complex<f64>>,
+    /// Angle of the eigenvalue of each eigenvector divided by pi, in (-1, 1]
+    angles: Vec<f64>,
+}
+
+impl Spectrum {
+    /// Compute the eigendecomposition of a unitary.
+    ///
+    /// For a separation angle s, the Hermitian matrix (e^(-is) u + e^(is) u^dagger) / 2 has
+    /// eigenvalue cos(a - s) for each eigenvalue e^(ia) of `u`, on the same eigenvectors.
+    /// Its orthonormal eigenvectors diagonalise `u` unless two distinct eigenvalues of `u`
+    /// have angles summing to (nearly) 2s, where their eigenvectors can be mixed. Each
+    /// decomposition is therefore checked against `u`, and other separation angles are
+    /// tried until one is accurate, keeping the most accurate if none is.
+    pub fn new(unitary: &Mat<Complex<f64>>) -> Self {
+        let mut best: Option<(Spectrum, f64)> = None;
+        for attempt in 0..SEPARATION_ATTEMPTS {
+            let separation = SEPARATION + attempt as f64 * SEPARATION_STEP;
+            let (spectrum, residual) = Spectrum::with_separation(unitary, separation);
+            if residual <= RESIDUAL_TOLERANCE {
+                return spectrum;
+            }
+            if best.as_ref().is_none_or(|(_, r)| residual < *r) {
+                best = Some((spectrum, residual));
+            }
+        }
+        best.expect("At least one separation angle is tried").0
+    }
+
+    /// Compute an eigendecomposition of a unitary with the given separation angle, returning
+    /// it with its largest residual |u v - e v|.
+    fn with_separation(unitary: &Mat<Complex<f64>>, separation: f64) -> (Self, f64) {
+        let size = unitary.nrows();
+        let rot = Complex::cis(separation);
+        let hermitian = Mat::from_fn(size, size, |i, j| {
+            (unitary[(i, j)] * rot.conj() + unitary[(j, i)].conj() * rot) * 0.5
+        });
+        let vectors = hermitian
+            .self_adjoint_eigen(Side::Lower)
+            .expect("Eigendecomposition of a Hermitian matrix should converge")
+            .U()
+            .to_owned();
+        let mapped = unitary * &vectors;
+        let mut residual: f64 = 0.0;
+        let angles = (0..size)
+            .map(|k| {
+                let eigenvalue: Complex<f64> = (0..size)
+                    .map(|i| vectors[(i, k)].conj() * mapped[(i, k)])
+                    .sum();
+                let error: f64 = (0..size)
+                    .map(|i| (mapped[(i, k)] - eigenvalue * vectors[(i, k)]).norm_sqr())
+                    .sum();
+                residual = residual.max(error.sqrt());
+                let angle = eigenvalue.arg() / PI;
+                if angle <= -1.0 + BRANCH_TOLERANCE {
+                    1.0
+                } else {
+                    angle
+                }
+            })
+            .collect();
+        (Spectrum { vectors, angles }, residual)
+    }
+
+    /// Returns the principal power of the unitary with the given exponent.
+    pub fn power(&self, exponent: f64) -> Mat<Complex<f64>> {
+        let size = self.vectors.nrows();
+        let scaled = Mat::from_fn(size, size, |i, k| {
+            self.vectors[(i, k)] * Complex::cis(PI * self.angles[k] * exponent)
+        });
+        &scaled * self.vectors.adjoint()
+    }
+}
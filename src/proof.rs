@@ -0,0 +1,703 @@
+//! Checking of equational proofs between terms.
+//!
+//! A proof is a chain of steps "A = B by axiom; B = C by axiom; ...", declared in a command
+//! with "proof ... ,". Both sides of each step are evaluated to squashed normal-form terms,
+//! so gate definitions, inverses, square roots, and associativity are taken for granted.
+//! A step is justified if one application of its axiom, at any position in either side,
+//! rewrites that side to the other. Checking a proof produces a `Certificate`, recording the
+//! position and direction each axiom was applied in.
+
+use std::{fmt::Display, ops::Range};
+
+use miette::Diagnostic;
+use pretty::RcDoc;
+use thiserror::Error;
+use winnow::{
+    LocatingSlice, ModalResult, Parser,
+    ascii::{multispace0, multispace1},
+    combinator::{separated, seq},
+    error::{StrContext, StrContextValue},
+    token::take_while,
+};
+
+use crate::{
+    normal_syntax::{PatternN, TermN, rules::Rule, term::AtomN},
+    raw_syntax::TermR,
+    text::{HasParser, Span, Spanned, ToDoc},
+    typecheck::{Env, TypeCheckError},
+    typed_syntax::TermType,
+};
+
+/// Tolerance used when comparing phases.
+const PHASE_TOLERANCE: f64 = 1e-9;
+
+/// Axioms which can justify a step of a proof.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Axiom {
+    /// Both sides evaluate to the same normal-form term
+    Refl,
+    /// Merging consecutive phases, "ph(a) ; ph(b) = ph(a + b)", and removing a zero phase
+    Phase,
+    /// Distributing "if let" over composition,
+    /// "if let p then A ; if let p then B = if let p then (A ; B)"
+    IfLetComp,
+    /// Removing an "if let" with identity body, "if let p then id = id"
+    IfLetId,
+    /// Matching a ket and its complement, "if let |x> x p then A ; if let |x'> x p then A =
+    /// id x (if let p then A)" where "|x'>" is the complement of "|x>", and similarly when
+    /// the ket is the last part of the pattern
+    Compl,
+    /// Cancelling a term followed by its inverse, "A ; A ^ -1 = id"
+    Inverse,
+    /// Interchanging composition and tensor, "(A x B) ; (C x D) = (A ; C) x (B ; D)"
+    Interchange,
+    /// A rewrite rule declared with "rule ... => ... ,"
+    Rule,
+}
+
+impl Axiom {
+    /// All axioms, in the order they are documented.
+    pub const ALL: [Axiom; 8] = [
+        Axiom::Refl,
+        Axiom::Phase,
+        Axiom::IfLetComp,
+        Axiom::IfLetId,
+        Axiom::Compl,
+        Axiom::Inverse,
+        Axiom::Interchange,
+        Axiom::Rule,
+    ];
+
+    /// Name of the axiom, as written after "by" in a proof.
+    pub fn name(self) -> &'static str {
+        match self {
+            Axiom::Refl => "refl",
+            Axiom::Phase => "phase",
+            Axiom::IfLetComp => "if-let-comp",
+            Axiom::IfLetId => "if-let-id",
+            Axiom::Compl => "compl",
+            Axiom::Inverse => "inverse",
+            Axiom::Interchange => "interchange",
+            Axiom::Rule => "rule",
+        }
+    }
+
+    /// Look up an axiom by name.
+    pub fn from_name(name: &str) -> Option<Axiom> {
+        Axiom::ALL.into_iter().find(|a| a.name() == name)
+    }
+}
+
+impl Display for Axiom {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Errors that can occur when checking a proof.
+#[derive(Error, Diagnostic, Debug, Clone)]
+pub enum ProofError<S: Span> {
+    /// Error when typechecking a side of a step.
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    TypeCheck(#[from] TypeCheckError<S>),
+    /// Error for an axiom name which is not recognised.
+    #[error("Unrecognised axiom {name}.")]
+    #[diagnostic(
+        code("Unknown axiom."),
+        help(
+            "Known axioms are: refl, phase, if-let-comp, if-let-id, compl, inverse, interchange, rule."
+        )
+    )]
+    UnknownAxiom {
+        /// The unknown axiom name
+        name: String,
+        /// Span of axiom name
+        #[label("Axiom used here")]
+        span: S,
+    },
+    /// Error for a step whose sides have different types.
+    #[error("Type mismatch between sides of step {step}")]
+    #[diagnostic(code("Step type mismatch."))]
+    StepTypeMismatch {
+        /// Number of the step, from 1
+        step: usize,
+        /// Left-hand side of step
+        #[label("Has type {lty}")]
+        lhs: TermR<S>,
+        /// Type of left-hand side
+        lty: TermType,
+        /// Right-hand side of step
+        #[label("Has type {rty}")]
+        rhs: TermR<S>,
+        /// Type of right-hand side
+        rty: TermType,
+    },
+    /// Error for a step which does not start where the previous step finished.
+    #[error("Step {step} does not continue from the previous step.")]
+    #[diagnostic(code("Broken proof chain."))]
+    BrokenChain {
+        /// Number of the step, from 1
+        step: usize,
+        /// Right-hand side of the previous step
+        #[label("Previous step ends here")]
+        prev: TermR<S>,
+        /// Left-hand side of this step
+        #[label("This step starts from a different term")]
+        next: TermR<S>,
+    },
+    /// Error for a step which is not justified by its axiom.
+    #[error("Step {step} is not justified by {axiom}.")]
+    #[diagnostic(
+        code("Unjustified step."),
+        help("A step must apply its axiom exactly once, at any position, in either direction.")
+    )]
+    UnjustifiedStep {
+        /// Number of the step, from 1
+        step: usize,
+        /// Axiom given for the step
+        axiom: Axiom,
+        /// Left-hand side of step
+        #[label("Left-hand side")]
+        lhs: TermR<S>,
+        /// Right-hand side of step
+        #[label("Right-hand side")]
+        rhs: TermR<S>,
+        /// Span of axiom name
+        #[label("Does not rewrite one side to the other")]
+        span: S,
+    },
+}
+
+/// A step of a proof in raw syntax, "lhs = rhs by axiom".
+#[derive(Clone, Debug)]
+pub struct StepR<S> {
+    /// Left-hand side of step
+    pub lhs: TermR<S>,
+    /// Right-hand side of step
+    pub rhs: TermR<S>,
+    /// Name of the axiom justifying the step
+    pub axiom: Spanned<S, String>,
+}
+
+/// A proof in raw syntax, a chain of steps.
+#[derive(Clone, Debug)]
+pub struct ProofR<S> {
+    /// Steps of the proof, each starting where the last finished
+    pub steps: Vec<StepR<S>>,
+}
+
+impl<S> ToDoc for ProofR<S> {
+    fn to_doc(&self) -> RcDoc<'_> {
+        RcDoc::intersperse(
+            self.steps.iter().map(|step| {
+                step.lhs
+                    .to_doc()
+                    .append(RcDoc::line())
+                    .append("= ")
+                    .append(step.rhs.to_doc())
+                    .append(RcDoc::line())
+                    .append("by ")
+                    .append(step.axiom.inner.as_str())
+                    .group()
+            }),
+            RcDoc::text(";").append(RcDoc::line()),
+        )
+    }
+}
+
+impl HasParser for ProofR<Range<usize>> {
+    fn parser(input: &mut LocatingSlice<&str>) -> ModalResult<Self> {
+        let mut axiom = take_while(1.., |c: char| c.is_alphanumeric() || c == '-')
+            .with_span()
+            .map(|(name, span): (&str, _)| Spanned {
+                inner: name.to_owned(),
+                span,
+            })
+            .context(StrContext::Label("axiom"));
+        let step = seq!(StepR {
+            lhs: TermR::parser,
+            _: (multispace0, "=", multispace0).context(StrContext::Expected(StrContextValue::CharLiteral('='))),
+            rhs: TermR::parser,
+            _: (multispace1, "by", multispace1).context(StrContext::Expected(StrContextValue::StringLiteral("by"))),
+            axiom: axiom.by_ref(),
+        });
+        separated(1.., step, (multispace0, ';', multispace0))
+            .map(|steps| ProofR { steps })
+            .parse_next(input)
+    }
+}
+
+/// Direction an axiom was applied in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// The axiom rewrites the left-hand side of the step to the right-hand side
+    Forwards,
+    /// The axiom rewrites the right-hand side of the step to the left-hand side
+    Backwards,
+}
+
+/// A checked step of a proof.
+#[derive(Clone, Debug)]
+pub struct CertifiedStep {
+    /// Left-hand side of step, as a squashed normal-form term
+    pub lhs: TermN,
+    /// Right-hand side of step, as a squashed normal-form term
+    pub rhs: TermN,
+    /// Axiom justifying the step
+    pub axiom: Axiom,
+    /// Direction the axiom was applied in
+    pub direction: Direction,
+    /// Position the axiom was applied at, as the indices of the subterms leading to it
+    /// from the side it rewrites. Positions in compositions and tensors are the index of the
+    /// first factor rewritten, and the body of an "if let" has index 0.
+    pub position: Vec<usize>,
+}
+
+/// A certificate that a proof is correct, recording how each step is justified.
+#[derive(Clone, Debug)]
+pub struct Certificate {
+    /// Checked steps of the proof
+    pub steps: Vec<CertifiedStep>,
+}
+
+impl Display for Certificate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let term = |t: &TermN| t.quote().to_raw().to_doc().pretty(60).to_string();
+        let (Some(first), Some(last)) = (self.steps.first(), self.steps.last()) else {
+            return Ok(());
+        };
+        writeln!(f, "Proof of\n{}\n=\n{}", term(&first.lhs), term(&last.rhs))?;
+        for (i, step) in self.steps.iter().enumerate() {
+            let position: Vec<_> = step.position.iter().map(usize::to_string).collect();
+            let direction = match step.direction {
+                Direction::Forwards => "left to right",
+                Direction::Backwards => "right to left",
+            };
+            write!(f, "  step {}: by {}", i + 1, step.axiom)?;
+            if step.axiom != Axiom::Refl {
+                write!(f, ", {direction}, at [{}]", position.join(", "))?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl<S: Span> ProofR<S> {
+    /// Check each step of the proof in the given environment, returning a certificate.
+    pub fn check(&self, env: &Env) -> Result<Certificate, ProofError<S>> {
+        let mut steps: Vec<CertifiedStep> = vec![];
+        for (i, step) in self.steps.iter().enumerate() {
+            let axiom =
+                Axiom::from_name(&step.axiom.inner).ok_or_else(|| ProofError::UnknownAxiom {
+                    name: step.axiom.inner.clone(),
+                    span: step.axiom.span.clone(),
+                })?;
+            let l = step.lhs.check(env, None)?;
+            let r = step.rhs.check(env, None)?;
+            let (lty, rty) = (l.get_type(), r.get_type());
+            if lty != rty {
+                return Err(ProofError::StepTypeMismatch {
+                    step: i + 1,
+                    lhs: step.lhs.clone(),
+                    lty,
+                    rhs: step.rhs.clone(),
+                    rty,
+                });
+            }
+            let mut lhs: TermN = l.eval();
+            lhs.squash();
+            let mut rhs: TermN = r.eval();
+            rhs.squash();
+            if let Some(prev) = steps.last()
+                && !approx_eq(&prev.rhs, &lhs)
+            {
+                return Err(ProofError::BrokenChain {
+                    step: i + 1,
+                    prev: self.steps[i - 1].rhs.clone(),
+                    next: step.lhs.clone(),
+                });
+            }
+            let justification = if axiom == Axiom::Refl {
+                approx_eq(&lhs, &rhs).then(|| (Direction::Forwards, vec![]))
+            } else {
+                find_rewrite(&lhs, &rhs, axiom, env.rules())
+                    .map(|position| (Direction::Forwards, position))
+                    .or_else(|| {
+                        find_rewrite(&rhs, &lhs, axiom, env.rules())
+                            .map(|position| (Direction::Backwards, position))
+                    })
+            };
+            let Some((direction, position)) = justification else {
+                return Err(ProofError::UnjustifiedStep {
+                    step: i + 1,
+                    axiom,
+                    lhs: step.lhs.clone(),
+                    rhs: step.rhs.clone(),
+                    span: step.axiom.span.clone(),
+                });
+            };
+            steps.push(CertifiedStep {
+                lhs,
+                rhs,
+                axiom,
+                direction,
+                position,
+            });
+        }
+        Ok(Certificate { steps })
+    }
+}
+
+/// Find a position at which one application of `axiom` rewrites `from` to `to`.
+fn find_rewrite(from: &TermN, to: &TermN, axiom: Axiom, rules: &[Rule]) -> Option<Vec<usize>> {
+    rewrites(from, axiom, rules)
+        .into_iter()
+        .find_map(|(mut t, position)| {
+            t.squash();
+            approx_eq(&t, to).then_some(position)
+        })
+}
+
+/// All terms obtained from `term` by one application of `axiom`, with the position it was
+/// applied at.
+fn rewrites(term: &TermN, axiom: Axiom, rules: &[Rule]) -> Vec<(TermN, Vec<usize>)> {
+    let mut out: Vec<_> = rewrite_here(term, axiom, rules)
+        .into_iter()
+        .map(|t| (t, vec![]))
+        .collect();
+    match term {
+        TermN::Comp(terms, ty) => {
+            for i in 0..terms.len() {
+                for (len, replacement) in rewrite_run(&terms[i..], axiom, rules) {
+                    let mut new = terms.clone();
+                    new.splice(i..i + len, replacement);
+                    out.push((TermN::Comp(new, *ty), vec![i]));
+                }
+            }
+            for (i, child) in terms.iter().enumerate() {
+                push_child(&mut out, i, child, axiom, rules, |t| {
+                    let mut new = terms.clone();
+                    new[i] = t;
+                    TermN::Comp(new, *ty)
+                });
+            }
+        }
+        TermN::Tensor(terms) => {
+            if axiom == Axiom::Rule {
+                for i in 0..terms.len() {
+                    for (len, replacement) in rule_tensor_run(&terms[i..], rules) {
+                        let mut new = terms.clone();
+                        new.splice(i..i + len, replacement);
+                        out.push((TermN::Tensor(new), vec![i]));
+                    }
+                }
+            }
+            for (i, child) in terms.iter().enumerate() {
+                push_child(&mut out, i, child, axiom, rules, |t| {
+                    let mut new = terms.clone();
+                    new[i] = t;
+                    TermN::Tensor(new)
+                });
+            }
+        }
+        TermN::Atom(AtomN::IfLet(pattern, inner, ty)) => {
+            push_child(&mut out, 0, inner, axiom, rules, |t| {
+                TermN::Atom(AtomN::IfLet(pattern.clone(), Box::new(t), *ty))
+            });
+        }
+        TermN::Atom(_) => {}
+    }
+    out
+}
+
+/// Add the rewrites of the child at index `i` of a term, rebuilding the term around them.
+fn push_child(
+    out: &mut Vec<(TermN, Vec<usize>)>,
+    i: usize,
+    child: &TermN,
+    axiom: Axiom,
+    rules: &[Rule],
+    rebuild: impl Fn(TermN) -> TermN,
+) {
+    for (t, mut position) in rewrites(child, axiom, rules) {
+        position.insert(0, i);
+        out.push((rebuild(t), position));
+    }
+}
+
+/// Rewrites of a whole term by one application of `axiom`.
+fn rewrite_here(term: &TermN, axiom: Axiom, rules: &[Rule]) -> Vec<TermN> {
+    match (axiom, term) {
+        (Axiom::Phase, TermN::Atom(AtomN::Phase(a))) if phase_eq(*a, 0.0) => {
+            vec![TermN::Comp(vec![], TermType(0))]
+        }
+        (Axiom::IfLetId, TermN::Atom(AtomN::IfLet(_, inner, ty))) if inner.is_identity() => {
+            vec![TermN::Comp(vec![], *ty)]
+        }
+        (Axiom::Rule, _) => rules
+            .iter()
+            .filter(|rule| approx_eq(term, &rule.lhs))
+            .map(|rule| rule.rhs.clone())
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Rewrites of a run of factors at the start of a composition by one application of `axiom`,
+/// as the number of factors rewritten and the factors replacing them.
+fn rewrite_run(terms: &[TermN], axiom: Axiom, rules: &[Rule]) -> Vec<(usize, Vec<TermN>)> {
+    if axiom == Axiom::Rule {
+        return rules
+            .iter()
+            .filter_map(|rule| {
+                let TermN::Comp(lhs, _) = &rule.lhs else {
+                    return None;
+                };
+                let matches =
+                    lhs.len() <= terms.len() && lhs.iter().zip(terms).all(|(l, t)| approx_eq(l, t));
+                matches.then(|| (lhs.len(), vec![rule.rhs.clone()]))
+            })
+            .collect();
+    }
+    let [a, b, ..] = terms else {
+        return vec![];
+    };
+    let replacement = match (axiom, a, b) {
+        (Axiom::Phase, TermN::Atom(AtomN::Phase(x)), TermN::Atom(AtomN::Phase(y))) => {
+            if phase_eq(x + y, 0.0) {
+                Some(vec![])
+            } else {
+                Some(vec![TermN::Atom(AtomN::Phase(x + y))])
+            }
+        }
+        (
+            Axiom::IfLetComp,
+            TermN::Atom(AtomN::IfLet(p, x, ty)),
+            TermN::Atom(AtomN::IfLet(q, y, _)),
+        ) if pattern_approx_eq(p, q) => {
+            let inner = TermN::Comp(vec![(**x).clone(), (**y).clone()], x.get_type());
+            Some(vec![TermN::Atom(AtomN::IfLet(
+                p.clone(),
+                Box::new(inner),
+                *ty,
+            ))])
+        }
+        (Axiom::Compl, TermN::Atom(AtomN::IfLet(p, x, ty)), TermN::Atom(AtomN::IfLet(q, y, _)))
+            if approx_eq(x, y) =>
+        {
+            complement(p, q, x, *ty).map(|t| vec![t])
+        }
+        (Axiom::Inverse, a, b) if approx_eq(b, &a.inverse()) => Some(vec![]),
+        (Axiom::Interchange, TermN::Tensor(xs), TermN::Tensor(ys))
+            if xs.len() == ys.len()
+                && xs.iter().zip(ys).all(|(x, y)| x.get_type() == y.get_type()) =>
+        {
+            let terms = xs
+                .iter()
+                .zip(ys)
+                .map(|(x, y)| TermN::Comp(vec![x.clone(), y.clone()], x.get_type()))
+                .collect();
+            Some(vec![TermN::Tensor(terms)])
+        }
+        _ => None,
+    };
+    replacement.map(|r| (2, r)).into_iter().collect()
+}
+
+/// Rewrites of a run of factors at the start of a tensor by a rule whose left-hand side is a
+/// tensor.
+fn rule_tensor_run(terms: &[TermN], rules: &[Rule]) -> Vec<(usize, Vec<TermN>)> {
+    rules
+        .iter()
+        .filter_map(|rule| {
+            let TermN::Tensor(lhs) = &rule.lhs else {
+                return None;
+            };
+            let matches =
+                lhs.len() <= terms.len() && lhs.iter().zip(terms).all(|(l, t)| approx_eq(l, t));
+            matches.then(|| (lhs.len(), vec![rule.rhs.clone()]))
+        })
+        .collect()
+}
+
+/// The parts of a pattern, split at the top level tensor.
+fn pattern_parts(pattern: &PatternN) -> &[PatternN] {
+    match pattern {
+        PatternN::Tensor(patterns) => patterns,
+        p => std::slice::from_ref(p),
+    }
+}
+
+/// Apply the complement axiom to "if let p then body ; if let q then body" of type `ty`.
+fn complement(p: &PatternN, q: &PatternN, body: &TermN, ty: TermType) -> Option<TermN> {
+    let (ps, qs) = (pattern_parts(p), pattern_parts(q));
+    if ps.len() != qs.len() {
+        return None;
+    }
+    let is_compl = |a: &PatternN, b: &PatternN| matches!((a, b), (PatternN::Ket(x), PatternN::Ket(y)) if x.compl() == *y);
+    let rest_eq =
+        |a: &[PatternN], b: &[PatternN]| a.iter().zip(b).all(|(x, y)| pattern_approx_eq(x, y));
+    let rest = |parts: &[PatternN]| match parts {
+        [] => body.clone(),
+        [part] => TermN::Atom(AtomN::IfLet(
+            part.clone(),
+            Box::new(body.clone()),
+            TermType(ty.0 - 1),
+        )),
+        parts => TermN::Atom(AtomN::IfLet(
+            PatternN::Tensor(parts.to_vec()),
+            Box::new(body.clone()),
+            TermType(ty.0 - 1),
+        )),
+    };
+    let id = TermN::Comp(vec![], TermType(1));
+    let n = ps.len();
+    if is_compl(&ps[0], &qs[0]) && rest_eq(&ps[1..], &qs[1..]) {
+        Some(TermN::Tensor(vec![id, rest(&ps[1..])]))
+    } else if is_compl(&ps[n - 1], &qs[n - 1]) && rest_eq(&ps[..n - 1], &qs[..n - 1]) {
+        Some(TermN::Tensor(vec![rest(&ps[..n - 1]), id]))
+    } else {
+        None
+    }
+}
+
+/// Returns true if two angles, divided by pi, are equal modulo 2.
+fn phase_eq(a: f64, b: f64) -> bool {
+    let r = (a - b).rem_euclid(2.0);
+    r < PHASE_TOLERANCE || 2.0 - r < PHASE_TOLERANCE
+}
+
+/// Equality of normal-form terms, comparing phases up to tolerance and modulo 2.
+fn approx_eq(a: &TermN, b: &TermN) -> bool {
+    match (a, b) {
+        (TermN::Comp(xs, xty), TermN::Comp(ys, yty)) => {
+            xty == yty && xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| approx_eq(x, y))
+        }
+        (TermN::Tensor(xs), TermN::Tensor(ys)) => {
+            xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| approx_eq(x, y))
+        }
+        (TermN::Atom(x), TermN::Atom(y)) => atom_approx_eq(x, y),
+        _ => false,
+    }
+}
+
+fn atom_approx_eq(a: &AtomN, b: &AtomN) -> bool {
+    match (a, b) {
+        (AtomN::Phase(x), AtomN::Phase(y)) => phase_eq(*x, *y),
+        (AtomN::Gate(x), AtomN::Gate(y)) => x == y,
+        (AtomN::Power(x), AtomN::Power(y)) => x == y,
+        (AtomN::IfLet(p, x, _), AtomN::IfLet(q, y, _)) => {
+            pattern_approx_eq(p, q) && approx_eq(x, y)
+        }
+        _ => false,
+    }
+}
+
+fn pattern_approx_eq(a: &PatternN, b: &PatternN) -> bool {
+    match (a, b) {
+        (PatternN::Comp(xs, xty), PatternN::Comp(ys, yty)) => {
+            xty == yty
+                && xs.len() == ys.len()
+                && xs.iter().zip(ys).all(|(x, y)| pattern_approx_eq(x, y))
+        }
+        (PatternN::Tensor(xs), PatternN::Tensor(ys)) => {
+            xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| pattern_approx_eq(x, y))
+        }
+        (PatternN::Ket(x), PatternN::Ket(y)) => x == y,
+        (PatternN::Unitary(x), PatternN::Unitary(y)) => atom_approx_eq(x, y),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Command;
+
+    const GATES: &str = "gate Z = if let |1> then -1,
+        gate X = if let |-> then -1,
+        gate CX = if let |1> x id then X,";
+
+    /// Check the proofs of a program defining Z, X and CX.
+    fn check_proofs(proofs: &str) -> Result<Vec<Certificate>, ProofError<Range<usize>>> {
+        let src = format!("{GATES}\n{proofs}\nid");
+        let command = Command::parser
+            .parse(LocatingSlice::new(src.as_str()))
+            .expect("Program should parse");
+        let (env, _) = command.check().expect("Program should typecheck");
+        command.check_proofs(&env)
+    }
+
+    fn axioms(certificate: &Certificate) -> Vec<Axiom> {
+        certificate.steps.iter().map(|step| step.axiom).collect()
+    }
+
+    #[test]
+    fn certifies_each_step() {
+        let certificates = check_proofs(
+            "proof Z ; Z = if let |1> then (-1 ; -1) by if-let-comp;
+                  if let |1> then (-1 ; -1) = if let |1> then id0 by phase;
+                  if let |1> then id0 = id by if-let-id,
+             proof (if let |0> then -1) ; Z = id x -1 by compl,
+             proof X x id ; id x Z = X x Z by interchange,
+             proof CX ; CX ^ -1 = id2 by inverse,",
+        )
+        .unwrap();
+        assert_eq!(
+            certificates.iter().map(axioms).collect::<Vec<_>>(),
+            [
+                vec![Axiom::IfLetComp, Axiom::Phase, Axiom::IfLetId],
+                vec![Axiom::Compl],
+                vec![Axiom::Interchange],
+                vec![Axiom::Inverse],
+            ]
+        );
+    }
+
+    #[test]
+    fn certifies_steps_in_either_direction() {
+        let certificates = check_proofs("proof id = Z ; Z ^ -1 by inverse,").unwrap();
+        assert_eq!(certificates[0].steps[0].direction, Direction::Backwards);
+    }
+
+    #[test]
+    fn rejects_unjustified_step() {
+        let Err(e) = check_proofs("proof Z = Z by refl; Z = X by phase,") else {
+            panic!("Proof should be rejected");
+        };
+        assert!(matches!(
+            e,
+            ProofError::UnjustifiedStep {
+                step: 2,
+                axiom: Axiom::Phase,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn rejects_broken_chain() {
+        let Err(e) = check_proofs("proof Z = Z by refl; X = X by refl,") else {
+            panic!("Proof should be rejected");
+        };
+        assert!(matches!(e, ProofError::BrokenChain { step: 2, .. }));
+    }
+
+    #[test]
+    fn rejects_unknown_axiom() {
+        let Err(e) = check_proofs("proof Z = Z by magic,") else {
+            panic!("Proof should be rejected");
+        };
+        assert!(matches!(e, ProofError::UnknownAxiom { name, .. } if name == "magic"));
+    }
+
+    #[test]
+    fn rejects_step_between_types() {
+        let Err(e) = check_proofs("proof Z = CX by refl,") else {
+            panic!("Proof should be rejected");
+        };
+        assert!(matches!(e, ProofError::StepTypeMismatch { step: 1, .. }));
+    }
+}
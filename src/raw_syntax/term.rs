@@ -1,21 +1,27 @@
 //! Raw syntax terms.
 
-use std::ops::Range;
+use std::{ops::Range, rc::Rc};
 
+use miette::SourceSpan;
 use pretty::RcDoc;
 use winnow::{
     LocatingSlice, ModalResult, Parser,
     ascii::{dec_uint, multispace0, multispace1},
-    combinator::{alt, cut_err, delimited, opt, preceded, separated, seq},
+    combinator::{
+        alt, cut_err, delimited, not, opt, preceded, separated, separated_pair, seq, terminated,
+    },
     error::{StrContext, StrContextValue},
 };
 
 use crate::{
+    circuit_syntax::pattern::PatternC,
+    ket::{CompKetState, KetState},
     phase::Phase,
+    power::Exponent,
     raw_syntax::PatternR,
     text::{HasParser, Name, Span, Spanned, ToDoc},
     typecheck::{Env, TypeCheckError},
-    typed_syntax::{TermT, TermType},
+    typed_syntax::{PatternT, TermT, TermType, term::MAX_REPETITIONS},
 };
 
 /// Raw syntax term with text span.
@@ -87,6 +93,33 @@ pub enum AtomRInner<S> {
     Inverse(Box<AtomR<S>>),
     /// Square root of a term "sqrt(t)"
     Sqrt(Box<AtomR<S>>),
+    /// Power of a term "t ^ r"
+    Power(Box<AtomR<S>>, Exponent),
+    /// Controlled term "ctrl(n, |x>, t)", applying "t" when each of "n" control wires is in
+    /// state "|x>". Written "ctrl(t)" for one "|1>" control and "negctrl(t)" for "|0>".
+    Ctrl {
+        /// Number of control wires
+        controls: usize,
+        /// State each control wire is matched against
+        state: KetState,
+        /// Controlled term
+        inner: Box<TermR<S>>,
+    },
+    /// Case split "match { p_1 => t_1, ..., p_n => t_n, else => t }" over orthogonal
+    /// patterns, with an optional "else" branch covering the rest of the space
+    Match {
+        /// Pattern and body of each branch
+        branches: Vec<(PatternR<S>, TermR<S>)>,
+        /// Body of the "else" branch
+        otherwise: Option<Box<TermR<S>>>,
+    },
+    /// Repetition of a term "repeat n { t }"
+    Repeat {
+        /// Number of times the body is applied
+        count: usize,
+        /// Body of the repetition
+        inner: Box<TermR<S>>,
+    },
 }
 
 impl<S> ToDoc for AtomRInner<S> {
@@ -117,6 +150,75 @@ impl<S> ToDoc for AtomRInner<S> {
                 .append(RcDoc::line())
                 .append(")")
                 .group(),
+            AtomRInner::Power(inner, exponent) => {
+                inner.to_doc().append(" ^ ").append(exponent.to_doc())
+            }
+            AtomRInner::Ctrl {
+                controls,
+                state,
+                inner,
+            } => {
+                let mut args = vec![];
+                if *controls != 1 {
+                    args.push(RcDoc::as_string(controls));
+                }
+                if state.is_hadamard_basis() {
+                    args.push(RcDoc::text(format!("|{}>", state.to_char())));
+                }
+                args.push(inner.to_doc());
+                let name = if *state == KetState::Zero {
+                    "negctrl("
+                } else {
+                    "ctrl("
+                };
+                RcDoc::text(name)
+                    .append(
+                        RcDoc::line_()
+                            .append(RcDoc::intersperse(
+                                args,
+                                RcDoc::text(",").append(RcDoc::line()),
+                            ))
+                            .nest(2),
+                    )
+                    .append(RcDoc::line_())
+                    .append(")")
+                    .group()
+            }
+            AtomRInner::Match {
+                branches,
+                otherwise,
+            } => {
+                let branches = branches
+                    .iter()
+                    .map(|(p, t)| {
+                        p.to_doc()
+                            .append(" =>")
+                            .append(RcDoc::line().append(t.to_doc()).nest(2))
+                            .group()
+                    })
+                    .chain(otherwise.iter().map(|t| {
+                        RcDoc::text("else =>")
+                            .append(RcDoc::line().append(t.to_doc()).nest(2))
+                            .group()
+                    }));
+                RcDoc::text("match {")
+                    .append(
+                        RcDoc::line()
+                            .append(RcDoc::intersperse(
+                                branches,
+                                RcDoc::text(",").append(RcDoc::line()),
+                            ))
+                            .nest(2),
+                    )
+                    .append(RcDoc::line())
+                    .append("}")
+                    .group()
+            }
+            AtomRInner::Repeat { count, inner } => RcDoc::text(format!("repeat {count} {{"))
+                .append(RcDoc::line().append(inner.to_doc()).nest(2))
+                .append(RcDoc::line())
+                .append("}")
+                .group(),
         }
     }
 }
@@ -154,9 +256,22 @@ impl<S: Span> TermR<S> {
         }
         Ok(TermT::Comp(v))
     }
+
+    /// Returns the span of a composition in this term, or in the definition of a gate it uses,
+    /// if there is one.
+    pub(crate) fn composition(&self, env: &Env) -> Option<SourceSpan> {
+        if self.inner.terms.len() != 1 {
+            return Some(self.span.clone().into());
+        }
+        self.inner.terms.iter().find_map(|t| t.composition(env))
+    }
 }
 
 impl<S: Span> TensorR<S> {
+    fn composition(&self, env: &Env) -> Option<SourceSpan> {
+        self.inner.terms.iter().find_map(|t| t.composition(env))
+    }
+
     fn check(&self, env: &Env, check_sqrt: Option<&S>) -> Result<TermT, TypeCheckError<S>> {
         Ok(TermT::Tensor(
             self.inner
@@ -169,6 +284,33 @@ impl<S: Span> TensorR<S> {
 }
 
 impl<S: Span> AtomR<S> {
+    fn composition(&self, env: &Env) -> Option<SourceSpan> {
+        match &self.inner {
+            AtomRInner::Brackets(term) => term.composition(env),
+            AtomRInner::Id(_) | AtomRInner::Phase(_) => None,
+            AtomRInner::IfLet { inner, .. } => inner.composition(env),
+            AtomRInner::Gate(name) => env.gates.get(name).and_then(|entry| entry.composition),
+            AtomRInner::Inverse(inner) | AtomRInner::Sqrt(inner) => inner.composition(env),
+            AtomRInner::Power(..) => None,
+            AtomRInner::Ctrl { inner, .. } => inner.composition(env),
+            AtomRInner::Match {
+                branches,
+                otherwise,
+            } => branches
+                .iter()
+                .map(|(_, t)| t)
+                .chain(otherwise.as_deref())
+                .find_map(|t| t.composition(env)),
+            AtomRInner::Repeat { count, inner } => {
+                if *count > 1 {
+                    Some(self.span.clone().into())
+                } else {
+                    inner.composition(env)
+                }
+            }
+        }
+    }
+
     fn check(&self, env: &Env, check_sqrt: Option<&S>) -> Result<TermT, TypeCheckError<S>> {
         match &self.inner {
             AtomRInner::Brackets(term) => term.check(env, check_sqrt),
@@ -194,10 +336,19 @@ impl<S: Span> AtomR<S> {
                 }
             }
             AtomRInner::Gate(name) => {
-                if let Some(def) = env.0.get(name) {
+                if let Some(entry) = env.gates.get(name) {
+                    if let (Some(span_of_root), Some(composition)) = (check_sqrt, entry.composition)
+                    {
+                        return Err(TypeCheckError::GateNotRootable {
+                            name: name.clone(),
+                            span: self.span.clone(),
+                            span_of_root: span_of_root.clone(),
+                            composition,
+                        });
+                    }
                     Ok(TermT::Gate {
                         name: name.clone(),
-                        def: Box::new(def.clone()),
+                        def: entry.def.clone(),
                     })
                 } else {
                     Err(TypeCheckError::UnknownSymbol {
@@ -211,16 +362,180 @@ impl<S: Span> AtomR<S> {
                 Ok(TermT::Inverse(Box::new(inner_t)))
             }
             AtomRInner::Sqrt(inner) => {
-                let inner_t = if check_sqrt.is_some() {
-                    inner.check(env, None)?
+                let inner_t = inner.check(env, Some(check_sqrt.unwrap_or(&self.span)))?;
+                Ok(TermT::Sqrt(Box::new(inner_t)))
+            }
+            AtomRInner::Power(inner, exponent) => {
+                let inner_t = inner.check(env, None)?;
+                let rootable = inner_t.get_type().0 == 0 || inner.composition(env).is_none();
+                let value = exponent.eval();
+                if !rootable && value.fract() == 0.0 && value.abs() > MAX_REPETITIONS as f64 {
+                    return Err(TypeCheckError::TooManyRepetitions {
+                        span: self.span.clone(),
+                        count: format!("{}", value.abs()),
+                        limit: MAX_REPETITIONS,
+                    });
+                }
+                Ok(TermT::Power {
+                    rootable,
+                    inner: Rc::new(inner_t),
+                    exponent: *exponent,
+                })
+            }
+            AtomRInner::Ctrl {
+                controls,
+                state,
+                inner,
+            } => {
+                let inner_t = inner.check(env, check_sqrt)?;
+                let ty = inner_t.get_type();
+                let ket = PatternT::Ket(CompKetState::new(vec![*state; *controls]));
+                let pattern = if ty.0 == 0 {
+                    ket
                 } else {
-                    inner.check(env, Some(&self.span))?
+                    PatternT::Tensor(vec![ket, PatternT::Unitary(Box::new(TermT::Id(ty)))])
                 };
+                Ok(TermT::IfLet {
+                    pattern,
+                    inner: Box::new(inner_t),
+                })
+            }
+            AtomRInner::Match {
+                branches,
+                otherwise,
+            } => check_match(env, check_sqrt, branches, otherwise.as_deref()),
+            AtomRInner::Repeat { count, inner } => {
+                if *count > MAX_REPETITIONS {
+                    return Err(TypeCheckError::TooManyRepetitions {
+                        span: self.span.clone(),
+                        count: count.to_string(),
+                        limit: MAX_REPETITIONS,
+                    });
+                }
+                if let Some(span_of_root) = check_sqrt
+                    && *count > 1
+                {
+                    let tensor = Spanned {
+                        inner: TensorRInner {
+                            terms: vec![self.clone()],
+                        },
+                        span: self.span.clone(),
+                    };
+                    return Err(TypeCheckError::TermNotRootable {
+                        tm: Spanned {
+                            inner: TermRInner {
+                                terms: vec![tensor],
+                            },
+                            span: self.span.clone(),
+                        },
+                        span_of_root: span_of_root.clone(),
+                    });
+                }
+                let inner_t = inner.check(env, check_sqrt)?;
+                Ok(TermT::Repeat {
+                    inner: Box::new(inner_t),
+                    count: *count,
+                })
+            }
+        }
+    }
+}
 
-                Ok(TermT::Sqrt(Box::new(inner_t)))
+/// Typecheck the branches of a "match", desugaring it to a composition of "if let"s.
+/// The "else" branch becomes one "if let" for each pattern in a decomposition of the rest of
+/// the space, with kets on every wire matched by some branch.
+fn check_match<S: Span>(
+    env: &Env,
+    check_sqrt: Option<&S>,
+    branches: &[(PatternR<S>, TermR<S>)],
+    otherwise: Option<&TermR<S>>,
+) -> Result<TermT, TypeCheckError<S>> {
+    let mut checked: Vec<(&PatternR<S>, PatternT, Option<PatternC>)> = vec![];
+    let mut terms = vec![];
+    for (pattern, inner) in branches {
+        let p = pattern.check(env)?;
+        let t = inner.check(env, check_sqrt)?;
+        let pty = p.get_type();
+        let tty = t.get_type();
+        if pty.1 != tty.0 {
+            return Err(TypeCheckError::BranchTypeMismatch {
+                p: pattern.clone(),
+                pty,
+                t: inner.clone(),
+                tty,
+            });
+        }
+        if let Some((first, first_p, _)) = checked.first()
+            && first_p.get_type().0 != pty.0
+        {
+            return Err(TypeCheckError::MatchTypeMismatch {
+                p1: (*first).clone(),
+                ty1: first_p.get_type(),
+                p2: pattern.clone(),
+                ty2: pty,
+            });
+        }
+        let parts = p.to_ket_parts();
+        for (other, _, other_parts) in &checked {
+            let orthogonal = match (&parts, other_parts) {
+                (Some(a), Some(b)) => a.is_orthogonal_to(b),
+                _ => false,
+            };
+            if !orthogonal {
+                return Err(TypeCheckError::BranchesNotOrthogonal {
+                    p1: (*other).clone(),
+                    p2: pattern.clone(),
+                });
             }
         }
+        checked.push((pattern, p.clone(), parts));
+        terms.push(TermT::IfLet {
+            pattern: p,
+            inner: Box::new(t),
+        });
     }
+    if let Some(otherwise) = otherwise {
+        let t = otherwise.check(env, check_sqrt)?;
+        let qubits = checked[0].1.get_type().0;
+        let mut remaining = vec![PatternC::id(qubits)];
+        for (pattern, _, parts) in &checked {
+            let not_expressible = || TypeCheckError::ElseNotExpressible {
+                p: (*pattern).clone(),
+                t: otherwise.clone(),
+            };
+            let parts = parts.as_ref().ok_or_else(not_expressible)?;
+            let mut rest = vec![];
+            for r in &remaining {
+                rest.extend(r.subtract(parts).ok_or_else(not_expressible)?);
+            }
+            remaining = rest;
+        }
+        // Split the rest of the space so each piece matches a ket on every wire matched by
+        // some branch, giving every piece the same type.
+        let mut matched = vec![None; qubits];
+        for parts in checked.iter().filter_map(|(_, _, parts)| parts.as_ref()) {
+            for (m, part) in matched.iter_mut().zip(&parts.parts) {
+                *m = m.or(*part);
+            }
+        }
+        let remaining = remaining.iter().flat_map(|r| r.split_on(&matched));
+        for r in remaining {
+            let rty = TermType(r.id_qubits());
+            if t.get_type() != rty {
+                return Err(TypeCheckError::ElseTypeMismatch {
+                    t: otherwise.clone(),
+                    tty: t.get_type(),
+                    remaining: r.quote().to_raw().to_doc().pretty(80).to_string(),
+                    rty,
+                });
+            }
+            terms.push(TermT::IfLet {
+                pattern: r.quote(),
+                inner: Box::new(t.clone()),
+            });
+        }
+    }
+    Ok(TermT::Comp(terms))
 }
 
 impl HasParser for TermRInner<Range<usize>> {
@@ -244,6 +559,27 @@ impl HasParser for TensorRInner<Range<usize>> {
 
 impl HasParser for AtomRInner<Range<usize>> {
     fn parser(input: &mut LocatingSlice<&str>) -> ModalResult<Self> {
+        // Optional number of controls of "ctrl" and "negctrl", followed by a comma.
+        let controls = || {
+            opt(terminated(
+                dec_uint.verify(|n: &usize| *n != 0),
+                (multispace0, ',', multispace0),
+            ))
+            .map(|n| n.unwrap_or(1))
+        };
+        let close = || {
+            cut_err(
+                (multispace0, ")").context(StrContext::Expected(StrContextValue::CharLiteral(')'))),
+            )
+        };
+        // Branch "p => t" of a "match", other than its "else" branch.
+        let branch = || {
+            separated_pair(
+                preceded(not(("else", multispace0, "=>")), PatternR::parser),
+                (multispace0, "=>", multispace0),
+                TermR::parser,
+            )
+        };
         let without_inverse = alt((
             delimited(
                 ("(", multispace0),
@@ -257,6 +593,66 @@ impl HasParser for AtomRInner<Range<usize>> {
             preceded(("sqrt", multispace0), cut_err(AtomR::parser))
                 .map(|inner| AtomRInner::Sqrt(Box::new(inner))),
             preceded("id", opt(dec_uint)).map(|qubits| AtomRInner::Id(qubits.unwrap_or(1))),
+            delimited(
+                ("ctrl", multispace0, "(", multispace0),
+                cut_err((
+                    controls(),
+                    opt(terminated(
+                        delimited("|", KetState::parser, ">"),
+                        (multispace0, ',', multispace0),
+                    )),
+                    TermR::parser,
+                )),
+                close(),
+            )
+            .map(|(controls, state, inner)| AtomRInner::Ctrl {
+                controls,
+                state: state.unwrap_or(KetState::One),
+                inner: Box::new(inner),
+            }),
+            delimited(
+                ("negctrl", multispace0, "(", multispace0),
+                cut_err((controls(), TermR::parser)),
+                close(),
+            )
+            .map(|(controls, inner)| AtomRInner::Ctrl {
+                controls,
+                state: KetState::Zero,
+                inner: Box::new(inner),
+            }),
+            preceded(
+                ("match", multispace0, "{"),
+                cut_err(seq!(
+                    _: multispace0,
+                    separated(1.., branch(), (multispace0, ',', multispace0)),
+                    opt(preceded(
+                        (multispace0, ',', multispace0, "else", multispace0, "=>", multispace0),
+                        TermR::parser,
+                    )),
+                    _: multispace0,
+                    _: "}".context(StrContext::Expected(StrContextValue::CharLiteral('}')))
+                )),
+            )
+            .map(|(branches, otherwise)| AtomRInner::Match {
+                branches,
+                otherwise: otherwise.map(Box::new),
+            }),
+            preceded(
+                ("repeat", multispace1),
+                cut_err(seq!(
+                    dec_uint,
+                    _: multispace0,
+                    _: "{".context(StrContext::Expected(StrContextValue::CharLiteral('{'))),
+                    _: multispace0,
+                    TermR::parser,
+                    _: multispace0,
+                    _: "}".context(StrContext::Expected(StrContextValue::CharLiteral('}')))
+                )),
+            )
+            .map(|(count, inner)| AtomRInner::Repeat {
+                count,
+                inner: Box::new(inner),
+            }),
             preceded(
                 "if",
                 cut_err(seq!(
@@ -279,6 +675,16 @@ impl HasParser for AtomRInner<Range<usize>> {
         .context(StrContext::Expected(StrContextValue::CharLiteral('(')))
         .context(StrContext::Expected(StrContextValue::StringLiteral("sqrt")))
         .context(StrContext::Expected(StrContextValue::StringLiteral("id")))
+        .context(StrContext::Expected(StrContextValue::StringLiteral("ctrl")))
+        .context(StrContext::Expected(StrContextValue::StringLiteral(
+            "match",
+        )))
+        .context(StrContext::Expected(StrContextValue::StringLiteral(
+            "negctrl",
+        )))
+        .context(StrContext::Expected(StrContextValue::StringLiteral(
+            "repeat",
+        )))
         .context(StrContext::Expected(StrContextValue::StringLiteral("if")))
         .context(StrContext::Expected(StrContextValue::CharLiteral('H')))
         .context(StrContext::Expected(StrContextValue::Description(
@@ -291,18 +697,105 @@ impl HasParser for AtomRInner<Range<usize>> {
                 multispace0,
                 "^",
                 multispace0,
-                cut_err("-1").context(StrContext::Expected(StrContextValue::StringLiteral("-1"))),
+                cut_err(Exponent::parser).context(StrContext::Expected(
+                    StrContextValue::Description("exponent"),
+                )),
             ))
             .context(StrContext::Label("term")),
         )
             .with_span()
-            .map(|((inner, invert), span)| {
-                if invert.is_some() {
-                    AtomRInner::Inverse(Box::new(Spanned { inner, span }))
-                } else {
-                    inner
+            .map(|((inner, power), span)| match power {
+                Some((_, _, _, exponent)) => {
+                    let inner = Box::new(Spanned { inner, span });
+                    if exponent.eval() == -1.0 {
+                        AtomRInner::Inverse(inner)
+                    } else {
+                        AtomRInner::Power(inner, exponent)
+                    }
                 }
+                None => inner,
             })
             .parse_next(input)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use winnow::{LocatingSlice, Parser};
+
+    use super::*;
+    use crate::command::check_source;
+
+    /// Asserts that a sugared term typechecks to a term with the same type and unitary as the
+    /// given "if let"s.
+    fn assert_desugars(src: &str, expected: &str) {
+        let (_, term) = check_source(src);
+        let (_, expected) = check_source(expected);
+        assert_eq!(term.get_type(), expected.get_type());
+        let (a, b) = (term.to_unitary().unwrap(), expected.to_unitary().unwrap());
+        for i in 0..a.nrows() {
+            for j in 0..a.ncols() {
+                assert!((a[(i, j)] - b[(i, j)]).norm() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn match_else_covers_rest_with_kets() {
+        assert_desugars(
+            "match { |00> => -1, else => i }",
+            "if let |00> then -1; if let |1> x |0> then i; if let |1> x |1> then i; \
+             if let |0> x |1> then i",
+        );
+    }
+
+    #[test]
+    fn match_else_keeps_unmatched_wires() {
+        assert_desugars(
+            "match { |00> x id => id, |01> x id => id, else => id }",
+            "if let |00> x id then id; if let |01> x id then id; \
+             if let |1> x |0> x id then id; if let |1> x |1> x id then id",
+        );
+    }
+
+    const GATES: &str = "gate X = if let |-> then -1, gate Z = if let |1> then -1,";
+
+    #[test]
+    fn ctrl_infers_control_pattern() {
+        for (ctrl, if_let) in [
+            ("ctrl(X)", "if let |1> x id then X"),
+            ("ctrl(2, X)", "if let |11> x id then X"),
+            ("negctrl(X)", "if let |0> x id then X"),
+            ("negctrl(3, X x Z)", "if let |000> x id x id then X x Z"),
+            ("ctrl(-1)", "if let |1> then -1"),
+        ] {
+            assert_desugars(&format!("{GATES} {ctrl}"), &format!("{GATES} {if_let}"));
+        }
+    }
+
+    #[test]
+    fn ctrl_chooses_basis_of_controls() {
+        for (ctrl, if_let) in [
+            ("ctrl(|+>, X)", "if let |+> x id then X"),
+            ("ctrl(2, |->, Z)", "if let |--> x id then Z"),
+            ("ctrl(|0>, X)", "if let |0> x id then X"),
+        ] {
+            assert_desugars(&format!("{GATES} {ctrl}"), &format!("{GATES} {if_let}"));
+        }
+    }
+
+    #[test]
+    fn ctrl_prints_as_written() {
+        for src in ["ctrl(X)", "ctrl(2, |->, X)", "negctrl(3, X)", "negctrl(X)"] {
+            let term = TermR::parser
+                .parse(LocatingSlice::new(src))
+                .expect("Term should parse");
+            assert_eq!(term.to_doc().pretty(80).to_string(), src);
+        }
+        // A "|0>" control is printed as "negctrl".
+        let term = TermR::parser
+            .parse(LocatingSlice::new("ctrl(|0>, X)"))
+            .expect("Term should parse");
+        assert_eq!(term.to_doc().pretty(80).to_string(), "negctrl(X)");
+    }
+}
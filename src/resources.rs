@@ -0,0 +1,155 @@
+//! Resource estimation for compiled programs.
+//!
+//! Counts are taken from the circuit-normal form of a program, together with its lowering
+//! to one- and two-qubit gates for the T-count.
+
+use std::fmt::Display;
+
+use crate::circuit_syntax::{
+    TermC,
+    lower::{Decomposition, Entangler},
+};
+
+/// Resource estimates for a circuit-normal term.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResourceEstimate {
+    /// Number of qubits the term acts on.
+    pub qubits: usize,
+    /// Number of clauses.
+    pub clauses: usize,
+    /// Number of layers of independent clauses.
+    pub depth: usize,
+    /// Number of clauses by the number of wires their pattern matches on, so that
+    /// `arity_histogram[k]` clauses are phases controlled on k wires.
+    pub arity_histogram: Vec<usize>,
+    /// Number of clauses whose phase is not a Clifford operation.
+    pub non_clifford: usize,
+    /// Number of clauses applying a numerical power, which are not counted in the lowered
+    /// circuit.
+    pub opaque_powers: usize,
+    /// Number of Hadamard gates needed to change basis for patterns matching |+> or |->,
+    /// two for each such wire of each clause.
+    pub hadamards: usize,
+    /// Number of ancillas used by the lowered circuit.
+    pub ancillas: usize,
+    /// Number of two-qubit gates in the lowered circuit.
+    pub two_qubit_gates: usize,
+    /// Number of T gates in the lowered circuit, counting rotations by odd multiples
+    /// of pi/4.
+    pub t_count: usize,
+    /// Number of rotations in the lowered circuit which are not multiples of pi/4, and
+    /// must be approximated by Clifford+T gates.
+    pub rotations: usize,
+}
+
+impl TermC {
+    /// Estimate the resources needed by this term, lowering it with the given strategy.
+    pub fn estimate_resources(
+        &self,
+        decomposition: Decomposition,
+        entangler: Entangler,
+    ) -> ResourceEstimate {
+        let mut arity_histogram = vec![0; self.ty.0 + 1];
+        let mut non_clifford = 0;
+        let mut hadamards = 0;
+        for clause in &self.clauses {
+            let arity = clause.pattern.parts.iter().flatten().count();
+            arity_histogram[arity] += 1;
+            hadamards += 2 * clause
+                .pattern
+                .parts
+                .iter()
+                .flatten()
+                .filter(|s| s.is_hadamard_basis())
+                .count();
+            if clause.power.is_none() && !is_clifford_phase(arity, clause.phase) {
+                non_clifford += 1;
+            }
+        }
+        while arity_histogram.len() > 1 && arity_histogram.last() == Some(&0) {
+            arity_histogram.pop();
+        }
+        let lowered = self.lower(decomposition, entangler);
+        ResourceEstimate {
+            qubits: self.ty.0,
+            clauses: self.clauses.len(),
+            depth: self.schedule().depth(),
+            arity_histogram,
+            non_clifford,
+            opaque_powers: self.opaque_powers(),
+            hadamards,
+            ancillas: lowered.ancillas,
+            two_qubit_gates: lowered.two_qubit_count(),
+            t_count: lowered.t_count(),
+            rotations: lowered.rotation_count(),
+        }
+    }
+}
+
+/// Returns true if a phase of `phase` pi controlled on `arity` wires is a Clifford
+/// operation: a global phase, a power of S, or a power of CZ, up to basis changes.
+fn is_clifford_phase(arity: usize, phase: f64) -> bool {
+    let multiple_of = |step: f64| {
+        let m = phase / step;
+        (m - m.round()).abs() < 1e-12
+    };
+    match arity {
+        0 => true,
+        1 => multiple_of(0.5),
+        2 => multiple_of(1.0),
+        _ => multiple_of(2.0),
+    }
+}
+
+impl ResourceEstimate {
+    /// Render the estimate as a JSON object.
+    pub fn to_json(&self) -> String {
+        let histogram = self
+            .arity_histogram
+            .iter()
+            .map(usize::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let fields = [
+            ("qubits", self.qubits.to_string()),
+            ("clauses", self.clauses.to_string()),
+            ("depth", self.depth.to_string()),
+            ("arity_histogram", format!("[{histogram}]")),
+            ("non_clifford", self.non_clifford.to_string()),
+            ("opaque_powers", self.opaque_powers.to_string()),
+            ("hadamards", self.hadamards.to_string()),
+            ("ancillas", self.ancillas.to_string()),
+            ("two_qubit_gates", self.two_qubit_gates.to_string()),
+            ("t_count", self.t_count.to_string()),
+            ("rotations", self.rotations.to_string()),
+        ];
+        let body = fields
+            .iter()
+            .map(|(key, value)| format!("  \"{key}\": {value}"))
+            .collect::<Vec<_>>()
+            .join(",\n");
+        format!("{{\n{body}\n}}")
+    }
+}
+
+impl Display for ResourceEstimate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Qubits:                 {}", self.qubits)?;
+        writeln!(f, "Clauses:                {}", self.clauses)?;
+        writeln!(f, "Depth:                  {}", self.depth)?;
+        writeln!(f, "Clauses by arity:")?;
+        for (arity, count) in self.arity_histogram.iter().enumerate() {
+            if *count != 0 {
+                writeln!(f, "  {arity:>3} wires:           {count}")?;
+            }
+        }
+        writeln!(f, "Non-Clifford phases:    {}", self.non_clifford)?;
+        writeln!(f, "Opaque powers:          {}", self.opaque_powers)?;
+        writeln!(f, "Hadamard basis changes: {}", self.hadamards)?;
+        writeln!(f, "Lowered circuit:")?;
+        writeln!(f, "  Ancillas:             {}", self.ancillas)?;
+        writeln!(f, "  Two-qubit gates:      {}", self.two_qubit_gates)?;
+        writeln!(f, "  T-count:              {}", self.t_count)?;
+        write!(f, "  Arbitrary rotations:  {}", self.rotations)
+    }
+}
@@ -0,0 +1,397 @@
+//! Re-sugaring of evaluated terms and circuits back to named gates.
+//!
+//! Evaluation inlines every gate definition. Re-sugaring recognises fragments of a
+//! normal-form term, or runs of clauses of a circuit-normal term, whose unitary is exactly
+//! the unitary of a gate of the environment, and prints them by name instead. Fragments of
+//! circuits are matched on a block of adjacent wires, and placed on those wires with
+//! identities.
+
+use std::rc::Rc;
+
+use faer::Mat;
+use num_complex::Complex;
+
+use crate::{
+    circuit_syntax::{TermC, pattern::PatternC, term::ClauseC},
+    normal_syntax::{Operator, PatternN, TermN, term::AtomN},
+    text::Name,
+    typecheck::Env,
+    typed_syntax::{PatternT, TermT, TermType},
+};
+
+/// Largest number of qubits of a gate which is recognised.
+const MAX_GATE_QUBITS: usize = 6;
+
+/// Largest number of clauses of a circuit matched against a single gate.
+const MAX_CLAUSES: usize = 32;
+
+/// Maximum deviation between the unitaries of a fragment and a gate it is recognised as.
+const TOLERANCE: f64 = 1e-9;
+
+/// Maximum deviation between the fingerprints of a fragment and a gate it may be recognised
+/// as. This is looser than `TOLERANCE`, as the errors in a row of a unitary add up.
+const FINGERPRINT_TOLERANCE: f64 = 1e-6;
+
+/// A gate which can be recognised, with its unitary and fingerprint.
+struct LibraryGate {
+    name: Name,
+    def: Rc<TermT>,
+    unitary: Mat<Complex<f64>>,
+    fingerprint: Mat<Complex<f64>>,
+}
+
+impl LibraryGate {
+    fn to_term(&self) -> TermT {
+        TermT::Gate {
+            name: self.name.clone(),
+            def: self.def.clone(),
+        }
+    }
+}
+
+/// The gates of an environment which can be recognised when re-sugaring.
+/// Gates acting on no qubits, gates with the unitary of an identity, gates with more than
+/// `MAX_GATE_QUBITS` qubits, and gates whose unitary does not fit in the memory limit are not
+/// recognised. When several gates have the same unitary,
+/// the first one defined is used.
+///
+/// Fragments are first compared with the gates by their fingerprint, the image of a fixed
+/// probe state, which is cheap to compute. The unitary of a fragment is only built when its
+/// fingerprint is that of some gate. Fragments longer than every gate are not considered.
+pub struct GateLibrary {
+    /// Gates on each number of qubits, in the order they are defined
+    gates: Vec<Vec<LibraryGate>>,
+    /// Probe state on each number of qubits
+    probes: Vec<Mat<Complex<f64>>>,
+    /// Largest number of factors of the normal form of a gate
+    max_factors: usize,
+    /// Largest number of clauses of the circuit of a gate
+    max_clauses: usize,
+}
+
+impl GateLibrary {
+    /// Build the library of gates defined in an environment.
+    pub fn new(env: &Env) -> Self {
+        let probes: Vec<_> = (0..=MAX_GATE_QUBITS).map(probe).collect();
+        let mut gates: Vec<Vec<LibraryGate>> = (0..=MAX_GATE_QUBITS).map(|_| vec![]).collect();
+        let (mut max_factors, mut max_clauses) = (0, 0);
+        for (name, def) in env.gates() {
+            let qubits = def.get_type().0;
+            if qubits == 0 || qubits > MAX_GATE_QUBITS {
+                continue;
+            }
+            let mut evalled: TermN = def.eval();
+            let Ok(unitary) = evalled.to_unitary() else {
+                continue;
+            };
+            let size = 1 << qubits;
+            if deviation(&unitary, &Mat::identity(size, size)) <= TOLERANCE
+                || gates[qubits]
+                    .iter()
+                    .any(|g| deviation(&g.unitary, &unitary) <= TOLERANCE)
+            {
+                continue;
+            }
+            evalled.squash();
+            evalled.simplify();
+            max_factors = max_factors.max(match &evalled {
+                TermN::Comp(terms, _) => terms.len(),
+                _ => 1,
+            });
+            max_clauses = max_clauses.max(def.eval_circ().num_clauses());
+            gates[qubits].push(LibraryGate {
+                name: name.clone(),
+                def: def.clone(),
+                fingerprint: &unitary * &probes[qubits],
+                unitary,
+            });
+        }
+        GateLibrary {
+            gates,
+            probes,
+            max_factors,
+            max_clauses: max_clauses.min(MAX_CLAUSES),
+        }
+    }
+
+    /// Returns true if no gates can be recognised.
+    pub fn is_empty(&self) -> bool {
+        self.gates.iter().all(Vec::is_empty)
+    }
+
+    /// Returns the gates acting on the given number of qubits.
+    fn gates(&self, qubits: usize) -> &[LibraryGate] {
+        self.gates.get(qubits).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns true if some gate acts on the given number of qubits.
+    fn has_arity(&self, qubits: usize) -> bool {
+        !self.gates(qubits).is_empty()
+    }
+
+    /// Returns true if some gate on the given number of qubits has the given fingerprint.
+    fn has_fingerprint(&self, qubits: usize, fingerprint: &Mat<Complex<f64>>) -> bool {
+        self.gates(qubits)
+            .iter()
+            .any(|g| deviation(&g.fingerprint, fingerprint) <= FINGERPRINT_TOLERANCE)
+    }
+
+    /// Find a gate on the given number of qubits with the given fingerprint and the unitary
+    /// computed by `unitary`, which is only called if some gate has the fingerprint.
+    fn find(
+        &self,
+        qubits: usize,
+        fingerprint: &Mat<Complex<f64>>,
+        unitary: impl FnOnce() -> Option<Mat<Complex<f64>>>,
+    ) -> Option<TermT> {
+        if !self.has_fingerprint(qubits, fingerprint) {
+            return None;
+        }
+        let unitary = unitary()?;
+        self.gates(qubits)
+            .iter()
+            .find(|g| deviation(&g.unitary, &unitary) <= TOLERANCE)
+            .map(LibraryGate::to_term)
+    }
+
+    /// Find a gate with the same unitary as a normal-form term.
+    fn find_term(&self, term: &TermN) -> Option<TermT> {
+        let qubits = term.get_type().0;
+        if !self.has_arity(qubits) {
+            return None;
+        }
+        let op = term.to_operator().ok()?;
+        let fingerprint = op.apply(&self.probes[qubits]);
+        self.find(qubits, &fingerprint, || op.to_matrix().ok())
+    }
+
+    /// Find the longest run of two or more factors of a composition starting at `start`, and
+    /// ending at or before `end`, with the unitary of a gate. The fingerprints of the runs are
+    /// computed together, by applying the operators `ops` of the factors in turn to the probe.
+    fn find_run(
+        &self,
+        terms: &[TermN],
+        ops: &[Operator],
+        ty: TermType,
+        start: usize,
+        end: usize,
+    ) -> Option<(usize, TermT)> {
+        let end = end.min(start + self.max_factors);
+        let mut state = self.probes[ty.0].clone();
+        let mut matched = vec![];
+        for (j, op) in ops.iter().enumerate().take(end).skip(start) {
+            state = op.apply(&state);
+            if j > start && self.has_fingerprint(ty.0, &state) {
+                matched.push((j + 1, state.clone()));
+            }
+        }
+        matched.into_iter().rev().find_map(|(j, fingerprint)| {
+            let run = TermN::Comp(terms[start..j].to_vec(), ty);
+            let gate = self.find(ty.0, &fingerprint, || run.to_unitary().ok())?;
+            Some((j, gate))
+        })
+    }
+}
+
+fn deviation(a: &Mat<Complex<f64>>, b: &Mat<Complex<f64>>) -> f64 {
+    (a - b).norm_max()
+}
+
+/// A fixed state on the given number of qubits, with every amplitude non-zero and no
+/// symmetries, whose image under a unitary is used as a fingerprint of the unitary.
+fn probe(qubits: usize) -> Mat<Complex<f64>> {
+    let size = 1 << qubits;
+    let norm = (size as f64).sqrt().recip();
+    Mat::from_fn(size, 1, |i, _| {
+        Complex::from_polar(norm, (i * i + 1) as f64 * std::f64::consts::SQRT_2)
+    })
+}
+
+impl TermN {
+    /// Quote this normal-form term, replacing fragments with the unitary of a gate in the
+    /// library by that gate. Larger fragments are preferred.
+    pub fn resugar(&self, library: &GateLibrary) -> TermT {
+        if let Some(gate) = library.find_term(self) {
+            return gate;
+        }
+        match self {
+            TermN::Comp(terms, ty) => {
+                if terms.is_empty() {
+                    return TermT::Id(*ty);
+                }
+                let ops = library
+                    .has_arity(ty.0)
+                    .then(|| {
+                        terms
+                            .iter()
+                            .map(TermN::to_operator)
+                            .collect::<Result<Vec<_>, _>>()
+                    })
+                    .and_then(Result::ok);
+                let mut out = vec![];
+                let mut i = 0;
+                while i < terms.len() {
+                    let longest = ops.as_ref().and_then(|ops| {
+                        // Runs of two or more factors, excluding the whole composition.
+                        let end = if i == 0 { terms.len() - 1 } else { terms.len() };
+                        library.find_run(terms, ops, *ty, i, end)
+                    });
+                    if let Some((j, gate)) = longest {
+                        out.push(gate);
+                        i = j;
+                    } else {
+                        out.push(terms[i].resugar(library));
+                        i += 1;
+                    }
+                }
+                if out.len() == 1 {
+                    out.pop().unwrap()
+                } else {
+                    TermT::Comp(out)
+                }
+            }
+            TermN::Tensor(terms) => {
+                TermT::Tensor(terms.iter().map(|t| t.resugar(library)).collect())
+            }
+            TermN::Atom(AtomN::IfLet(pattern, inner, _)) => TermT::IfLet {
+                pattern: pattern.resugar(library),
+                inner: Box::new(inner.resugar(library)),
+            },
+            TermN::Atom(_) => self.quote(),
+        }
+    }
+}
+
+impl PatternN {
+    /// Quote this normal-form pattern, replacing unitaries with the unitary of a gate in the
+    /// library by that gate.
+    pub fn resugar(&self, library: &GateLibrary) -> PatternT {
+        match self {
+            PatternN::Comp(patterns, _) if !patterns.is_empty() => {
+                PatternT::Comp(patterns.iter().map(|p| p.resugar(library)).collect())
+            }
+            PatternN::Tensor(patterns) => {
+                PatternT::Tensor(patterns.iter().map(|p| p.resugar(library)).collect())
+            }
+            PatternN::Unitary(atom) => {
+                PatternT::Unitary(Box::new(TermN::Atom((**atom).clone()).resugar(library)))
+            }
+            p => p.quote(),
+        }
+    }
+}
+
+impl TermC {
+    /// Quote this circuit-normal term, replacing runs of clauses with the unitary of a gate
+    /// in the library on a block of adjacent wires by that gate. Longer runs are preferred.
+    pub fn resugar(&self, library: &GateLibrary) -> TermT {
+        let qubits = self.ty.0;
+        let mut out = vec![];
+        let mut i = 0;
+        while i < self.clauses.len() {
+            let max = self.clauses.len().min(i + library.max_clauses);
+            let found = (i + 1..=max)
+                .rev()
+                .find_map(|j| self.find_run(&self.clauses[i..j], library).map(|g| (j, g)));
+            if let Some((j, gate)) = found {
+                out.push(gate);
+                i = j;
+            } else {
+                out.push(self.clauses[i].quote());
+                i += 1;
+            }
+        }
+        match out.len() {
+            0 => TermT::Id(TermType(qubits)),
+            1 => out.pop().unwrap(),
+            _ => TermT::Comp(out),
+        }
+    }
+
+    /// Find a gate with the same unitary as a run of clauses on a block of adjacent wires,
+    /// returning it placed on those wires.
+    fn find_run(&self, run: &[ClauseC], library: &GateLibrary) -> Option<TermT> {
+        if run.iter().any(|c| c.power.is_some()) {
+            return None;
+        }
+        let qubits = self.ty.0;
+        let support: Vec<usize> = (0..qubits)
+            .filter(|&w| run.iter().any(|c| c.pattern.parts[w].is_some()))
+            .collect();
+        let (&lo, &hi) = (support.first()?, support.last()?);
+        (hi - lo + 1..=qubits.min(MAX_GATE_QUBITS))
+            .filter(|&k| library.has_arity(k))
+            .find_map(|k| {
+                let start = lo.min(qubits - k);
+                let clauses = run
+                    .iter()
+                    .map(|c| ClauseC {
+                        pattern: PatternC {
+                            parts: c.pattern.parts[start..start + k].to_vec(),
+                        },
+                        phase: c.phase,
+                        power: None,
+                    })
+                    .collect();
+                let block = TermC {
+                    clauses,
+                    ty: TermType(k),
+                };
+                let mut fingerprint = library.probes[k].clone();
+                block.apply(&mut fingerprint).ok()?;
+                let gate = library.find(k, &fingerprint, || block.to_unitary().ok())?;
+                let mut parts = vec![];
+                if start > 0 {
+                    parts.push(TermT::Id(TermType(start)));
+                }
+                parts.push(gate);
+                if start + k < qubits {
+                    parts.push(TermT::Id(TermType(qubits - start - k)));
+                }
+                Some(if parts.len() == 1 {
+                    parts.pop().unwrap()
+                } else {
+                    TermT::Tensor(parts)
+                })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{command::check_source, text::ToDoc};
+
+    const GATES: &str = "gate X = if let |-> then -1, gate Z = if let |1> then -1,
+        gate CX = if let |1> x id then X,";
+
+    /// Evaluate a term and print it and its circuit re-sugared with the gates of its program.
+    fn resugar(term: &str) -> (String, String) {
+        let (env, term) = check_source(&format!("{GATES} {term}"));
+        let library = GateLibrary::new(&env);
+        let mut evalled: TermN = term.eval();
+        evalled.squash();
+        evalled.simplify();
+        let circuit = evalled.quote().eval_circ();
+        let print = |t: TermT| t.to_raw().to_doc().pretty(80).to_string();
+        (
+            print(evalled.resugar(&library)),
+            print(circuit.resugar(&library)),
+        )
+    }
+
+    #[test]
+    fn recognises_gates() {
+        let (term, circuit) = resugar("CX ; (X x Z) ; CX");
+        assert_eq!(term, "CX; X x Z; CX");
+        assert_eq!(circuit, "CX; X x id; id x Z; CX");
+    }
+
+    #[test]
+    fn recognises_gates_in_long_compositions() {
+        let term = vec!["X x Z ; CX"; 500].join(" ; ");
+        let (term, circuit) = resugar(&term);
+        assert_eq!(term.matches("CX").count(), 500);
+        assert_eq!(circuit.matches("CX").count(), 500);
+    }
+}
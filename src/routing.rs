@@ -0,0 +1,355 @@
+//! Routing of lowered circuits onto restricted qubit connectivity.
+//!
+//! A coupling map gives the pairs of physical qubits on which two-qubit gates can act.
+//! Routing places each wire of a circuit on the physical qubit with the same index and,
+//! before each two-qubit gate whose wires are not coupled, inserts swaps moving one wire
+//! along a shortest path towards the other. An inserted swap cancels against the last gate
+//! on its qubits when that gate is the same swap. The wires are not returned to their
+//! initial positions, so a routed circuit comes with its final layout.
+
+use std::collections::VecDeque;
+
+use miette::Diagnostic;
+use thiserror::Error;
+
+use crate::lowered_syntax::{CircuitL, GateL};
+
+/// Errors that can occur when building a coupling map or routing a circuit.
+#[derive(Error, Diagnostic, Debug, Clone)]
+pub enum RoutingError {
+    /// Error for a line of an edge-list which is not a pair of qubits.
+    #[error("Line {line} of the coupling map is not an edge: \"{text}\".")]
+    #[diagnostic(
+        code("Invalid coupling map."),
+        help("Each line should hold two qubit indices separated by whitespace.")
+    )]
+    InvalidEdge {
+        /// Line number, starting from 1
+        line: usize,
+        /// Text of the line
+        text: String,
+    },
+    /// Error for a circuit with more wires than the coupling map has qubits.
+    #[error("Circuit uses {required} qubits but the coupling map only has {available}.")]
+    #[diagnostic(code("Coupling map too small."))]
+    TooFewQubits {
+        /// Number of wires of the circuit
+        required: usize,
+        /// Number of qubits in the coupling map
+        available: usize,
+    },
+    /// Error for a two-qubit gate on qubits with no path between them.
+    #[error("Qubits {a} and {b} are not connected in the coupling map.")]
+    #[diagnostic(code("Disconnected coupling map."))]
+    Disconnected {
+        /// First physical qubit
+        a: usize,
+        /// Second physical qubit
+        b: usize,
+    },
+}
+
+/// An undirected graph of physical qubits, with an edge between each pair of qubits on
+/// which a two-qubit gate can act.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CouplingMap {
+    neighbours: Vec<Vec<usize>>,
+}
+
+impl CouplingMap {
+    /// Build a coupling map from a list of edges.
+    pub fn from_edges(qubits: usize, edges: &[(usize, usize)]) -> Self {
+        let mut neighbours = vec![vec![]; qubits];
+        for &(a, b) in edges {
+            if a != b && !neighbours[a].contains(&b) {
+                neighbours[a].push(b);
+                neighbours[b].push(a);
+            }
+        }
+        for n in &mut neighbours {
+            n.sort_unstable();
+        }
+        CouplingMap { neighbours }
+    }
+
+    /// Qubits in a line, with each qubit coupled to the next.
+    pub fn line(qubits: usize) -> Self {
+        let edges: Vec<_> = (1..qubits).map(|i| (i - 1, i)).collect();
+        Self::from_edges(qubits, &edges)
+    }
+
+    /// Qubits in a grid, numbered row by row, with each qubit coupled to the qubits
+    /// horizontally and vertically adjacent to it.
+    pub fn grid(rows: usize, cols: usize) -> Self {
+        let mut edges = vec![];
+        for r in 0..rows {
+            for c in 0..cols {
+                let q = r * cols + c;
+                if c + 1 < cols {
+                    edges.push((q, q + 1));
+                }
+                if r + 1 < rows {
+                    edges.push((q, q + cols));
+                }
+            }
+        }
+        Self::from_edges(rows * cols, &edges)
+    }
+
+    /// Parse a coupling map from a list of edges, one per line, given by two qubit
+    /// indices separated by whitespace. Empty lines and lines starting with "//" are
+    /// ignored. The number of qubits is one more than the largest index.
+    pub fn parse_edge_list(src: &str) -> Result<Self, RoutingError> {
+        let mut edges = vec![];
+        for (i, line) in src.lines().enumerate() {
+            let text = line.trim();
+            if text.is_empty() || text.starts_with("//") {
+                continue;
+            }
+            let invalid = || RoutingError::InvalidEdge {
+                line: i + 1,
+                text: text.to_owned(),
+            };
+            let qubits = text
+                .split_whitespace()
+                .map(|q| q.parse::<usize>().map_err(|_| invalid()))
+                .collect::<Result<Vec<_>, _>>()?;
+            let [a, b] = qubits[..] else {
+                return Err(invalid());
+            };
+            edges.push((a, b));
+        }
+        let qubits = edges.iter().map(|&(a, b)| a.max(b) + 1).max().unwrap_or(0);
+        Ok(Self::from_edges(qubits, &edges))
+    }
+
+    /// Returns the number of physical qubits.
+    pub fn qubits(&self) -> usize {
+        self.neighbours.len()
+    }
+
+    /// Returns true if a two-qubit gate can act on the given qubits.
+    pub fn is_coupled(&self, a: usize, b: usize) -> bool {
+        self.neighbours[a].contains(&b)
+    }
+
+    /// Breadth first search from `start`, returning the distance to and parent of each
+    /// reachable qubit, and the qubits in the order they were reached.
+    fn search(&self, start: usize) -> (Vec<Option<(usize, usize)>>, Vec<usize>) {
+        let mut found = vec![None; self.qubits()];
+        let mut order = vec![];
+        let mut queue = VecDeque::from([start]);
+        found[start] = Some((0, start));
+        while let Some(q) = queue.pop_front() {
+            order.push(q);
+            let (d, _) = found[q].unwrap();
+            for &n in &self.neighbours[q] {
+                if found[n].is_none() {
+                    found[n] = Some((d + 1, q));
+                    queue.push_back(n);
+                }
+            }
+        }
+        (found, order)
+    }
+}
+
+/// A circuit routed onto a coupling map.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoutedCircuit {
+    /// Circuit acting on the physical qubits, with the extra qubits of the coupling map
+    /// added as ancillas.
+    pub circuit: CircuitL,
+    /// Physical qubit holding each wire of the original circuit at the end of the routed
+    /// circuit, so that `layout[w]` is the final position of wire `w`.
+    pub layout: Vec<usize>,
+    /// Number of swaps inserted.
+    pub swaps: usize,
+}
+
+impl CircuitL {
+    /// Route this circuit onto the given coupling map, inserting swaps before two-qubit
+    /// gates on qubits which are not coupled.
+    pub fn route(&self, map: &CouplingMap) -> Result<RoutedCircuit, RoutingError> {
+        let qubits = map.qubits();
+        if self.qubits() > qubits {
+            return Err(RoutingError::TooFewQubits {
+                required: self.qubits(),
+                available: qubits,
+            });
+        }
+        let searches: Vec<_> = (0..qubits).map(|q| map.search(q).0).collect();
+        let mut layout: Vec<usize> = (0..qubits).collect();
+        let mut occupant = layout.clone();
+        let mut circuit = CircuitL::new(self.ty, qubits - self.ty.0);
+        circuit.phase = self.phase;
+        let mut swaps = 0;
+        // Whether each gate of the routed circuit is a swap inserted by routing.
+        let mut inserted = vec![];
+        for g in &self.gates {
+            if let [a, b] = g.wires()[..] {
+                let target = layout[b];
+                let mut q = layout[a];
+                let (distance, _) =
+                    searches[target][q].ok_or(RoutingError::Disconnected { a: q, b: target })?;
+                // Each step moves wire `a` to the parent of its qubit in the search tree
+                // rooted at the target, which is one step closer.
+                for _ in 1..distance {
+                    let (_, next) = searches[target][q].unwrap();
+                    match circuit.push_swap(q, next) {
+                        None => {
+                            inserted.push(true);
+                            swaps += 1;
+                        }
+                        Some(i) => {
+                            if inserted.remove(i) {
+                                swaps -= 1;
+                            }
+                        }
+                    }
+                    occupant.swap(q, next);
+                    layout[occupant[q]] = q;
+                    layout[occupant[next]] = next;
+                    q = next;
+                }
+            }
+            circuit.gates.push(g.map_wires(|w| layout[w]));
+            inserted.push(false);
+        }
+        layout.truncate(self.qubits());
+        Ok(RoutedCircuit {
+            circuit,
+            layout,
+            swaps,
+        })
+    }
+
+    /// Append a swap of two qubits, unless the last gate acting on either of them is the same
+    /// swap, in which case the two cancel and that gate is removed instead.
+    /// Returns the index of the removed gate, if any.
+    fn push_swap(&mut self, a: usize, b: usize) -> Option<usize> {
+        let last = self
+            .gates
+            .iter()
+            .rposition(|g| g.wires().iter().any(|&w| w == a || w == b));
+        if let Some(i) = last
+            && matches!(self.gates[i], GateL::Swap(x, y) if x.min(y) == a.min(b) && x.max(y) == a.max(b))
+        {
+            self.gates.remove(i);
+            Some(i)
+        } else {
+            self.gates.push(GateL::Swap(a, b));
+            None
+        }
+    }
+}
+
+impl RoutedCircuit {
+    /// Returns the routed circuit followed by swaps along edges of the coupling map
+    /// returning every wire to its initial position.
+    /// The resulting circuit has the same unitary as the original circuit, with the extra
+    /// qubits of the coupling map acting as ancillas.
+    pub fn restore_layout(&self, map: &CouplingMap) -> CircuitL {
+        let qubits = map.qubits();
+        let mut circuit = self.circuit.clone();
+        let mut layout: Vec<usize> = (0..qubits).collect();
+        layout[..self.layout.len()].copy_from_slice(&self.layout);
+        // Wires beyond the original circuit are placed on the remaining qubits.
+        let mut free = (0..qubits).filter(|q| !self.layout.contains(q));
+        for l in &mut layout[self.layout.len()..] {
+            *l = free.next().unwrap();
+        }
+        let mut occupant = vec![0; qubits];
+        for (w, &q) in layout.iter().enumerate() {
+            occupant[q] = w;
+        }
+        let mut visited = vec![false; qubits];
+        for root in 0..qubits {
+            if visited[root] {
+                continue;
+            }
+            let (found, order) = map.search(root);
+            for &q in &order {
+                visited[q] = true;
+            }
+            // Fixing qubits in reverse search order leaves the unfixed qubits connected in
+            // the search tree, so each wire can be moved to its qubit through them.
+            for &q in order.iter().rev() {
+                let path = tree_path(&found, layout[q], q);
+                for step in path.windows(2) {
+                    circuit.push_swap(step[0], step[1]);
+                    occupant.swap(step[0], step[1]);
+                    layout[occupant[step[0]]] = step[0];
+                    layout[occupant[step[1]]] = step[1];
+                }
+            }
+        }
+        circuit
+    }
+}
+
+/// Path from `from` to `to` in a search tree given by distances and parents.
+fn tree_path(tree: &[Option<(usize, usize)>], from: usize, to: usize) -> Vec<usize> {
+    let node = |q: usize| tree[q].unwrap();
+    let (mut a, mut b) = (from, to);
+    let mut up = vec![a];
+    let mut down = vec![b];
+    while a != b {
+        if node(a).0 >= node(b).0 {
+            a = node(a).1;
+            up.push(a);
+        } else {
+            b = node(b).1;
+            down.push(b);
+        }
+    }
+    down.pop();
+    up.extend(down.into_iter().rev());
+    up
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::typed_syntax::TermType;
+
+    /// Route a circuit onto a line, checking that the routed circuit followed by restoring
+    /// its layout has the same unitary as the circuit.
+    fn route_on_line(qubits: usize, gates: Vec<GateL>) -> RoutedCircuit {
+        let mut circuit = CircuitL::new(TermType(qubits), 0);
+        circuit.gates = gates;
+        let map = CouplingMap::line(qubits);
+        let routed = circuit.route(&map).unwrap();
+        let deviation =
+            (routed.restore_layout(&map).to_unitary() - circuit.to_unitary()).norm_max();
+        assert!(deviation < 1e-9);
+        routed
+    }
+
+    #[test]
+    fn line_routing_counts_swaps() {
+        let fan_out = (1..5).map(|t| GateL::CX(0, t)).collect();
+        let routed = route_on_line(5, fan_out);
+        assert_eq!(routed.swaps, 3);
+        assert_eq!(routed.circuit.two_qubit_count(), 7);
+        assert_eq!(routed.layout, [3, 0, 1, 2, 4]);
+
+        let routed = route_on_line(4, vec![GateL::CX(0, 3), GateL::CX(0, 1)]);
+        assert_eq!(routed.swaps, 3);
+        assert_eq!(routed.circuit.two_qubit_count(), 5);
+    }
+
+    #[test]
+    fn identical_swaps_cancel() {
+        // Moving wire 0 back across the swap undoes it.
+        let routed = route_on_line(3, vec![GateL::Swap(0, 1), GateL::H(2), GateL::CX(0, 2)]);
+        assert_eq!(routed.swaps, 0);
+        assert_eq!(routed.circuit.gates, [GateL::H(2), GateL::CX(1, 2)]);
+        assert_eq!(routed.layout, [1, 0, 2]);
+
+        // A gate between the swaps stops them cancelling.
+        let routed = route_on_line(3, vec![GateL::Swap(0, 1), GateL::H(1), GateL::CX(0, 2)]);
+        assert_eq!(routed.swaps, 1);
+        assert_eq!(routed.circuit.two_qubit_count(), 3);
+    }
+}
@@ -0,0 +1,116 @@
+//! Helpers for applying gates to dense matrices whose columns are state vectors.
+//!
+//! Qubit (wire) 0 corresponds to the most significant bit of a basis-state index,
+//! matching the ordering used by `kron` when building unitaries.
+
+use std::f64::consts::FRAC_1_SQRT_2;
+
+use faer::Mat;
+use num_complex::Complex;
+
+const CISQRT2: Complex<f64> = Complex::new(FRAC_1_SQRT_2, 0.0);
+
+/// The Hadamard matrix in row-major order.
+pub(crate) const HADAMARD: [[Complex<f64>; 2]; 2] = [
+    [CISQRT2, CISQRT2],
+    [CISQRT2, Complex::new(-FRAC_1_SQRT_2, 0.0)],
+];
+
+/// The Pauli X matrix in row-major order.
+pub(crate) const PAULI_X: [[Complex<f64>; 2]; 2] =
+    [[Complex::ZERO, Complex::ONE], [Complex::ONE, Complex::ZERO]];
+
+/// Returns the bit of a basis-state index holding the value of `wire` in a `qubits`-qubit register.
+pub(crate) fn wire_bit(qubits: usize, wire: usize) -> usize {
+    1 << (qubits - 1 - wire)
+}
+
+/// Applies the 2x2 matrix `u` to `wire` of every column of `state`.
+pub(crate) fn apply_single(
+    state: &mut Mat<Complex<f64>>,
+    qubits: usize,
+    wire: usize,
+    u: &[[Complex<f64>; 2]; 2],
+) {
+    let bit = wire_bit(qubits, wire);
+    for i in (0..state.nrows()).filter(|i| i & bit == 0) {
+        let j = i | bit;
+        for c in 0..state.ncols() {
+            let a = state[(i, c)];
+            let b = state[(j, c)];
+            state[(i, c)] = u[0][0] * a + u[0][1] * b;
+            state[(j, c)] = u[1][0] * a + u[1][1] * b;
+        }
+    }
+}
+
+/// Multiplies every row of `state` whose index `i` satisfies `i & mask == value` by `factor`.
+pub(crate) fn apply_phase(
+    state: &mut Mat<Complex<f64>>,
+    mask: usize,
+    value: usize,
+    factor: Complex<f64>,
+) {
+    for i in (0..state.nrows()).filter(|i| i & mask == value) {
+        for c in 0..state.ncols() {
+            state[(i, c)] *= factor;
+        }
+    }
+}
+
+/// Applies a controlled not with given `control` and `target` wires to every column of `state`.
+pub(crate) fn apply_cx(
+    state: &mut Mat<Complex<f64>>,
+    qubits: usize,
+    control: usize,
+    target: usize,
+) {
+    let cbit = wire_bit(qubits, control);
+    let tbit = wire_bit(qubits, target);
+    for i in (0..state.nrows()).filter(|i| i & cbit != 0 && i & tbit == 0) {
+        for c in 0..state.ncols() {
+            let a = state[(i, c)];
+            state[(i, c)] = state[(i | tbit, c)];
+            state[(i | tbit, c)] = a;
+        }
+    }
+}
+
+/// Applies the matrix `u` to the given `wires` of every column of `state`, on the rows whose
+/// index `i` satisfies `i & mask == value`. The first wire is the most significant qubit of `u`,
+/// and `mask` should not include any of the wires.
+pub(crate) fn apply_controlled(
+    state: &mut Mat<Complex<f64>>,
+    qubits: usize,
+    mask: usize,
+    value: usize,
+    wires: &[usize],
+    u: &Mat<Complex<f64>>,
+) {
+    let bits: Vec<usize> = wires.iter().map(|&w| wire_bit(qubits, w)).collect();
+    let targets = bits.iter().fold(0, |acc, bit| acc | bit);
+    // Offset of each basis state of the wires within a basis-state index.
+    let offsets: Vec<usize> = (0..1 << bits.len())
+        .map(|k: usize| {
+            bits.iter()
+                .enumerate()
+                .filter(|(j, _)| (k >> (bits.len() - 1 - j)) & 1 == 1)
+                .fold(0, |acc, (_, bit)| acc | bit)
+        })
+        .collect();
+    let mut amplitudes = vec![Complex::ZERO; offsets.len()];
+    for i in (0..state.nrows()).filter(|i| i & mask == value && i & targets == 0) {
+        for c in 0..state.ncols() {
+            for (a, o) in amplitudes.iter_mut().zip(&offsets) {
+                *a = state[(i | o, c)];
+            }
+            for (r, o) in offsets.iter().enumerate() {
+                state[(i | o, c)] = amplitudes
+                    .iter()
+                    .enumerate()
+                    .map(|(k, a)| u[(r, k)] * a)
+                    .sum();
+            }
+        }
+    }
+}
@@ -0,0 +1,279 @@
+//! Solutions to the norm equation t^dagger t = xi over Z[omega].
+//!
+//! The integer norm of xi is factored, and a factor of t is found for each prime by a gcd
+//! with an element whose divisors lie over that prime. Norms which cannot be factored
+//! quickly are given up on, and callers are expected to discard such candidates.
+
+use crate::synthesis::ring::{ZOmega, ZRoot2};
+
+/// Number of iterations of Pollard's rho method before a factorisation is abandoned.
+const RHO_ITERATIONS: u32 = 1 << 16;
+
+/// Find t in Z[omega] with t^dagger t = xi, where xi is doubly non-negative.
+pub(crate) fn solve_norm_equation(xi: ZRoot2) -> Option<ZOmega> {
+    if xi == ZRoot2::ZERO {
+        return Some(ZOmega::ZERO);
+    }
+    let mut rest = ZOmega::from_root2(xi);
+    let mut t = ZOmega::ONE;
+    // Each factor of sqrt(2) is, up to a unit, 2 + sqrt(2) = delta^dagger delta.
+    let two_plus_root2 = ZOmega::from_root2(ZRoot2 { a: 2, b: 1 });
+    while rest.div_root2().is_some() {
+        rest = rest.div_exact(two_plus_root2)?;
+        t = t.mul(ZOmega::DELTA)?;
+    }
+    let n = u128::try_from(rest.norm()?).ok()?;
+    for p in prime_factors(n)? {
+        while rest.norm()? % p as i128 == 0 {
+            let tau = prime_factor(rest, p)?;
+            rest = rest.div_exact(ZOmega::from_root2(tau.abs_sq()?))?;
+            t = t.mul(tau)?;
+        }
+    }
+    // What remains is a totally positive unit, and so lambda^(2m) for some m.
+    let ratio = xi.to_f64() / t.abs_sq()?.to_f64();
+    let m = (ratio.ln() / (2.0 * ZRoot2::LAMBDA.to_f64().ln())).round() as i32;
+    let t = t.mul(ZOmega::from_root2(ZRoot2::from_int(1).mul_lambda_pow(m)?))?;
+    (t.abs_sq()? == xi).then_some(t)
+}
+
+/// Find a non-unit tau dividing x whose norm is a power of the odd prime p, such that
+/// tau^dagger tau also divides x.
+fn prime_factor(x: ZOmega, p: u128) -> Option<ZOmega> {
+    let int = |h: u128| ZOmega::from_root2(ZRoot2::from_int(h as i128));
+    let sqrt_two = ZOmega::from_root2(ZRoot2 { a: 0, b: 1 });
+    let (square, other) = match p % 8 {
+        // p splits completely, so x picks out the factors above p which divide it.
+        1 => (p - 1, ZOmega::I),
+        // p is inert in Z[sqrt(2)] and splits into a conjugate pair in Z[omega].
+        3 => (p - 2, ZOmega::SQRT_MINUS_TWO),
+        5 => (p - 1, ZOmega::I),
+        // p splits in Z[sqrt(2)] into primes which are inert in Z[omega].
+        7 => (2, sqrt_two),
+        _ => return None,
+    };
+    let h = sqrt_mod(square, p)?;
+    let modulus = if p % 8 == 1 || p % 8 == 7 { x } else { int(p) };
+    [h, p - h].into_iter().find_map(|h| {
+        let tau = modulus.gcd(int(h).sub(other)?)?;
+        (tau.norm()? != 1).then_some(tau)
+    })
+}
+
+/// The distinct prime factors of n, if they can be found quickly.
+fn prime_factors(mut n: u128) -> Option<Vec<u128>> {
+    let mut primes = vec![];
+    let mut d = 2;
+    while d < 1 << 12 && d * d <= n {
+        if n.is_multiple_of(d) {
+            primes.push(d);
+            while n.is_multiple_of(d) {
+                n /= d;
+            }
+        }
+        d += 1;
+    }
+    let mut stack = vec![n];
+    while let Some(m) = stack.pop() {
+        if m == 1 || primes.contains(&m) {
+            continue;
+        }
+        if is_prime(m) {
+            primes.push(m);
+            continue;
+        }
+        let f = pollard_rho(m)?;
+        stack.push(f);
+        stack.push(m / f);
+    }
+    primes.sort_unstable();
+    primes.dedup();
+    Some(primes)
+}
+
+/// Find a non-trivial factor of a composite n using Pollard's rho method.
+fn pollard_rho(n: u128) -> Option<u128> {
+    for c in 1..8 {
+        let step = |x: u128| (mul_mod(x, x, n) + c) % n;
+        let (mut x, mut y) = (2, 2);
+        for _ in 0..RHO_ITERATIONS {
+            x = step(x);
+            y = step(step(y));
+            let d = gcd(x.abs_diff(y), n);
+            if d == n {
+                break;
+            }
+            if d != 1 {
+                return Some(d);
+            }
+        }
+    }
+    None
+}
+
+fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+fn mul_mod(a: u128, b: u128, m: u128) -> u128 {
+    if m <= u64::MAX as u128 {
+        return a * b % m;
+    }
+    let (mut a, mut b, mut acc) = (a % m, b, 0u128);
+    while b > 0 {
+        if b & 1 == 1 {
+            acc = (acc + a) % m;
+        }
+        a = (a << 1) % m;
+        b >>= 1;
+    }
+    acc
+}
+
+fn pow_mod(mut base: u128, mut exp: u128, m: u128) -> u128 {
+    let mut acc = 1 % m;
+    base %= m;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            acc = mul_mod(acc, base, m);
+        }
+        base = mul_mod(base, base, m);
+        exp >>= 1;
+    }
+    acc
+}
+
+/// Miller-Rabin test, deterministic for all n < 3.3 * 10^24.
+fn is_prime(n: u128) -> bool {
+    const BASES: [u128; 13] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41];
+    if n < 2 {
+        return false;
+    }
+    for p in BASES {
+        if n.is_multiple_of(p) {
+            return n == p;
+        }
+    }
+    let s = (n - 1).trailing_zeros();
+    let d = (n - 1) >> s;
+    BASES.iter().all(|&a| {
+        let mut x = pow_mod(a, d, n);
+        if x == 1 || x == n - 1 {
+            return true;
+        }
+        for _ in 1..s {
+            x = mul_mod(x, x, n);
+            if x == n - 1 {
+                return true;
+            }
+        }
+        false
+    })
+}
+
+/// Find a square root of a modulo an odd prime p, using the Tonelli-Shanks algorithm.
+fn sqrt_mod(a: u128, p: u128) -> Option<u128> {
+    let a = a % p;
+    if a == 0 {
+        return Some(0);
+    }
+    if pow_mod(a, (p - 1) / 2, p) != 1 {
+        return None;
+    }
+    let s = (p - 1).trailing_zeros();
+    let q = (p - 1) >> s;
+    let z = (2..p).find(|&z| pow_mod(z, (p - 1) / 2, p) == p - 1)?;
+    let (mut m, mut c, mut t, mut r) = (
+        s,
+        pow_mod(z, q, p),
+        pow_mod(a, q, p),
+        pow_mod(a, q.div_ceil(2), p),
+    );
+    while t != 1 {
+        let i = (1..m).find(|&i| pow_mod(t, 1 << i, p) == 1)?;
+        let b = pow_mod(c, 1 << (m - i - 1), p);
+        m = i;
+        c = mul_mod(b, b, p);
+        t = mul_mod(t, c, p);
+        r = mul_mod(r, b, p);
+    }
+    Some(r)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MERSENNE_61: u128 = (1 << 61) - 1;
+
+    #[test]
+    fn is_prime_on_known_values() {
+        for p in [2, 3, 5, 97, 7919, 1_000_000_007, MERSENNE_61] {
+            assert!(is_prime(p), "{p} is prime");
+        }
+        // 561 is a Carmichael number, and 3215031751 a strong pseudoprime to bases 2 to 7.
+        for n in [
+            0,
+            1,
+            4,
+            561,
+            3_215_031_751,
+            7919 * 7907,
+            MERSENNE_61 * 1_000_000_007,
+        ] {
+            assert!(!is_prime(n), "{n} is composite");
+        }
+    }
+
+    #[test]
+    fn sqrt_mod_on_known_values() {
+        // The squares modulo 7 are 1, 2 and 4.
+        assert_eq!(sqrt_mod(3, 7), None);
+        assert_eq!(sqrt_mod(0, 7), Some(0));
+        for p in [7, 13, 17, 97, 1_000_000_007, MERSENNE_61] {
+            for x in [1, 2, 3, 10, 12345] {
+                let a = mul_mod(x, x, p);
+                let r = sqrt_mod(a, p).expect("Squares have square roots");
+                assert_eq!(mul_mod(r, r, p), a);
+            }
+        }
+    }
+
+    #[test]
+    fn prime_factors_are_distinct() {
+        assert_eq!(
+            prime_factors(2 * 3 * 3 * 1_000_003),
+            Some(vec![2, 3, 1_000_003])
+        );
+        assert_eq!(
+            prime_factors(1_000_000_007 * 998_244_353),
+            Some(vec![998_244_353, 1_000_000_007])
+        );
+    }
+
+    #[test]
+    fn solve_norm_equation_round_trips() {
+        let samples = [
+            ZOmega {
+                coeffs: [1, 2, -3, 4],
+            },
+            ZOmega {
+                coeffs: [0, -1, 5, 2],
+            },
+            ZOmega {
+                coeffs: [17, 3, -8, 11],
+            },
+            ZOmega::DELTA,
+            ZOmega::ONE,
+        ];
+        for s in samples {
+            let xi = s.abs_sq().unwrap();
+            let t = solve_norm_equation(xi).expect("Norm of an element should be solvable");
+            assert_eq!(t.abs_sq(), Some(xi));
+        }
+        assert_eq!(solve_norm_equation(ZRoot2::ZERO), Some(ZOmega::ZERO));
+    }
+}
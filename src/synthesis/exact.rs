@@ -0,0 +1,210 @@
+//! Exact synthesis of single-qubit Clifford+T unitaries.
+//!
+//! A unitary with entries in Z[omega, 1/sqrt(2)] is reduced by repeatedly multiplying by
+//! H T^j on the left, each step lowering the smallest denominator exponent of |u|^2, where u
+//! is the top-left entry. Once the unitary is small enough, it is found in a table of short
+//! words.
+
+use std::{collections::HashMap, sync::OnceLock};
+
+use crate::synthesis::ring::ZOmega;
+
+/// A generator of single-qubit Clifford+T circuits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Generator {
+    H,
+    /// T^j for 0 < j < 8
+    T(u32),
+}
+
+/// A 2x2 matrix with entries in Z[omega] divided by sqrt(2)^k, in row-major order.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct ExactMat {
+    pub(crate) entries: [ZOmega; 4],
+    pub(crate) k: u32,
+}
+
+/// Largest denominator exponent of the unitaries stored in the lookup table.
+const TABLE_EXPONENT: u32 = 4;
+
+impl ExactMat {
+    fn identity() -> Self {
+        ExactMat {
+            entries: [ZOmega::ONE, ZOmega::ZERO, ZOmega::ZERO, ZOmega::ONE],
+            k: 0,
+        }
+    }
+
+    /// Divide out common factors of sqrt(2) from the entries.
+    pub(crate) fn normalise(mut self) -> Self {
+        while self.k > 0 {
+            let Some(entries) = self
+                .entries
+                .iter()
+                .map(|e| e.div_root2())
+                .collect::<Option<Vec<_>>>()
+            else {
+                break;
+            };
+            self.entries = entries.try_into().unwrap();
+            self.k -= 1;
+        }
+        self
+    }
+
+    /// Multiply on the left by the given generator.
+    fn apply(&self, g: Generator) -> Option<Self> {
+        let [a, b, c, d] = self.entries;
+        match g {
+            Generator::H => Some(
+                ExactMat {
+                    entries: [a.add(c)?, b.add(d)?, a.sub(c)?, b.sub(d)?],
+                    k: self.k + 1,
+                }
+                .normalise(),
+            ),
+            Generator::T(j) => Some(ExactMat {
+                entries: [a, b, c.mul_omega_pow(j), d.mul_omega_pow(j)],
+                k: self.k,
+            }),
+        }
+    }
+
+    /// Smallest denominator exponent of the squared magnitude of the first column entries,
+    /// as a power of sqrt(2).
+    fn sde(&self) -> Option<u32> {
+        let [a, _, c, _] = self.entries;
+        let entry = if a.is_zero() { c } else { a };
+        let mut abs_sq = ZOmega::from_root2(entry.abs_sq()?);
+        let mut sde = 2 * self.k;
+        while sde > 0
+            && let Some(reduced) = abs_sq.div_root2()
+        {
+            abs_sq = reduced;
+            sde -= 1;
+        }
+        Some(sde)
+    }
+
+    fn mul_omega_pow(&self, j: u32) -> Self {
+        ExactMat {
+            entries: self.entries.map(|e| e.mul_omega_pow(j)),
+            k: self.k,
+        }
+    }
+}
+
+/// Words of at most the table exponent, indexed by their unitary.
+/// Each entry holds the generators in matrix product order, and a power of omega
+/// giving the global phase.
+fn table() -> &'static HashMap<ExactMat, (Vec<Generator>, u32)> {
+    static TABLE: OnceLock<HashMap<ExactMat, (Vec<Generator>, u32)>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = HashMap::new();
+        let mut frontier = vec![(ExactMat::identity(), vec![])];
+        while !frontier.is_empty() {
+            let mut next = vec![];
+            for (m, word) in frontier {
+                if table.contains_key(&m) {
+                    continue;
+                }
+                for j in 0..8 {
+                    table
+                        .entry(m.mul_omega_pow(j))
+                        .or_insert_with(|| (word.clone(), j));
+                }
+                for g in [Generator::H, Generator::T(1)] {
+                    if let Some(n) = m.apply(g)
+                        && n.k <= TABLE_EXPONENT + 1
+                        && !table.contains_key(&n)
+                    {
+                        let mut w = vec![g];
+                        w.extend(word.iter().copied());
+                        next.push((n, w));
+                    }
+                }
+            }
+            frontier = next;
+        }
+        table
+    })
+}
+
+/// Decompose an exact unitary into generators in matrix product order,
+/// together with a power of omega giving the global phase.
+pub(crate) fn decompose(m: &ExactMat) -> Option<(Vec<Generator>, u32)> {
+    let mut m = m.clone().normalise();
+    let mut word = vec![];
+    while !table().contains_key(&m) {
+        // Find j with sde(H T^j m) < sde(m), so m = T^(-j) H (H T^j m).
+        let sde = m.sde()?;
+        let (j, reduced) = (0..8).find_map(|j| {
+            let n = m.apply(Generator::T(j))?.apply(Generator::H)?;
+            (n.sde()? < sde).then_some((j, n))
+        })?;
+        if j != 0 {
+            word.push(Generator::T(8 - j));
+        }
+        word.push(Generator::H);
+        m = reduced;
+    }
+    let (rest, phase) = table().get(&m)?;
+    word.extend(rest.iter().copied());
+    Some((simplify(word), *phase))
+}
+
+/// Merge adjacent powers of T and cancel adjacent Hadamards.
+fn simplify(word: Vec<Generator>) -> Vec<Generator> {
+    let mut out: Vec<Generator> = vec![];
+    for g in word {
+        match (out.last().copied(), g) {
+            (Some(Generator::H), Generator::H) => {
+                out.pop();
+            }
+            (Some(Generator::T(i)), Generator::T(j)) => {
+                out.pop();
+                if (i + j) % 8 != 0 {
+                    out.push(Generator::T((i + j) % 8));
+                }
+            }
+            (_, Generator::T(0)) => {}
+            _ => out.push(g),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The unitary of a word in matrix product order.
+    fn product(word: &[Generator]) -> ExactMat {
+        word.iter()
+            .rev()
+            .try_fold(ExactMat::identity(), |m, &g| m.apply(g))
+            .unwrap()
+    }
+
+    #[test]
+    fn decompose_reproduces_matrix() {
+        // Words with T powers from a simple sequence, long enough to need reducing before
+        // the table lookup.
+        for len in [0, 1, 3, 8, 20, 40] {
+            let word: Vec<_> = (0..len)
+                .flat_map(|i| [Generator::H, Generator::T((3 * i + 1) % 8)])
+                .collect();
+            let m = product(&word);
+            let (decomposed, phase) = decompose(&m).expect("Exact unitaries should decompose");
+            assert_eq!(product(&decomposed).mul_omega_pow(phase), m);
+        }
+    }
+
+    #[test]
+    fn simplify_merges_and_cancels() {
+        use Generator::{H, T};
+        assert_eq!(simplify(vec![T(3), T(5), H, H, T(1)]), vec![T(1)]);
+        assert_eq!(simplify(vec![H, T(2), T(3), H]), vec![H, T(5), H]);
+        assert_eq!(simplify(vec![T(0), H]), vec![H]);
+    }
+}
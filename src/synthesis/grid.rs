@@ -0,0 +1,154 @@
+//! Grid problems: finding elements of Z[sqrt(2)] and Z[omega] in given regions.
+
+use std::f64::consts::SQRT_2;
+
+use crate::synthesis::ring::{ZOmega, ZRoot2};
+
+const LAMBDA: f64 = 1.0 + SQRT_2;
+
+/// Solve the one-dimensional grid problem, returning all x in Z[sqrt(2)] with
+/// x in [x0, x1] and x^bullet in [y0, y1].
+///
+/// The problem is first rescaled by a power of lambda so that both intervals
+/// have similar widths, which keeps the enumeration proportional to the number of solutions.
+pub(crate) fn grid_points(x0: f64, x1: f64, y0: f64, y1: f64) -> Vec<ZRoot2> {
+    if x1 < x0 || y1 < y0 {
+        return vec![];
+    }
+    let m = (((y1 - y0) / (x1 - x0).max(f64::MIN_POSITIVE)).ln() / (2.0 * LAMBDA.ln()))
+        .round()
+        .clamp(-200.0, 200.0) as i32;
+    // Scaling x by lambda^m scales x^bullet by (-lambda)^(-m).
+    let scale = LAMBDA.powi(m);
+    let (x0, x1) = (x0 * scale, x1 * scale);
+    let (y0, y1) = if m % 2 == 0 {
+        (y0 / scale, y1 / scale)
+    } else {
+        (-y1 / scale, -y0 / scale)
+    };
+    let mut points = vec![];
+    let b_min = ((x0 - y1) / (2.0 * SQRT_2)).ceil() as i128;
+    let b_max = ((x1 - y0) / (2.0 * SQRT_2)).floor() as i128;
+    for b in b_min..=b_max {
+        let offset = b as f64 * SQRT_2;
+        let a_min = (x0 - offset).max(y0 + offset).ceil() as i128;
+        let a_max = (x1 - offset).min(y1 + offset).floor() as i128;
+        for a in a_min..=a_max {
+            if let Some(p) = (ZRoot2 { a, b }).mul_lambda_pow(-m) {
+                points.push(p);
+            }
+        }
+    }
+    points
+}
+
+/// Candidate top-left entries u = U / sqrt(2)^k for approximating the phase `z`,
+/// each returned as (U, |u - z|^2).
+///
+/// Candidates satisfy |u| <= 1 and |u^bullet| <= 1 up to rounding, and
+/// lie in the epsilon-region Re(u z^*) >= 1 - epsilon^2 / 2.
+pub(crate) fn candidates(z: (f64, f64), epsilon: f64, k: u32) -> Vec<(ZOmega, f64)> {
+    // Write sqrt(2) U = X + iY, with X and Y in Z[sqrt(2)] and scaled radius r.
+    let r = SQRT_2.powi(k as i32 + 1);
+    let slack = 1e-15 * r;
+    let (c, s) = z;
+    let depth = 1.0 - epsilon * epsilon / 2.0;
+    // Endpoints of the chord bounding the epsilon-region.
+    let half_chord = (1.0 - depth * depth).max(0.0).sqrt();
+    let mut x_lo = (depth * c + half_chord * s).min(depth * c - half_chord * s);
+    let mut x_hi = (depth * c + half_chord * s).max(depth * c - half_chord * s);
+    if c >= depth {
+        x_hi = 1.0;
+    }
+    if -c >= depth {
+        x_lo = -1.0;
+    }
+    let mut result = vec![];
+    for x in grid_points(x_lo * r - slack, x_hi * r + slack, -r - slack, r + slack) {
+        let xf = x.to_f64();
+        let xc = x.conj().to_f64();
+        let disk = (r * r - xf * xf).max(0.0).sqrt() + slack;
+        let disk_conj = (r * r - xc * xc).max(0.0).sqrt() + slack;
+        let (mut y_lo, mut y_hi) = (-disk, disk);
+        // The half-plane X c + Y s >= depth * r.
+        let bound = (depth * r - slack - xf * c) / s;
+        if s > 1e-12 {
+            y_lo = y_lo.max(bound);
+        } else if s < -1e-12 {
+            y_hi = y_hi.min(bound);
+        }
+        for y in grid_points(y_lo, y_hi, -disk_conj, disk_conj) {
+            // The integer parts of X and Y must have the same parity.
+            if (x.a - y.a) % 2 != 0 {
+                continue;
+            }
+            let u = ZOmega {
+                coeffs: [x.b, (x.a + y.a) / 2, y.b, (y.a - x.a) / 2],
+            };
+            let (re, im) = (xf / r, y.to_f64() / r);
+            let dist_sq = (re - c).powi(2) + (im - s).powi(2);
+            result.push((u, dist_sq));
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// All solutions of the grid problem with coefficients of at most `bound`.
+    fn brute_force(x0: f64, x1: f64, y0: f64, y1: f64, bound: i128) -> Vec<ZRoot2> {
+        let mut points = vec![];
+        for a in -bound..=bound {
+            for b in -bound..=bound {
+                let p = ZRoot2 { a, b };
+                let (x, y) = (p.to_f64(), p.conj().to_f64());
+                if (x0..=x1).contains(&x) && (y0..=y1).contains(&y) {
+                    points.push(p);
+                }
+            }
+        }
+        points
+    }
+
+    fn sorted(mut points: Vec<ZRoot2>) -> Vec<ZRoot2> {
+        points.sort_by_key(|p| (p.a, p.b));
+        points
+    }
+
+    #[test]
+    fn grid_points_match_brute_force() {
+        for (x0, x1, y0, y1) in [
+            (1.0, 3.5, -2.0, 4.0),
+            (0.1, 0.2, -30.0, 30.0),
+            (-40.0, 40.0, -0.5, 0.5),
+            (-3.0, -1.0, 2.0, 2.5),
+        ] {
+            assert_eq!(
+                sorted(grid_points(x0, x1, y0, y1)),
+                sorted(brute_force(x0, x1, y0, y1, 60)),
+                "Grid problem [{x0}, {x1}] x [{y0}, {y1}]"
+            );
+        }
+        assert!(grid_points(1.0, 0.0, -1.0, 1.0).is_empty());
+    }
+
+    #[test]
+    fn candidates_lie_in_epsilon_region() {
+        let epsilon = 0.1;
+        let angle: f64 = 0.7;
+        let z = (angle.cos(), angle.sin());
+        for k in 0..6 {
+            let scale = SQRT_2.powi(k as i32);
+            for (u, dist_sq) in candidates(z, epsilon, k) {
+                let [a, b, c, d] = u.coeffs.map(|x| x as f64);
+                let re = (a + (b - d) / SQRT_2) / scale;
+                let im = (c + (b + d) / SQRT_2) / scale;
+                assert!(re * re + im * im <= 1.0 + 1e-9);
+                assert!(re * z.0 + im * z.1 >= 1.0 - epsilon * epsilon / 2.0 - 1e-9);
+                assert!(((re - z.0).powi(2) + (im - z.1).powi(2) - dist_sq).abs() < 1e-9);
+            }
+        }
+    }
+}
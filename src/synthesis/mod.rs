@@ -0,0 +1,271 @@
+//! Clifford+T synthesis of lowered circuits.
+//!
+//! Each Rz rotation is replaced by a sequence of Clifford+T gates approximating it within
+//! a requested epsilon, in operator norm. Rotations by multiples of pi/4 are synthesised
+//! exactly. Other rotations are approximated in the style of gridsynth: a candidate
+//! unitary with entries in Z[omega, 1/sqrt(2)] is found by solving grid problems and a norm
+//! equation, and is then decomposed exactly into Hadamard and T gates.
+//!
+//! Candidates are searched for in double precision, which limits the smallest supported
+//! epsilon to `MIN_EPSILON`.
+
+mod diophantine;
+mod exact;
+mod grid;
+mod ring;
+
+use std::{collections::HashMap, f64::consts::PI};
+
+use miette::Diagnostic;
+use thiserror::Error;
+
+use crate::{
+    lowered_syntax::{CircuitL, GateL},
+    synthesis::{
+        exact::{ExactMat, Generator},
+        ring::ZOmega,
+    },
+};
+
+/// Smallest epsilon supported by the synthesis.
+pub const MIN_EPSILON: f64 = 1e-7;
+
+/// Largest denominator exponent searched before giving up.
+const MAX_EXPONENT: u32 = 120;
+
+/// Errors that can occur during synthesis.
+#[derive(Error, Diagnostic, Debug, Clone)]
+pub enum SynthesisError {
+    /// Error for an epsilon which is too small or not positive.
+    #[error("Cannot synthesise to precision {epsilon:e}.")]
+    #[diagnostic(
+        code("Unsupported precision."),
+        help("The precision must be at least {MIN_EPSILON:e}.")
+    )]
+    UnsupportedEpsilon {
+        /// The requested precision
+        epsilon: f64,
+    },
+    /// Error for a rotation for which no approximation was found.
+    #[error("No Clifford+T approximation found for Rz({angle}pi).")]
+    #[diagnostic(code("Synthesis failed."))]
+    NotFound {
+        /// Angle of the rotation, divided by pi
+        angle: f64,
+    },
+}
+
+/// Summary of a synthesis pass.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SynthesisReport {
+    /// Number of rotations that were approximated rather than synthesised exactly.
+    pub approximated: usize,
+    /// Number of T and T^dagger gates in the output.
+    pub t_count: usize,
+    /// Largest error of a single rotation.
+    pub max_error: f64,
+    /// Sum of the errors of all rotations, which bounds the error of the whole circuit.
+    pub total_error: f64,
+}
+
+/// A Clifford+T sequence for a single rotation.
+#[derive(Clone)]
+struct Approximation {
+    /// Gates in circuit order, as powers of T and Hadamards.
+    word: Vec<Generator>,
+    /// Global phase, divided by pi.
+    phase: f64,
+    error: f64,
+}
+
+impl CircuitL {
+    /// Replace each Rz rotation with a Clifford+T sequence within `epsilon` of it.
+    pub fn synthesise(&self, epsilon: f64) -> Result<(CircuitL, SynthesisReport), SynthesisError> {
+        if epsilon.is_nan() || epsilon < MIN_EPSILON {
+            return Err(SynthesisError::UnsupportedEpsilon { epsilon });
+        }
+        let mut cache: HashMap<u64, Approximation> = HashMap::new();
+        let mut circuit = CircuitL::new(self.ty, self.ancillas);
+        circuit.phase = self.phase;
+        let mut report = SynthesisReport {
+            approximated: 0,
+            t_count: 0,
+            max_error: 0.0,
+            total_error: 0.0,
+        };
+        for gate in &self.gates {
+            let GateL::Rz(wire, angle) = gate else {
+                circuit.gates.push(gate.clone());
+                continue;
+            };
+            let approx = match cache.get(&angle.to_bits()) {
+                Some(a) => a.clone(),
+                None => {
+                    let a = approximate_rz(*angle, epsilon)
+                        .ok_or(SynthesisError::NotFound { angle: *angle })?;
+                    cache.insert(angle.to_bits(), a.clone());
+                    a
+                }
+            };
+            if approx.error > 0.0 {
+                report.approximated += 1;
+            }
+            report.max_error = report.max_error.max(approx.error);
+            report.total_error += approx.error;
+            circuit.phase += approx.phase;
+            for g in approx.word {
+                match g {
+                    Generator::H => circuit.gates.push(GateL::H(*wire)),
+                    Generator::T(j) => circuit.gates.extend(t_power(*wire, j)),
+                }
+            }
+        }
+        report.t_count = circuit
+            .gates
+            .iter()
+            .filter(|g| matches!(g, GateL::T(_) | GateL::Tdg(_)))
+            .count();
+        Ok((circuit, report))
+    }
+}
+
+/// Gates implementing T^j with at most one T gate.
+fn t_power(wire: usize, j: u32) -> Vec<GateL> {
+    match j % 8 {
+        1 => vec![GateL::T(wire)],
+        2 => vec![GateL::S(wire)],
+        3 => vec![GateL::S(wire), GateL::T(wire)],
+        4 => vec![GateL::Z(wire)],
+        5 => vec![GateL::Z(wire), GateL::T(wire)],
+        6 => vec![GateL::Sdg(wire)],
+        7 => vec![GateL::Tdg(wire)],
+        _ => vec![],
+    }
+}
+
+/// Approximate Rz(angle pi) = diag(z, z^*) with z = e^(-i pi angle / 2).
+fn approximate_rz(angle: f64, epsilon: f64) -> Option<Approximation> {
+    // Rz(m pi / 4) = e^(-i m pi / 8) T^m.
+    let m = angle * 4.0;
+    if (m - m.round()).abs() < 1e-12 {
+        let m = m.round();
+        return Some(Approximation {
+            word: vec![Generator::T(m.rem_euclid(8.0) as u32)],
+            phase: -m / 8.0,
+            error: 0.0,
+        });
+    }
+    let z = ((-angle * PI / 2.0).cos(), (-angle * PI / 2.0).sin());
+    for k in 0..=MAX_EXPONENT {
+        let mut candidates = grid::candidates(z, epsilon, k);
+        candidates.sort_by(|a, b| a.1.total_cmp(&b.1));
+        for (u, dist_sq) in candidates {
+            let Some(approx) = complete(u, k, dist_sq, epsilon) else {
+                continue;
+            };
+            return Some(approx);
+        }
+    }
+    None
+}
+
+/// Given a top-left entry u / sqrt(2)^k, find a unitary [[u, -t^dagger], [t, u^dagger]]
+/// within `epsilon` of the target and decompose it.
+fn complete(u: ZOmega, k: u32, dist_sq: f64, epsilon: f64) -> Option<Approximation> {
+    let xi = ZOmega::from_root2(ring::ZRoot2 {
+        a: 1i128.checked_shl(k)?,
+        b: 0,
+    })
+    .sub(ZOmega::from_root2(u.abs_sq()?))?
+    .to_root2()?;
+    if !xi.is_doubly_nonnegative()? {
+        return None;
+    }
+    let t_abs_sq = xi.to_f64() / 2f64.powi(k as i32);
+    let error = (dist_sq + t_abs_sq).sqrt();
+    if error > epsilon {
+        return None;
+    }
+    let t = diophantine::solve_norm_equation(xi)?;
+    let m = ExactMat {
+        entries: [u, t.adjoint().neg(), t, u.adjoint()],
+        k,
+    };
+    let (word, phase) = exact::decompose(&m)?;
+    // `word` is in matrix product order, so reverse it to get circuit order.
+    Some(Approximation {
+        word: word.into_iter().rev().collect(),
+        phase: phase as f64 / 4.0,
+        error,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use num_complex::Complex;
+
+    use super::*;
+
+    type Mat2 = [[Complex<f64>; 2]; 2];
+
+    fn mul(x: Mat2, y: Mat2) -> Mat2 {
+        let entry = |i: usize, j: usize| x[i][0] * y[0][j] + x[i][1] * y[1][j];
+        [[entry(0, 0), entry(0, 1)], [entry(1, 0), entry(1, 1)]]
+    }
+
+    /// The unitary of a word in circuit order, with its global phase.
+    fn unitary(approx: &Approximation) -> Mat2 {
+        let (zero, one) = (Complex::new(0.0, 0.0), Complex::new(1.0, 0.0));
+        let h = Complex::new(std::f64::consts::FRAC_1_SQRT_2, 0.0);
+        let phase = Complex::cis(PI * approx.phase);
+        approx
+            .word
+            .iter()
+            .fold([[phase, zero], [zero, phase]], |u, g| {
+                let gate = match g {
+                    Generator::H => [[h, h], [h, -h]],
+                    Generator::T(j) => [[one, zero], [zero, Complex::cis(PI * *j as f64 / 4.0)]],
+                };
+                mul(gate, u)
+            })
+    }
+
+    /// The operator norm of the difference of two unitaries of the form
+    /// [[a, -b^*], [b, a^*]] up to a common phase, which is sqrt(|a|^2 + |b|^2).
+    fn distance(x: Mat2, y: Mat2) -> f64 {
+        let a = x[0][0] - y[0][0];
+        let b = x[1][0] - y[1][0];
+        (a.norm_sqr() + b.norm_sqr()).sqrt()
+    }
+
+    fn rz(angle: f64) -> Mat2 {
+        let z = Complex::cis(-PI * angle / 2.0);
+        let zero = Complex::new(0.0, 0.0);
+        [[z, zero], [zero, z.conj()]]
+    }
+
+    #[test]
+    fn approximate_rz_is_within_epsilon() {
+        for epsilon in [1e-2, 1e-4, 1e-6] {
+            for angle in [0.1, 0.3, -0.7, 1.0 / 3.0, 1.9] {
+                let approx = approximate_rz(angle, epsilon).expect("Approximation should exist");
+                assert!(approx.error <= epsilon);
+                let d = distance(unitary(&approx), rz(angle));
+                assert!(
+                    d <= epsilon + 1e-9,
+                    "Rz({angle}pi) within {epsilon:e}, got {d:e}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn approximate_rz_is_exact_for_multiples_of_quarter_pi() {
+        for m in -8..=8 {
+            let angle = m as f64 / 4.0;
+            let approx = approximate_rz(angle, 1e-3).unwrap();
+            assert_eq!(approx.error, 0.0);
+            assert!(distance(unitary(&approx), rz(angle)) < 1e-12);
+        }
+    }
+}
@@ -0,0 +1,339 @@
+//! Exact arithmetic in the rings Z[sqrt(2)] and Z[omega], where omega = e^(i pi / 4).
+//!
+//! Arithmetic returns `None` on overflow, so that callers can discard candidates whose
+//! coefficients grow too large.
+
+use std::{cmp::Ordering, f64::consts::SQRT_2};
+
+/// An element a + b sqrt(2) of Z[sqrt(2)].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct ZRoot2 {
+    pub(crate) a: i128,
+    pub(crate) b: i128,
+}
+
+impl ZRoot2 {
+    pub(crate) const ZERO: ZRoot2 = ZRoot2 { a: 0, b: 0 };
+    /// The fundamental unit lambda = 1 + sqrt(2).
+    pub(crate) const LAMBDA: ZRoot2 = ZRoot2 { a: 1, b: 1 };
+    /// The inverse of lambda, sqrt(2) - 1.
+    pub(crate) const LAMBDA_INV: ZRoot2 = ZRoot2 { a: -1, b: 1 };
+
+    pub(crate) fn from_int(a: i128) -> Self {
+        ZRoot2 { a, b: 0 }
+    }
+
+    /// The Galois conjugate a - b sqrt(2).
+    pub(crate) fn conj(self) -> Self {
+        ZRoot2 {
+            a: self.a,
+            b: -self.b,
+        }
+    }
+
+    pub(crate) fn mul(self, other: Self) -> Option<Self> {
+        let two_bb = self.b.checked_mul(other.b)?.checked_mul(2)?;
+        Some(ZRoot2 {
+            a: self.a.checked_mul(other.a)?.checked_add(two_bb)?,
+            b: self
+                .a
+                .checked_mul(other.b)?
+                .checked_add(self.b.checked_mul(other.a)?)?,
+        })
+    }
+
+    /// Multiply by lambda^n, for possibly negative n.
+    pub(crate) fn mul_lambda_pow(self, n: i32) -> Option<Self> {
+        let unit = if n >= 0 {
+            ZRoot2::LAMBDA
+        } else {
+            ZRoot2::LAMBDA_INV
+        };
+        (0..n.unsigned_abs()).try_fold(self, |x, _| x.mul(unit))
+    }
+
+    /// The norm a^2 - 2b^2, equal to the product of the element with its conjugate.
+    pub(crate) fn norm(self) -> Option<i128> {
+        self.a
+            .checked_mul(self.a)?
+            .checked_sub(self.b.checked_mul(self.b)?.checked_mul(2)?)
+    }
+
+    /// The exact sign of the element.
+    pub(crate) fn sign(self) -> Option<Ordering> {
+        let sa = self.a.cmp(&0);
+        let sb = self.b.cmp(&0);
+        if sa == sb || sb == Ordering::Equal {
+            return Some(sa);
+        }
+        if sa == Ordering::Equal {
+            return Some(sb);
+        }
+        let aa = self.a.checked_mul(self.a)?;
+        let bb = self.b.checked_mul(self.b)?.checked_mul(2)?;
+        Some(if aa > bb { sa } else { sb })
+    }
+
+    /// Returns true if both the element and its conjugate are non-negative.
+    pub(crate) fn is_doubly_nonnegative(self) -> Option<bool> {
+        Some(self.sign()? != Ordering::Less && self.conj().sign()? != Ordering::Less)
+    }
+
+    /// Floating point value, computed to avoid cancellation between the two parts.
+    pub(crate) fn to_f64(self) -> f64 {
+        let approx = self.a as f64 + self.b as f64 * SQRT_2;
+        let conj = self.a as f64 - self.b as f64 * SQRT_2;
+        match self.norm() {
+            Some(n) if conj.abs() > approx.abs() => n as f64 / conj,
+            _ => approx,
+        }
+    }
+}
+
+/// An element a + b omega + c omega^2 + d omega^3 of Z[omega].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct ZOmega {
+    pub(crate) coeffs: [i128; 4],
+}
+
+impl ZOmega {
+    pub(crate) const ZERO: ZOmega = ZOmega { coeffs: [0; 4] };
+    pub(crate) const ONE: ZOmega = ZOmega {
+        coeffs: [1, 0, 0, 0],
+    };
+    pub(crate) const I: ZOmega = ZOmega {
+        coeffs: [0, 0, 1, 0],
+    };
+    /// The element 1 + omega, with delta^dagger delta = 2 + sqrt(2).
+    pub(crate) const DELTA: ZOmega = ZOmega {
+        coeffs: [1, 1, 0, 0],
+    };
+    /// The element i sqrt(2) = omega + omega^3.
+    pub(crate) const SQRT_MINUS_TWO: ZOmega = ZOmega {
+        coeffs: [0, 1, 0, 1],
+    };
+
+    pub(crate) fn from_root2(x: ZRoot2) -> Self {
+        // sqrt(2) = omega - omega^3
+        ZOmega {
+            coeffs: [x.a, x.b, 0, -x.b],
+        }
+    }
+
+    /// Returns the element as a member of Z[sqrt(2)], if it is real.
+    pub(crate) fn to_root2(self) -> Option<ZRoot2> {
+        let [a, b, c, d] = self.coeffs;
+        (c == 0 && b == -d).then_some(ZRoot2 { a, b })
+    }
+
+    pub(crate) fn is_zero(self) -> bool {
+        self.coeffs == [0; 4]
+    }
+
+    /// The complex conjugate.
+    pub(crate) fn adjoint(self) -> Self {
+        let [a, b, c, d] = self.coeffs;
+        ZOmega {
+            coeffs: [a, -d, -c, -b],
+        }
+    }
+
+    /// Multiply by omega^n.
+    pub(crate) fn mul_omega_pow(self, n: u32) -> Self {
+        let mut coeffs = self.coeffs;
+        for _ in 0..n % 8 {
+            let [a, b, c, d] = coeffs;
+            coeffs = [-d, a, b, c];
+        }
+        ZOmega { coeffs }
+    }
+
+    pub(crate) fn neg(self) -> Self {
+        ZOmega {
+            coeffs: self.coeffs.map(|x| -x),
+        }
+    }
+
+    pub(crate) fn add(self, other: Self) -> Option<Self> {
+        let mut coeffs = [0; 4];
+        for (i, c) in coeffs.iter_mut().enumerate() {
+            *c = self.coeffs[i].checked_add(other.coeffs[i])?;
+        }
+        Some(ZOmega { coeffs })
+    }
+
+    pub(crate) fn sub(self, other: Self) -> Option<Self> {
+        self.add(other.neg())
+    }
+
+    pub(crate) fn mul(self, other: Self) -> Option<Self> {
+        let mut coeffs = [0i128; 4];
+        for (i, x) in self.coeffs.iter().enumerate() {
+            for (j, y) in other.coeffs.iter().enumerate() {
+                let p = x.checked_mul(*y)?;
+                // omega^4 = -1
+                if i + j < 4 {
+                    coeffs[i + j] = coeffs[i + j].checked_add(p)?;
+                } else {
+                    coeffs[i + j - 4] = coeffs[i + j - 4].checked_sub(p)?;
+                }
+            }
+        }
+        Some(ZOmega { coeffs })
+    }
+
+    /// The squared absolute value, x^dagger x, which is an element of Z[sqrt(2)].
+    pub(crate) fn abs_sq(self) -> Option<ZRoot2> {
+        self.adjoint().mul(self)?.to_root2()
+    }
+
+    /// The integer norm |x|^2 |x^bullet|^2.
+    pub(crate) fn norm(self) -> Option<i128> {
+        self.abs_sq()?.norm()
+    }
+
+    /// Divide by sqrt(2), if the result lies in Z[omega].
+    pub(crate) fn div_root2(self) -> Option<Self> {
+        // x / sqrt(2) = x (omega - omega^3) / 2
+        let doubled = self.mul(ZOmega {
+            coeffs: [0, 1, 0, -1],
+        })?;
+        doubled.coeffs.iter().all(|c| c % 2 == 0).then(|| ZOmega {
+            coeffs: doubled.coeffs.map(|c| c / 2),
+        })
+    }
+
+    /// Division with remainder, rounding the exact quotient coordinate-wise.
+    fn div_rem(self, other: Self) -> Option<(Self, Self)> {
+        let n = other.norm()?;
+        let abs_sq = ZOmega::from_root2(other.abs_sq()?.conj());
+        let numerator = self.mul(other.adjoint())?.mul(abs_sq)?;
+        let mut q = [0; 4];
+        for (qi, x) in q.iter_mut().zip(numerator.coeffs) {
+            *qi = x
+                .checked_mul(2)?
+                .checked_add(n)?
+                .div_euclid(n.checked_mul(2)?);
+        }
+        let q = ZOmega { coeffs: q };
+        Some((q, self.sub(q.mul(other)?)?))
+    }
+
+    /// Exact division, if the result lies in Z[omega].
+    pub(crate) fn div_exact(self, other: Self) -> Option<Self> {
+        let (q, r) = self.div_rem(other)?;
+        r.is_zero().then_some(q)
+    }
+
+    /// A greatest common divisor, defined up to a unit.
+    pub(crate) fn gcd(self, other: Self) -> Option<Self> {
+        let (mut x, mut y) = (self, other);
+        while !y.is_zero() {
+            let (_, r) = x.div_rem(y)?;
+            if r.norm()? >= y.norm()? {
+                return None;
+            }
+            (x, y) = (y, r);
+        }
+        Some(x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use num_complex::Complex;
+
+    use super::*;
+
+    fn to_complex(x: ZOmega) -> Complex<f64> {
+        x.coeffs
+            .iter()
+            .enumerate()
+            .map(|(j, &c)| Complex::cis(PI * j as f64 / 4.0) * c as f64)
+            .sum()
+    }
+
+    const SAMPLES: [ZOmega; 4] = [
+        ZOmega {
+            coeffs: [1, 2, -3, 4],
+        },
+        ZOmega {
+            coeffs: [0, -1, 5, 2],
+        },
+        ZOmega {
+            coeffs: [7, 0, 0, -1],
+        },
+        ZOmega::DELTA,
+    ];
+
+    #[test]
+    fn mul_matches_complex_multiplication() {
+        for x in SAMPLES {
+            for y in SAMPLES {
+                let product = to_complex(x.mul(y).unwrap());
+                assert!((product - to_complex(x) * to_complex(y)).norm() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn abs_sq_is_real_and_matches_norm() {
+        assert_eq!(ZOmega::DELTA.abs_sq(), Some(ZRoot2 { a: 2, b: 1 }));
+        for x in SAMPLES {
+            let abs_sq = x.abs_sq().unwrap();
+            assert!((abs_sq.to_f64() - to_complex(x).norm_sqr()).abs() < 1e-9);
+            assert_eq!(x.norm(), abs_sq.norm());
+        }
+    }
+
+    #[test]
+    fn div_root2() {
+        let sqrt_two = ZOmega::from_root2(ZRoot2 { a: 0, b: 1 });
+        assert_eq!(sqrt_two.div_root2(), Some(ZOmega::ONE));
+        assert_eq!(ZOmega::ONE.div_root2(), None);
+        for x in SAMPLES {
+            assert_eq!(x.mul(sqrt_two).unwrap().div_root2(), Some(x));
+        }
+    }
+
+    #[test]
+    fn sign_is_exact() {
+        assert_eq!(ZRoot2::LAMBDA_INV.sign(), Some(Ordering::Greater));
+        assert_eq!(ZRoot2 { a: 3, b: -2 }.sign(), Some(Ordering::Greater));
+        assert_eq!(ZRoot2 { a: 1, b: -1 }.sign(), Some(Ordering::Less));
+        assert_eq!(ZRoot2::ZERO.sign(), Some(Ordering::Equal));
+        assert_eq!(ZRoot2::LAMBDA.is_doubly_nonnegative(), Some(false));
+        assert_eq!(ZRoot2 { a: 2, b: 1 }.is_doubly_nonnegative(), Some(true));
+    }
+
+    #[test]
+    fn lambda_powers_invert() {
+        let x = ZRoot2 { a: 5, b: -3 };
+        assert_eq!(x.mul_lambda_pow(7).unwrap().mul_lambda_pow(-7), Some(x));
+        assert_eq!(
+            ZRoot2::LAMBDA.mul(ZRoot2::LAMBDA_INV),
+            Some(ZRoot2::from_int(1))
+        );
+    }
+
+    #[test]
+    fn to_f64_avoids_cancellation() {
+        let small = ZRoot2::from_int(1).mul_lambda_pow(-20).unwrap();
+        let expected = (SQRT_2 - 1.0).powi(20);
+        assert!((small.to_f64() / expected - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn gcd_finds_common_factor() {
+        let x = ZOmega {
+            coeffs: [2, 1, 0, 1],
+        };
+        let three = ZOmega::from_root2(ZRoot2::from_int(3));
+        let five = ZOmega::from_root2(ZRoot2::from_int(5));
+        let g = x.mul(three).unwrap().gcd(x.mul(five).unwrap()).unwrap();
+        assert_eq!(g.norm(), x.norm());
+        assert!(g.div_exact(x).is_some());
+    }
+}
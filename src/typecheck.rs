@@ -1,11 +1,12 @@
 //! Functions and datastructures for type checking
 
-use std::collections::HashMap;
+use std::{collections::HashMap, rc::Rc};
 
-use miette::Diagnostic;
+use miette::{Diagnostic, SourceSpan};
 use thiserror::Error;
 
 use crate::{
+    normal_syntax::rules::Rule,
     raw_syntax::{PatternR, TermR, pattern::PatTensorR, term::TensorR},
     text::{Name, Span},
     typed_syntax::{PatternType, TermT, TermType},
@@ -59,6 +60,84 @@ pub enum TypeCheckError<S: Span> {
         /// Type of pattern 2
         ty2: PatternType,
     },
+    /// Error for mismatching type between a pattern and term in a branch of a "match".
+    #[error("Type mismatch between pattern and term in match branch")]
+    #[diagnostic(code("Match branch type mismatch."))]
+    BranchTypeMismatch {
+        /// Pattern
+        #[label("Has type {pty}")]
+        p: PatternR<S>,
+        /// Type of pattern
+        pty: PatternType,
+        /// Body term
+        #[label("Has type {tty}")]
+        t: TermR<S>,
+        /// Type of body term
+        tty: TermType,
+    },
+    /// Error for branches of a "match" whose patterns match different numbers of qubits.
+    #[error("Type mismatch between patterns of match branches")]
+    #[diagnostic(code("Match type mismatch."))]
+    MatchTypeMismatch {
+        /// Pattern 1
+        #[label("Has type {ty1}")]
+        p1: PatternR<S>,
+        /// Type of pattern 1
+        ty1: PatternType,
+        /// Pattern 2
+        #[label("Has type {ty2}")]
+        p2: PatternR<S>,
+        /// Type of pattern 2
+        ty2: PatternType,
+    },
+    /// Error for branches of a "match" whose patterns could not be shown to be orthogonal.
+    #[error("Could not show that the patterns of match branches are orthogonal.")]
+    #[diagnostic(
+        code("Overlapping match branches."),
+        help(
+            "Branch patterns should be tensors of kets and unitaries which match complementary \
+             kets, such as |0> and |1>, on a common wire."
+        )
+    )]
+    BranchesNotOrthogonal {
+        /// Pattern 1
+        #[label("This pattern")]
+        p1: PatternR<S>,
+        /// Pattern 2
+        #[label("may overlap with this pattern")]
+        p2: PatternR<S>,
+    },
+    /// Error for an "else" branch whose subspace could not be written as patterns of kets.
+    #[error("Could not find the subspace left by the branches of match.")]
+    #[diagnostic(
+        code("Invalid else branch."),
+        help(
+            "For an else branch, branch patterns should be tensors of kets and unitaries which \
+             match kets in the same basis on each wire."
+        )
+    )]
+    ElseNotExpressible {
+        /// Pattern of a branch
+        #[label("The rest of the subspace of this pattern can not be matched")]
+        p: PatternR<S>,
+        /// Body of the else branch
+        #[label("Else branch")]
+        t: TermR<S>,
+    },
+    /// Error for an "else" branch whose type does not match part of the subspace it covers.
+    #[error("Type mismatch in else branch of match")]
+    #[diagnostic(code("Else type mismatch."))]
+    ElseTypeMismatch {
+        /// Body of the else branch
+        #[label("Has type {tty}, but is applied where \"{remaining}\" leaves type {rty}")]
+        t: TermR<S>,
+        /// Type of the body
+        tty: TermType,
+        /// Pattern for part of the subspace left by the other branches
+        remaining: String,
+        /// Type left by this pattern
+        rty: TermType,
+    },
     /// Error for an unknown top-level symbol.
     #[error("Unrecognised top-level symbol {name}.")]
     #[diagnostic(code("Unknown symbol."))]
@@ -71,7 +150,10 @@ pub enum TypeCheckError<S: Span> {
     },
     /// Error for when a sqrt operation is applied to a term with compositions.
     #[error("Tried to root unrootable unitary term.")]
-    #[diagnostic(code("Invalid root."))]
+    #[diagnostic(
+        code("Invalid root."),
+        help("Use \"t ^ (1/2)\" to take the square root numerically.")
+    )]
     TermNotRootable {
         /// Term which contains compositions
         tm: TermR<S>,
@@ -80,8 +162,133 @@ pub enum TypeCheckError<S: Span> {
         #[label("Square root applied here")]
         span_of_root: S,
     },
+    /// Error for when a sqrt operation is applied to a gate whose definition has compositions.
+    #[error("Tried to root gate {name}, whose definition is unrootable.")]
+    #[diagnostic(
+        code("Invalid root."),
+        help("Use \"t ^ (1/2)\" to take the square root numerically.")
+    )]
+    GateNotRootable {
+        /// Name of the gate
+        name: Name,
+        /// Span of the use of the gate
+        #[label("Gate used here")]
+        span: S,
+        /// Span of sqrt term causing error
+        #[label("Square root applied here")]
+        span_of_root: S,
+        /// Composition in the definition of the gate
+        #[label("This composition in the definition prevents square rooting")]
+        composition: SourceSpan,
+    },
+    /// Error for a repetition or integer power repeating its body too many times.
+    #[error("Repetition count {count} is over the limit of {limit}.")]
+    #[diagnostic(
+        code("Too many repetitions."),
+        help(
+            "Repetitions are unrolled when evaluated, and integer powers of terms with \
+             compositions are expanded to repetitions."
+        )
+    )]
+    TooManyRepetitions {
+        /// Span of the repetition or power
+        #[label("Repeats its body {count} times")]
+        span: S,
+        /// Number of repetitions
+        count: String,
+        /// Largest number of repetitions allowed
+        limit: usize,
+    },
+    /// Error for a rewrite rule whose sides have different types.
+    #[error("Type mismatch between sides of rule")]
+    #[diagnostic(code("Rule type mismatch."))]
+    RuleTypeMismatch {
+        /// Left-hand side of rule
+        #[label("Has type {lty}")]
+        lhs: TermR<S>,
+        /// Type of left-hand side
+        lty: TermType,
+        /// Right-hand side of rule
+        #[label("Has type {rty}")]
+        rhs: TermR<S>,
+        /// Type of right-hand side
+        rty: TermType,
+    },
+    /// Error for a rewrite rule whose left-hand side is an identity.
+    #[error("Rule rewrites an identity.")]
+    #[diagnostic(
+        code("Identity rule."),
+        help("Rules rewriting an identity would match everywhere.")
+    )]
+    IdentityRule {
+        /// Left-hand side of rule
+        #[label("Evaluates to an identity")]
+        lhs: TermR<S>,
+    },
+    /// Error for a rewrite rule whose sides have different unitaries.
+    #[error("Rule is not sound: its sides have different unitaries.")]
+    #[diagnostic(code("Unsound rule."))]
+    UnsoundRule {
+        /// Left-hand side of rule
+        #[label("Left-hand side")]
+        lhs: TermR<S>,
+        /// Right-hand side of rule
+        #[label("Differs from the left-hand side by {deviation:.3e}")]
+        rhs: TermR<S>,
+        /// Largest difference between entries of the unitaries
+        deviation: f64,
+    },
+    /// Error for a term whose unitary would need more memory than the limit allows.
+    #[error("Unitary of term needs {required} of memory, over the limit of {limit}.")]
+    #[diagnostic(
+        code("Term too large."),
+        help(
+            "Raise the memory limit, or work with the circuit of the term (for example its \
+             resource estimates), which does not need the unitary."
+        )
+    )]
+    TooLarge {
+        /// Term whose unitary is too large
+        #[label("Has type {ty}")]
+        tm: TermR<S>,
+        /// Type of the term
+        ty: TermType,
+        /// Estimated memory needed for the unitary
+        required: String,
+        /// Memory limit
+        limit: String,
+    },
 }
 
-/// Typing enviroment, holding definitions of top level symbols.
+/// Entry of a top level symbol in an `Env`.
+pub(crate) struct GateEntry {
+    /// Definition, shared with the uses of the symbol
+    pub(crate) def: Rc<TermT>,
+    /// A composition in the definition, including in the definitions of gates it uses.
+    /// The symbol cannot be square rooted if there is one.
+    pub(crate) composition: Option<SourceSpan>,
+}
+
+/// Typing enviroment, holding definitions of top level symbols and rewrite rules.
 #[derive(Default)]
-pub struct Env(pub(crate) HashMap<Name, TermT>);
+pub struct Env {
+    /// Definitions of top level symbols
+    pub(crate) gates: HashMap<Name, GateEntry>,
+    /// Names of top level symbols, in the order they were defined
+    pub(crate) names: Vec<Name>,
+    /// Rewrite rules, checked to be sound
+    pub(crate) rules: Vec<Rule>,
+}
+
+impl Env {
+    /// Returns the top level symbols of this environment with their definitions, in the order
+    /// they were defined.
+    pub fn gates(&self) -> impl Iterator<Item = (&Name, &Rc<TermT>)> {
+        self.names.iter().map(|name| (name, &self.gates[name].def))
+    }
+
+    /// Returns the rewrite rules declared in this environment.
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+}
@@ -0,0 +1,184 @@
+//! Control over which gates are expanded during evaluation.
+//!
+//! By default evaluation expands every gate. An `InlinePolicy` keeps chosen gates opaque,
+//! so that they appear in normal-form terms as named atoms (see `GateN`) holding their
+//! evaluated definition and its unitary.
+
+use std::{
+    any::{Any, TypeId},
+    cell::RefCell,
+    collections::HashMap,
+    rc::Rc,
+};
+
+use crate::{
+    normal_syntax::{Buildable, TermN, term::GateN},
+    text::Name,
+    typed_syntax::TermT,
+};
+
+/// Options choosing which gates are kept opaque during evaluation.
+#[derive(Clone, Debug, Default)]
+pub struct InlinePolicy {
+    /// Names of gates to keep opaque
+    pub opaque: Vec<String>,
+    /// Number of nested gate definitions to expand. Gates used inside this many other gates
+    /// are kept opaque, so a depth of 0 keeps every gate of the evaluated term opaque.
+    pub max_depth: Option<usize>,
+    /// Gates acting on more than this many qubits are kept opaque
+    pub max_qubits: Option<usize>,
+}
+
+impl InlinePolicy {
+    /// Returns true if this policy expands every gate.
+    pub fn inlines_all(&self) -> bool {
+        self.opaque.is_empty() && self.max_depth.is_none() && self.max_qubits.is_none()
+    }
+
+    /// Returns true if a gate with the given name and number of qubits, used inside `depth`
+    /// other gates, should be kept opaque.
+    fn is_opaque(&self, name: &Name, qubits: usize, depth: usize) -> bool {
+        self.opaque.iter().any(|n| *n == name.to_string())
+            || self.max_depth.is_some_and(|d| depth >= d)
+            || self.max_qubits.is_some_and(|q| qubits > q)
+    }
+}
+
+/// Opaque gates, keyed by the address of their definition, the bits of the phase multiplier,
+/// and the depth they were used at. Definitions are keyed by address rather than name, as a
+/// name can be redefined, and are kept alive by the evaluated term for the whole evaluation.
+type OpaqueGates = HashMap<(*const TermT, u64, usize), GateN>;
+
+/// Evaluated definitions of expanded gates, keyed by the address of their definition, the
+/// bits of the phase multiplier, the depth they were expanded at, and the type they were
+/// evaluated to.
+type ExpandedGates = HashMap<(*const TermT, u64, usize, TypeId), Box<dyn Any>>;
+
+/// State threaded through evaluation: the policy, the current depth of nested gate
+/// definitions, and the gates evaluated so far, so that each gate is evaluated once for each
+/// phase multiplier it is used with.
+#[derive(Clone, Copy)]
+pub(crate) struct Inliner<'a> {
+    policy: &'a InlinePolicy,
+    depth: usize,
+    gates: &'a RefCell<OpaqueGates>,
+    expanded: &'a RefCell<ExpandedGates>,
+}
+
+impl<'a> Inliner<'a> {
+    /// Run `f` with a fresh inliner for the given policy.
+    pub(crate) fn with<T>(policy: &InlinePolicy, f: impl FnOnce(Inliner<'_>) -> T) -> T {
+        let gates = RefCell::new(HashMap::new());
+        let expanded = RefCell::new(HashMap::new());
+        f(Inliner {
+            policy,
+            depth: 0,
+            gates: &gates,
+            expanded: &expanded,
+        })
+    }
+
+    /// Returns the opaque gate for a use of `name` with the given phase multiplier, or `None`
+    /// if the gate should be expanded.
+    pub(crate) fn opaque_gate(
+        &self,
+        name: &Name,
+        def: &Rc<TermT>,
+        phase_mul: f64,
+    ) -> Option<GateN> {
+        if !self.policy.is_opaque(name, def.get_type().0, self.depth) {
+            return None;
+        }
+        let key = (Rc::as_ptr(def), phase_mul.to_bits(), self.key_depth());
+        if let Some(gate) = self.gates.borrow().get(&key) {
+            return Some(gate.clone());
+        }
+        let mut body: TermN = def.eval_with_phase_mul(phase_mul, self.deeper());
+        body.squash();
+        let gate = GateN::new(name.clone(), phase_mul, def.clone(), body);
+        self.gates.borrow_mut().insert(key, gate.clone());
+        Some(gate)
+    }
+
+    /// Evaluate the definition of an expanded gate with the given phase multiplier, reusing
+    /// the result of an earlier evaluation if there is one.
+    pub(crate) fn expand<B: Buildable>(&self, def: &Rc<TermT>, phase_mul: f64) -> B {
+        let key = (
+            Rc::as_ptr(def),
+            phase_mul.to_bits(),
+            self.key_depth(),
+            TypeId::of::<B>(),
+        );
+        if let Some(evalled) = self.expanded.borrow().get(&key) {
+            return evalled
+                .downcast_ref::<B>()
+                .expect("Cached evaluation should have the type in its key")
+                .clone();
+        }
+        let evalled: B = def.eval_with_phase_mul(phase_mul, self.deeper());
+        self.expanded
+            .borrow_mut()
+            .insert(key, Box::new(evalled.clone()));
+        evalled
+    }
+
+    /// The depth to key cached evaluations by. Only a depth limit makes the evaluation of a
+    /// definition depend on the depth it is used at.
+    fn key_depth(&self) -> usize {
+        if self.policy.max_depth.is_some() {
+            self.depth
+        } else {
+            0
+        }
+    }
+
+    /// The inliner to use inside the definition of a gate.
+    pub(crate) fn deeper(self) -> Self {
+        Inliner {
+            depth: self.depth + 1,
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{command::check_source, normal_syntax::term::AtomN, text::ToDoc};
+
+    /// Returns the evaluated definitions of the opaque gates in a composition.
+    fn gate_bodies(term: &TermN) -> Vec<String> {
+        let TermN::Comp(terms, _) = term else {
+            panic!("Term should be a composition");
+        };
+        terms
+            .iter()
+            .map(|t| match t {
+                TermN::Atom(AtomN::Gate(gate)) => {
+                    gate.body().quote().to_raw().to_doc().pretty(60).to_string()
+                }
+                _ => panic!("Term should be an opaque gate"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn opaque_gate_depends_on_depth() {
+        let (_, term) = check_source(
+            "gate B = if let |1> then -1,
+             gate C = B,
+             gate A = C,
+             gate D = A,
+             A ; D",
+        );
+        let policy = InlinePolicy {
+            opaque: vec!["A".to_owned()],
+            max_depth: Some(2),
+            ..InlinePolicy::default()
+        };
+        let mut evalled: TermN = term.eval_with_policy(&policy);
+        evalled.squash();
+        // At depth 0 the definition of A expands C, but at depth 1 it keeps C opaque.
+        assert_eq!(gate_bodies(&evalled), ["B", "C"]);
+    }
+}
@@ -8,3 +8,6 @@ pub use term::{TermT, TermType};
 
 pub mod pattern;
 pub use pattern::{PatternT, PatternType};
+
+pub mod inline;
+pub use inline::InlinePolicy;
@@ -10,7 +10,7 @@ use crate::{
         PatternR,
         pattern::{PatAtomR, PatAtomRInner, PatTensorR, PatTensorRInner, PatternRInner},
     },
-    typed_syntax::TermT,
+    typed_syntax::{TermT, inline::Inliner, term::ClauseCache},
 };
 
 /// A pattern type "qn < qm"
@@ -60,29 +60,29 @@ impl PatternT {
 
     /// Evaluate a term to a `PatternN`, expanding top level definitions
     /// and evaluating inverse and sqrt macros.
-    pub(super) fn eval(&self) -> PatternN {
+    pub(super) fn eval(&self, inliner: Inliner) -> PatternN {
         match self {
             PatternT::Comp(patterns) => {
                 if patterns.len() == 1 {
-                    patterns[0].eval()
+                    patterns[0].eval(inliner)
                 } else {
                     PatternN::Comp(
-                        patterns.iter().map(PatternT::eval).collect(),
+                        patterns.iter().map(|p| p.eval(inliner)).collect(),
                         self.get_type(),
                     )
                 }
             }
             PatternT::Tensor(patterns) => {
                 if patterns.len() == 1 {
-                    patterns[0].eval()
+                    patterns[0].eval(inliner)
                 } else {
-                    PatternN::Tensor(patterns.iter().map(PatternT::eval).collect())
+                    PatternN::Tensor(patterns.iter().map(|p| p.eval(inliner)).collect())
                 }
             }
             PatternT::Ket(states) => {
                 PatternN::Tensor(states.iter().map(|&state| PatternN::Ket(state)).collect())
             }
-            PatternT::Unitary(inner) => inner.eval(),
+            PatternT::Unitary(inner) => inner.eval_with_phase_mul(1.0, inliner),
         }
     }
 
@@ -90,12 +90,13 @@ impl PatternT {
         &self,
         pattern: &mut PatternC,
         inj: &mut Vec<usize>,
+        cache: &ClauseCache,
         clauses: &mut Vec<ClauseC>,
     ) {
         match self {
             PatternT::Comp(patterns) => {
                 for p in patterns {
-                    p.eval_circ(pattern, inj, clauses);
+                    p.eval_circ(pattern, inj, cache, clauses);
                 }
             }
             PatternT::Tensor(patterns) => {
@@ -103,7 +104,7 @@ impl PatternT {
                 for p in patterns.iter().rev() {
                     let size = p.get_type().0;
                     let mut i = inj.split_off(inj.len() - size);
-                    p.eval_circ(pattern, &mut i, clauses);
+                    p.eval_circ(pattern, &mut i, cache, clauses);
                     stack.push(i);
                 }
                 while let Some(i) = stack.pop() {
@@ -116,11 +117,31 @@ impl PatternT {
                 }
             }
             PatternT::Unitary(inner) => {
-                inner.eval_circ_clause(pattern, inj, -1.0, clauses);
+                inner.eval_circ_clause_cached(pattern, inj, -1.0, cache, clauses);
             }
         }
     }
 
+    /// Returns a circuit-normal pattern matching the same subspace as this pattern, if it is a
+    /// tensor of kets and unitary patterns.
+    pub(crate) fn to_ket_parts(&self) -> Option<PatternC> {
+        match self {
+            PatternT::Comp(patterns) if patterns.len() == 1 => patterns[0].to_ket_parts(),
+            PatternT::Comp(_) => None,
+            PatternT::Tensor(patterns) => {
+                let mut parts = vec![];
+                for p in patterns {
+                    parts.extend(p.to_ket_parts()?.parts);
+                }
+                Some(PatternC { parts })
+            }
+            PatternT::Ket(states) => Some(PatternC {
+                parts: states.iter().copied().map(Some).collect(),
+            }),
+            PatternT::Unitary(inner) => Some(PatternC::id(inner.get_type().0)),
+        }
+    }
+
     /// Convert to a raw pattern.
     pub fn to_raw(&self) -> PatternR<()> {
         let patterns = if let PatternT::Comp(patterns) = self {
@@ -1,17 +1,33 @@
 //! Term syntax terms.
 
-use std::{fmt::Display, iter::Sum};
+use std::{cell::RefCell, collections::HashMap, fmt::Display, iter::Sum, rc::Rc};
+
+use faer::Mat;
+use num_complex::Complex;
 
 use crate::{
-    circuit_syntax::{TermC, pattern::PatternC, term::ClauseC},
-    normal_syntax::{Buildable, term::AtomN},
+    budget::TooLargeError,
+    circuit_syntax::{
+        TermC,
+        pattern::PatternC,
+        repeat::{ItemC, RepeatC},
+        term::{ClauseC, PowerC},
+    },
+    normal_syntax::{
+        Buildable, TermN,
+        term::{AtomN, PowerN},
+    },
     phase::Phase,
+    power::Exponent,
     raw_syntax::{
         TermR,
         term::{AtomR, AtomRInner, TensorR, TensorRInner, TermRInner},
     },
     text::Name,
-    typed_syntax::{PatternT, PatternType},
+    typed_syntax::{
+        PatternT, PatternType,
+        inline::{InlinePolicy, Inliner},
+    },
 };
 
 /// A unitary type "qn <-> qn"
@@ -59,13 +75,67 @@ pub enum TermT {
     Gate {
         /// Name of symbol/gate
         name: Name,
-        /// Definition of symbol
-        def: Box<TermT>,
+        /// Definition of symbol, shared with the environment and every other use of it
+        def: Rc<TermT>,
     },
     /// Inverse of a term "t ^ -1"
     Inverse(Box<TermT>),
     /// Square root of a term "sqrt(t)"
     Sqrt(Box<TermT>),
+    /// Power of a term "t ^ r"
+    Power {
+        /// Base of the power
+        inner: Rc<TermT>,
+        /// Exponent of the power
+        exponent: Exponent,
+        /// Whether the base has no compositions, so that the power can be taken by scaling
+        /// its phases. Other integer powers are expanded to repetitions, and other powers are
+        /// computed numerically and kept opaque when evaluated.
+        rootable: bool,
+    },
+    /// Repetition of a term "repeat n { t }"
+    Repeat {
+        /// Body of the repetition
+        inner: Box<TermT>,
+        /// Number of times the body is applied
+        count: usize,
+    },
+}
+
+/// Clauses of gates, keyed by the address of their definition and the bits of the phase
+/// multiplier.
+type GateClauses = HashMap<(*const TermT, u64), Rc<Vec<ClauseC>>>;
+
+/// Whether the definitions of gates contain repetitions, keyed by the address of the
+/// definition.
+type GateRepetitions = HashMap<*const TermT, bool>;
+
+/// Circuit clauses of gates on their own wires, keyed by gate definition and phase multiplier,
+/// together with whether each gate definition contains repetitions.
+/// Definitions are keyed by address rather than name, as a name can be redefined.
+#[derive(Default)]
+pub(crate) struct ClauseCache {
+    clauses: RefCell<GateClauses>,
+    repetitions: RefCell<GateRepetitions>,
+}
+
+impl ClauseCache {
+    /// Returns the cached clauses for a gate, computing them with `f` if needed.
+    fn get_or_insert(
+        &self,
+        def: &Rc<TermT>,
+        phase_mul: f64,
+        f: impl FnOnce() -> Vec<ClauseC>,
+    ) -> Rc<Vec<ClauseC>> {
+        let key = (Rc::as_ptr(def), phase_mul.to_bits());
+        if let Some(clauses) = self.clauses.borrow().get(&key) {
+            return clauses.clone();
+        }
+        // Computed without holding the borrow, as gates may use other gates.
+        let clauses = Rc::new(f());
+        self.clauses.borrow_mut().insert(key, clauses.clone());
+        clauses
+    }
 }
 
 impl TermT {
@@ -80,6 +150,8 @@ impl TermT {
             TermT::Gate { def, .. } => def.get_type(),
             TermT::Inverse(inner) => inner.get_type(),
             TermT::Sqrt(inner) => inner.get_type(),
+            TermT::Power { inner, .. } => inner.get_type(),
+            TermT::Repeat { inner, .. } => inner.get_type(),
         }
     }
 
@@ -87,13 +159,21 @@ impl TermT {
     /// and evaluating inverse and sqrt macros.
     /// In particular this can be used to generate a `TermN` from a `TermT`.
     pub fn eval<B: Buildable>(&self) -> B {
-        self.eval_with_phase_mul(1.0)
+        self.eval_with_policy(&InlinePolicy::default())
+    }
+
+    /// Evaluate a term to a given `Buildable` type, expanding top level definitions except
+    /// those kept opaque by the given policy, and evaluating inverse and sqrt macros.
+    pub fn eval_with_policy<B: Buildable>(&self, policy: &InlinePolicy) -> B {
+        Inliner::with(policy, |inliner| self.eval_with_phase_mul(1.0, inliner))
     }
 
-    pub(super) fn eval_with_phase_mul<B: Buildable>(&self, phase_mul: f64) -> B {
+    pub(crate) fn eval_with_phase_mul<B: Buildable>(&self, phase_mul: f64, inliner: Inliner) -> B {
         match self {
             TermT::Comp(terms) => {
-                let mut mapped_terms = terms.iter().map(|t| t.eval_with_phase_mul(phase_mul));
+                let mut mapped_terms = terms
+                    .iter()
+                    .map(|t| t.eval_with_phase_mul(phase_mul, inliner));
                 if terms.len() == 1 {
                     mapped_terms.next().unwrap()
                 } else if phase_mul > 0.0 {
@@ -104,25 +184,111 @@ impl TermT {
             }
             TermT::Tensor(terms) => {
                 if terms.len() == 1 {
-                    terms[0].eval_with_phase_mul(phase_mul)
+                    terms[0].eval_with_phase_mul(phase_mul, inliner)
                 } else {
-                    B::tensor(terms.iter().map(|t| t.eval_with_phase_mul(phase_mul)))
+                    B::tensor(
+                        terms
+                            .iter()
+                            .map(|t| t.eval_with_phase_mul(phase_mul, inliner)),
+                    )
                 }
             }
             TermT::Id(ty) => B::comp(std::iter::empty(), ty),
             TermT::Phase(phase) => B::atom(AtomN::Phase(phase_mul * phase.eval())),
             TermT::IfLet { pattern, inner } => B::atom(AtomN::IfLet(
-                pattern.eval(),
-                Box::new(inner.eval_with_phase_mul(phase_mul)),
+                pattern.eval(inliner),
+                Box::new(inner.eval_with_phase_mul(phase_mul, inliner)),
                 TermType(pattern.get_type().0),
             )),
-            TermT::Gate { def, .. } => def.eval_with_phase_mul(phase_mul),
-            TermT::Inverse(inner) => inner.eval_with_phase_mul(-phase_mul),
-            TermT::Sqrt(inner) => inner.eval_with_phase_mul(phase_mul / 2.0),
+            TermT::Gate { name, def } => match inliner.opaque_gate(name, def, phase_mul) {
+                Some(gate) => B::atom(AtomN::Gate(gate)),
+                None => inliner.expand(def, phase_mul),
+            },
+            TermT::Inverse(inner) => inner.eval_with_phase_mul(-phase_mul, inliner),
+            TermT::Sqrt(inner) => inner.eval_with_phase_mul(phase_mul / 2.0, inliner),
+            TermT::Power {
+                inner,
+                exponent,
+                rootable,
+            } => {
+                let exponent = phase_mul * exponent.eval();
+                if *rootable {
+                    inner.eval_with_phase_mul(exponent, inliner)
+                } else if phase_mul.fract() != 0.0 {
+                    // Principal roots do not compose, so a root of a power is taken through
+                    // the spectrum of the power itself: ((X;Z)^2)^0.5 is i rather than X;Z.
+                    B::atom(AtomN::Power(PowerN::new(Rc::new(self.clone()), phase_mul)))
+                } else if let Some((count, sign)) = integer_power(exponent) {
+                    inner.eval_repeated(count, sign, inliner)
+                } else {
+                    B::atom(AtomN::Power(PowerN::new(inner.clone(), exponent)))
+                }
+            }
+            TermT::Repeat { inner, count } => inner.eval_repeated(*count, phase_mul, inliner),
         }
     }
 
+    /// Evaluate a composition of `count` copies of this term.
+    fn eval_repeated<B: Buildable>(&self, count: usize, phase_mul: f64, inliner: Inliner) -> B {
+        if count == 1 {
+            return self.eval_with_phase_mul(phase_mul, inliner);
+        }
+        let t = self.eval_with_phase_mul(phase_mul, inliner);
+        B::comp(std::iter::repeat_n(t, count), &self.get_type())
+    }
+
+    /// Returns true if this term contains a repetition, including integer powers of terms
+    /// with compositions, or uses a gate whose definition does.
+    /// Each gate definition is visited once.
+    pub fn has_repetition(&self) -> bool {
+        self.has_repetition_cached(&RefCell::default())
+    }
+
+    /// As `has_repetition`, reusing the results for gate definitions visited before.
+    fn has_repetition_cached(&self, cache: &RefCell<GateRepetitions>) -> bool {
+        match self {
+            TermT::Comp(terms) | TermT::Tensor(terms) => {
+                terms.iter().any(|t| t.has_repetition_cached(cache))
+            }
+            TermT::Id(_) | TermT::Phase(_) => false,
+            TermT::IfLet { inner, .. } | TermT::Inverse(inner) | TermT::Sqrt(inner) => {
+                inner.has_repetition_cached(cache)
+            }
+            TermT::Gate { def, .. } => {
+                let key = Rc::as_ptr(def);
+                if let Some(repeats) = cache.borrow().get(&key) {
+                    return *repeats;
+                }
+                let repeats = def.has_repetition_cached(cache);
+                cache.borrow_mut().insert(key, repeats);
+                repeats
+            }
+            TermT::Power {
+                inner,
+                exponent,
+                rootable,
+            } => {
+                (!rootable && integer_power(exponent.eval()).is_some())
+                    || inner.has_repetition_cached(cache)
+            }
+            TermT::Repeat { .. } => true,
+        }
+    }
+
+    /// Convert a term of type qn <-> qn to an n x n unitary matrix.
+    /// Every gate is kept opaque while evaluating, so that the unitary of each gate is
+    /// computed once and reused at each of its uses.
+    /// Returns an error if the unitary does not fit in the memory limit of `crate::budget`.
+    pub fn to_unitary(&self) -> Result<Mat<Complex<f64>>, TooLargeError> {
+        let policy = InlinePolicy {
+            max_depth: Some(0),
+            ..InlinePolicy::default()
+        };
+        self.eval_with_policy::<TermN>(&policy).to_unitary()
+    }
+
     /// Returns a `TermC` representing the "circuit-normal-form" of the term.
+    /// The clauses of each gate are computed once, and reused at each of its uses.
     pub fn eval_circ(&self) -> TermC {
         let mut clauses = vec![];
         let size = self.get_type().0;
@@ -134,22 +300,142 @@ impl TermT {
         }
     }
 
-    pub(super) fn eval_circ_clause(
+    /// Returns a `RepeatC` representing the "circuit-normal-form" of the term, keeping a
+    /// single copy of the clauses of each repetition.
+    pub fn eval_circ_repeated(&self) -> RepeatC {
+        let mut items = vec![];
+        let size = self.get_type().0;
+        let inj = (0..size).collect::<Vec<_>>();
+        let cache = ClauseCache::default();
+        self.eval_circ_items(&PatternC::id(size), &inj, 1.0, &cache, &mut items);
+        RepeatC {
+            items,
+            ty: self.get_type(),
+        }
+    }
+
+    /// As `eval_circ_clause_cached`, but keeping repetitions as repeated items.
+    fn eval_circ_items(
+        &self,
+        pattern: &PatternC,
+        inj: &[usize],
+        phase_mul: f64,
+        cache: &ClauseCache,
+        items: &mut Vec<ItemC>,
+    ) {
+        if !self.has_repetition_cached(&cache.repetitions) {
+            let mut clauses = vec![];
+            self.eval_circ_clause_cached(pattern, inj, phase_mul, cache, &mut clauses);
+            items.extend(clauses.into_iter().map(ItemC::Clause));
+            return;
+        }
+        match self {
+            TermT::Comp(terms) => {
+                if phase_mul < 0.0 {
+                    for t in terms.iter().rev() {
+                        t.eval_circ_items(pattern, inj, phase_mul, cache, items);
+                    }
+                } else {
+                    for t in terms {
+                        t.eval_circ_items(pattern, inj, phase_mul, cache, items);
+                    }
+                }
+            }
+            TermT::Tensor(terms) => {
+                let mut start = 0;
+                for t in terms {
+                    let end = start + t.get_type().0;
+                    t.eval_circ_items(pattern, &inj[start..end], phase_mul, cache, items);
+                    start = end;
+                }
+            }
+            TermT::IfLet {
+                pattern: if_pattern,
+                inner,
+            } => {
+                let mut unitary_clauses = Vec::new();
+                let mut inner_pattern = pattern.clone();
+                let mut inner_inj = inj.to_vec();
+                if_pattern.eval_circ(
+                    &mut inner_pattern,
+                    &mut inner_inj,
+                    cache,
+                    &mut unitary_clauses,
+                );
+                let temp: Vec<_> = unitary_clauses
+                    .iter()
+                    .rev()
+                    .map(|c| ItemC::Clause(c.invert()))
+                    .collect();
+                items.extend(unitary_clauses.into_iter().map(ItemC::Clause));
+                inner.eval_circ_items(&inner_pattern, &inner_inj, phase_mul, cache, items);
+                items.extend(temp);
+            }
+            // Gates with repetitions are expanded in place, rather than cached.
+            TermT::Gate { def, .. } => def.eval_circ_items(pattern, inj, phase_mul, cache, items),
+            TermT::Inverse(inner) => {
+                inner.eval_circ_items(pattern, inj, -phase_mul, cache, items);
+            }
+            TermT::Sqrt(inner) => {
+                inner.eval_circ_items(pattern, inj, phase_mul / 2.0, cache, items);
+            }
+            TermT::Power {
+                inner,
+                exponent,
+                rootable,
+            } => {
+                let exponent = phase_mul * exponent.eval();
+                if *rootable {
+                    inner.eval_circ_items(pattern, inj, exponent, cache, items);
+                } else if let Some((count, sign)) =
+                    integer_power(exponent).filter(|_| phase_mul.fract() == 0.0)
+                {
+                    let mut body = vec![];
+                    inner.eval_circ_items(pattern, inj, sign, cache, &mut body);
+                    items.push(ItemC::Repeat(body, count));
+                } else {
+                    let mut clauses = vec![];
+                    self.eval_circ_clause_cached(pattern, inj, phase_mul, cache, &mut clauses);
+                    items.extend(clauses.into_iter().map(ItemC::Clause));
+                }
+            }
+            TermT::Repeat { inner, count } => {
+                let mut body = vec![];
+                inner.eval_circ_items(pattern, inj, phase_mul, cache, &mut body);
+                items.push(ItemC::Repeat(body, *count));
+            }
+            TermT::Id(_) | TermT::Phase(_) => unreachable!("Term has no repetitions"),
+        }
+    }
+
+    pub(crate) fn eval_circ_clause(
         &self,
         pattern: &PatternC,
         inj: &[usize],
         phase_mul: f64,
         clauses: &mut Vec<ClauseC>,
+    ) {
+        let cache = ClauseCache::default();
+        self.eval_circ_clause_cached(pattern, inj, phase_mul, &cache, clauses);
+    }
+
+    pub(super) fn eval_circ_clause_cached(
+        &self,
+        pattern: &PatternC,
+        inj: &[usize],
+        phase_mul: f64,
+        cache: &ClauseCache,
+        clauses: &mut Vec<ClauseC>,
     ) {
         match self {
             TermT::Comp(terms) => {
                 if phase_mul < 0.0 {
                     for t in terms.iter().rev() {
-                        t.eval_circ_clause(pattern, inj, phase_mul, clauses);
+                        t.eval_circ_clause_cached(pattern, inj, phase_mul, cache, clauses);
                     }
                 } else {
                     for t in terms {
-                        t.eval_circ_clause(pattern, inj, phase_mul, clauses);
+                        t.eval_circ_clause_cached(pattern, inj, phase_mul, cache, clauses);
                     }
                 }
             }
@@ -158,7 +444,7 @@ impl TermT {
                 for t in terms {
                     let size = t.get_type().0;
                     let end = start + size;
-                    t.eval_circ_clause(pattern, &inj[start..end], phase_mul, clauses);
+                    t.eval_circ_clause_cached(pattern, &inj[start..end], phase_mul, cache, clauses);
                     start = end;
                 }
             }
@@ -169,6 +455,7 @@ impl TermT {
                 clauses.push(ClauseC {
                     pattern: pattern.clone(),
                     phase: phase_mul * phase.eval(),
+                    power: None,
                 });
             }
             TermT::IfLet {
@@ -178,22 +465,103 @@ impl TermT {
                 let mut unitary_clauses = Vec::new();
                 let mut inner_pattern = pattern.clone();
                 let mut inner_inj = inj.to_vec();
-                if_pattern.eval_circ(&mut inner_pattern, &mut inner_inj, &mut unitary_clauses);
+                if_pattern.eval_circ(
+                    &mut inner_pattern,
+                    &mut inner_inj,
+                    cache,
+                    &mut unitary_clauses,
+                );
                 let temp: Vec<_> = unitary_clauses.iter().rev().map(ClauseC::invert).collect();
                 clauses.extend(unitary_clauses);
 
-                inner.eval_circ_clause(&inner_pattern, &inner_inj, phase_mul, clauses);
+                inner.eval_circ_clause_cached(
+                    &inner_pattern,
+                    &inner_inj,
+                    phase_mul,
+                    cache,
+                    clauses,
+                );
 
                 clauses.extend(temp)
             }
             TermT::Gate { def, .. } => {
-                def.eval_circ_clause(pattern, inj, phase_mul, clauses);
+                let local = cache.get_or_insert(def, phase_mul, || {
+                    let size = def.get_type().0;
+                    let local_inj = (0..size).collect::<Vec<_>>();
+                    let mut local = vec![];
+                    def.eval_circ_clause_cached(
+                        &PatternC::id(size),
+                        &local_inj,
+                        phase_mul,
+                        cache,
+                        &mut local,
+                    );
+                    local
+                });
+                for clause in local.iter() {
+                    let mut p = pattern.clone();
+                    for (w, part) in clause.pattern.parts.iter().enumerate() {
+                        if part.is_some() {
+                            p.parts[inj[w]] = *part;
+                        }
+                    }
+                    clauses.push(ClauseC {
+                        pattern: p,
+                        phase: clause.phase,
+                        power: clause.power.as_ref().map(|power| PowerC {
+                            wires: power.wires.iter().map(|&w| inj[w]).collect(),
+                            power: power.power.clone(),
+                        }),
+                    });
+                }
             }
             TermT::Inverse(inner) => {
-                inner.eval_circ_clause(pattern, inj, -phase_mul, clauses);
+                inner.eval_circ_clause_cached(pattern, inj, -phase_mul, cache, clauses);
             }
             TermT::Sqrt(inner) => {
-                inner.eval_circ_clause(pattern, inj, phase_mul / 2.0, clauses);
+                inner.eval_circ_clause_cached(pattern, inj, phase_mul / 2.0, cache, clauses);
+            }
+            TermT::Power {
+                inner,
+                exponent,
+                rootable,
+            } => {
+                let exponent = phase_mul * exponent.eval();
+                if *rootable {
+                    inner.eval_circ_clause_cached(pattern, inj, exponent, cache, clauses);
+                } else if phase_mul.fract() != 0.0 {
+                    // A root of a power, as in `eval_with_phase_mul`.
+                    clauses.push(ClauseC {
+                        pattern: pattern.clone(),
+                        phase: 0.0,
+                        power: Some(PowerC {
+                            wires: inj.to_vec(),
+                            power: PowerN::new(Rc::new(self.clone()), phase_mul),
+                        }),
+                    });
+                } else if let Some((count, sign)) = integer_power(exponent) {
+                    let mut body = vec![];
+                    inner.eval_circ_clause_cached(pattern, inj, sign, cache, &mut body);
+                    for _ in 0..count {
+                        clauses.extend(body.iter().cloned());
+                    }
+                } else {
+                    clauses.push(ClauseC {
+                        pattern: pattern.clone(),
+                        phase: 0.0,
+                        power: Some(PowerC {
+                            wires: inj.to_vec(),
+                            power: PowerN::new(inner.clone(), exponent),
+                        }),
+                    });
+                }
+            }
+            TermT::Repeat { inner, count } => {
+                let mut body = vec![];
+                inner.eval_circ_clause_cached(pattern, inj, phase_mul, cache, &mut body);
+                for _ in 0..*count {
+                    clauses.extend(body.iter().cloned());
+                }
             }
         }
     }
@@ -210,15 +578,40 @@ impl TermT {
 
     fn to_raw_tensor(&self) -> TensorR<()> {
         let terms = if let TermT::Tensor(terms) = self {
-            terms.iter().map(|t| t.to_raw_atom()).collect()
+            let last = terms.len() - 1;
+            terms
+                .iter()
+                .enumerate()
+                .map(|(i, t)| {
+                    if i == last {
+                        t.to_raw_atom()
+                    } else {
+                        t.to_raw_closed_atom()
+                    }
+                })
+                .collect()
         } else {
             vec![self.to_raw_atom()]
         };
         TensorRInner { terms }.into()
     }
 
+    /// As `to_raw_atom`, but bracketing an "if let", whose body would otherwise extend over
+    /// whatever follows it.
+    fn to_raw_closed_atom(&self) -> AtomR<()> {
+        let atom = self.to_raw_atom();
+        if matches!(atom.inner, AtomRInner::IfLet { .. }) {
+            AtomRInner::Brackets(self.to_raw()).into()
+        } else {
+            atom
+        }
+    }
+
     fn to_raw_atom(&self) -> AtomR<()> {
         match self {
+            TermT::Comp(terms) | TermT::Tensor(terms) if terms.len() == 1 => {
+                return terms[0].to_raw_atom();
+            }
             TermT::Id(ty) => AtomRInner::Id(ty.0),
             TermT::Phase(phase) => AtomRInner::Phase(*phase),
             TermT::IfLet { pattern, inner } => AtomRInner::IfLet {
@@ -226,10 +619,157 @@ impl TermT {
                 inner: Box::new(inner.to_raw_tensor()),
             },
             TermT::Gate { name, .. } => AtomRInner::Gate(name.to_owned()),
-            TermT::Inverse(inner) => AtomRInner::Inverse(Box::new(inner.to_raw_atom())),
+            TermT::Inverse(inner) => AtomRInner::Inverse(Box::new(inner.to_raw_closed_atom())),
             TermT::Sqrt(inner) => AtomRInner::Sqrt(Box::new(inner.to_raw_atom())),
+            TermT::Power {
+                inner, exponent, ..
+            } => AtomRInner::Power(Box::new(inner.to_raw_closed_atom()), *exponent),
+            TermT::Repeat { inner, count } => AtomRInner::Repeat {
+                count: *count,
+                inner: Box::new(inner.to_raw()),
+            },
             t => AtomRInner::Brackets(t.to_raw()),
         }
         .into()
     }
 }
+
+/// Largest number of repetitions of a "repeat" or integer power.
+pub(crate) const MAX_REPETITIONS: usize = 1 << 20;
+
+/// Returns the number of repetitions and their direction, 1 or -1, for an integer exponent.
+/// Exponents over `MAX_REPETITIONS`, which can arise from powers of powers, are not expanded
+/// and are instead computed numerically.
+fn integer_power(exponent: f64) -> Option<(usize, f64)> {
+    (exponent.fract() == 0.0 && exponent.abs() <= MAX_REPETITIONS as f64)
+        .then(|| (exponent.abs() as usize, exponent.signum()))
+}
+
+#[cfg(test)]
+mod tests {
+    use faer::Mat;
+    use num_complex::Complex;
+
+    use crate::{
+        circuit_syntax::repeat::ItemC,
+        command::check_source,
+        normal_syntax::{TermN, term::AtomN},
+        text::ToDoc,
+        typed_syntax::inline::InlinePolicy,
+    };
+
+    /// A library where each gate uses the previous one twice, so that expanding the last gate
+    /// visits the first 2^depth times.
+    fn doubling_library(depth: usize, base: &str) -> String {
+        let mut src = format!("gate G0 = {base},\n");
+        for i in 1..=depth {
+            src += &format!("gate G{i} = G{} ; G{},\n", i - 1, i - 1);
+        }
+        src + &format!("G{depth}")
+    }
+
+    #[test]
+    fn has_repetition_visits_each_definition_once() {
+        let (_, term) = check_source(&doubling_library(64, "if let |1> then -1"));
+        assert!(!term.has_repetition());
+        let (_, term) = check_source(&doubling_library(64, "repeat 2 { if let |1> then -1 }"));
+        assert!(term.has_repetition());
+    }
+
+    #[test]
+    fn simplify_shares_nested_opaque_gates() {
+        let policy = InlinePolicy {
+            max_depth: Some(1),
+            ..InlinePolicy::default()
+        };
+        let src = doubling_library(40, "if let |1> then ph(0.1pi)");
+        let (_, term) = check_source(&src);
+        let mut evalled: TermN = term.eval_with_policy(&policy);
+        evalled.squash();
+        evalled.simplify();
+        assert_eq!(
+            evalled.quote().to_raw().to_doc().pretty(60).to_string(),
+            "G39; G39"
+        );
+
+        // Cancelling a gate against its inverse compares them without inverting definitions.
+        let (_, term) = check_source(&format!("{src} ; G40 ^ -1"));
+        let mut evalled: TermN = term.eval_with_policy(&policy);
+        evalled.squash();
+        evalled.simplify();
+        assert!(evalled.is_identity());
+    }
+
+    /// Asserts that a matrix is the given multiple of the identity.
+    fn assert_scalar(m: &Mat<Complex<f64>>, c: Complex<f64>) {
+        for i in 0..m.nrows() {
+            for j in 0..m.ncols() {
+                let expected = if i == j { c } else { Complex::ZERO };
+                assert!((m[(i, j)] - expected).norm() < 1e-9, "{m:?} should be {c}");
+            }
+        }
+    }
+
+    #[test]
+    fn root_of_power_uses_principal_root() {
+        // (X;Z)^2 is -1, whose principal square root is i.
+        let gates = "gate X = if let |-> then -1, gate Z = if let |1> then -1,";
+        for term in ["((X ; Z)^2)^0.5", "sqrt((X ; Z)^2)"] {
+            let (_, term) = check_source(&format!("{gates} {term}"));
+            let i = Complex::new(0.0, 1.0);
+            assert_scalar(&term.to_unitary().unwrap(), i);
+            assert_scalar(&term.eval_circ().to_unitary().unwrap(), i);
+            assert_scalar(&term.eval_circ_repeated().to_unitary().unwrap(), i);
+        }
+    }
+
+    #[test]
+    fn integer_powers_are_unrolled() {
+        let gates = "gate X = if let |-> then -1, gate Z = if let |1> then -1,";
+        let (_, term) = check_source(&format!("{gates} (X ; Z)^3"));
+        assert!(term.has_repetition());
+        let circ = term.eval_circ();
+        assert_eq!(circ.num_clauses(), 6);
+        assert_eq!(circ.opaque_powers(), 0);
+        let repeated = term.eval_circ_repeated();
+        assert!(matches!(repeated.items[..], [ItemC::Repeat(ref body, 3)] if body.len() == 2));
+        let deviation = (repeated.to_unitary().unwrap() - term.to_unitary().unwrap()).norm_max();
+        assert!(deviation < 1e-9);
+    }
+
+    #[test]
+    fn non_integer_powers_are_not_unrolled() {
+        let gates = "gate X = if let |-> then -1, gate Z = if let |1> then -1,";
+        let (_, term) = check_source(&format!("{gates} (X ; Z)^0.5"));
+        assert!(!term.has_repetition());
+        let evalled: TermN = term.eval();
+        assert!(matches!(evalled, TermN::Atom(AtomN::Power(_))));
+        let circ = term.eval_circ();
+        assert_eq!(circ.num_clauses(), 1);
+        assert_eq!(circ.opaque_powers(), 1);
+        let repeated = term.eval_circ_repeated();
+        assert!(matches!(repeated.items[..], [ItemC::Clause(ref c)] if c.power.is_some()));
+    }
+
+    #[test]
+    fn printed_terms_parse_to_the_same_term() {
+        let gates = "gate X = if let |-> then -1, gate Z = if let |1> then -1,";
+        for src in [
+            "( if let |+> then -1 ) x id",
+            "if let |1> x id x id then X x Z",
+            "( if let |1> x id then X ) x ( if let |0> then -1 ) x id",
+            "id x if let |1> x id then X",
+            "( if let |1> x id then X ) ^ -1",
+            "( if let |1> x id then X ^ -1 ) ^ 0.5",
+            "if let |1> x id x id then ( if let |0> then -1 ) x Z",
+        ] {
+            let (_, term) = check_source(&format!("{gates} {src}"));
+            let printed = term.to_raw().to_doc().pretty(80).to_string();
+            assert_eq!(printed, src);
+            let (_, reparsed) = check_source(&format!("{gates} {printed}"));
+            let deviation =
+                (reparsed.to_unitary().unwrap() - term.to_unitary().unwrap()).norm_max();
+            assert!(deviation < 1e-9, "{printed} should parse to {src}");
+        }
+    }
+}
@@ -0,0 +1,239 @@
+//! Extraction of circuits from graph-like ZX-diagrams.
+//!
+//! Gates are extracted from the outputs of the diagram towards its inputs, keeping a
+//! frontier of spiders each joined to one output. Phases on the frontier become Z phases,
+//! and Hadamard edges between frontier spiders become controlled Z gates. Row operations on
+//! the biadjacency matrix between the frontier and its neighbours become controlled nots,
+//! and are used to reduce some frontier spider to a single neighbour, which then replaces
+//! it on the frontier behind a Hadamard gate. Once every frontier spider is joined to an
+//! input, the remaining permutation of the wires is extracted as swaps.
+
+use crate::{
+    circuit_syntax::{TermC, pattern::PatternC, term::ClauseC},
+    ket::KetState,
+    typed_syntax::TermType,
+    zx::{
+        ZxError, ZxGraph,
+        graph::{EdgeType, VertexType, phase_is},
+    },
+};
+
+/// Gates extracted from a diagram.
+enum Gate {
+    Phase(usize, f64),
+    H(usize),
+    CZ(usize, usize),
+    CX(usize, usize),
+    Swap(usize, usize),
+}
+
+impl Gate {
+    /// Append clauses implementing this gate on the given number of qubits.
+    fn push_clauses(&self, qubits: usize, clauses: &mut Vec<ClauseC>) {
+        let clause = |parts: &[(usize, KetState)], phase: f64| {
+            let mut pattern = PatternC::id(qubits);
+            for &(q, s) in parts {
+                pattern.parts[q] = Some(s);
+            }
+            ClauseC {
+                pattern,
+                phase,
+                power: None,
+            }
+        };
+        match *self {
+            Gate::Phase(q, phase) => clauses.push(clause(&[(q, KetState::One)], phase)),
+            // H = e^(-i pi/4) S sqrt(X) S
+            Gate::H(q) => {
+                clauses.push(clause(&[(q, KetState::One)], 0.5));
+                clauses.push(clause(&[(q, KetState::Minus)], 0.5));
+                clauses.push(clause(&[(q, KetState::One)], 0.5));
+                clauses.push(clause(&[], -0.25));
+            }
+            Gate::CZ(a, b) => {
+                clauses.push(clause(&[(a, KetState::One), (b, KetState::One)], 1.0));
+            }
+            Gate::CX(c, t) => {
+                clauses.push(clause(&[(c, KetState::One), (t, KetState::Minus)], 1.0));
+            }
+            Gate::Swap(a, b) => {
+                for (c, t) in [(a, b), (b, a), (a, b)] {
+                    Gate::CX(c, t).push_clauses(qubits, clauses);
+                }
+            }
+        }
+    }
+}
+
+/// Reduce a matrix over the field with two elements to reduced row echelon form, returning
+/// the row operations used, as pairs `(source, target)` adding the source row to the
+/// target row.
+fn row_reduce(matrix: &mut [Vec<bool>]) -> Vec<(usize, usize)> {
+    let mut ops = vec![];
+    let cols = matrix.first().map_or(0, Vec::len);
+    let mut pivot_row = 0;
+    for col in 0..cols {
+        let Some(r) = (pivot_row..matrix.len()).find(|&r| matrix[r][col]) else {
+            continue;
+        };
+        if r != pivot_row {
+            add_row(matrix, r, pivot_row);
+            ops.push((r, pivot_row));
+        }
+        for t in 0..matrix.len() {
+            if t != pivot_row && matrix[t][col] {
+                add_row(matrix, pivot_row, t);
+                ops.push((pivot_row, t));
+            }
+        }
+        pivot_row += 1;
+    }
+    ops
+}
+
+fn add_row(matrix: &mut [Vec<bool>], source: usize, target: usize) {
+    let row = matrix[source].clone();
+    for (x, y) in matrix[target].iter_mut().zip(row) {
+        *x ^= y;
+    }
+}
+
+impl ZxGraph {
+    /// Extract a circuit-normal term equal to this diagram up to a global phase.
+    /// The diagram is brought into graph-like form first.
+    pub fn extract(&self) -> Result<TermC, ZxError> {
+        let mut g = self.clone();
+        g.to_graph_like();
+        let qubits = g.outputs.len();
+        if g.inputs.len() != qubits {
+            return Err(ZxError::NoFlow);
+        }
+        // Gates in reverse order, from the outputs towards the inputs.
+        let mut gates = vec![];
+        let mut frontier: Vec<Option<usize>> = g
+            .outputs
+            .iter()
+            .map(|&o| g.neighbours(o).next().map(|(v, _)| v))
+            .collect();
+        loop {
+            for (q, f) in frontier.iter().enumerate() {
+                let Some(f) = *f else { continue };
+                if !phase_is(g.phase(f), 0.0) {
+                    gates.push(Gate::Phase(q, g.phase(f)));
+                    g.set_phase(f, 0.0);
+                }
+            }
+            for a in 0..qubits {
+                for b in a + 1..qubits {
+                    if let (Some(fa), Some(fb)) = (frontier[a], frontier[b])
+                        && g.edge(fa, fb).is_some()
+                    {
+                        gates.push(Gate::CZ(a, b));
+                        g.remove_edge(fa, fb);
+                    }
+                }
+            }
+            // Frontier spiders joined to an input are finished once that is their only other
+            // neighbour, and are otherwise separated from the input by a new spider.
+            for (q, slot) in frontier.iter_mut().enumerate() {
+                let Some(f) = *slot else { continue };
+                let output = g.outputs[q];
+                let others: Vec<_> = g.neighbours(f).filter(|&(n, _)| n != output).collect();
+                let Some(&(b, ty)) = others.iter().find(|&&(n, _)| g.inputs.contains(&n)) else {
+                    continue;
+                };
+                if others.len() == 1 {
+                    if ty == EdgeType::Hadamard {
+                        gates.push(Gate::H(q));
+                    }
+                    *slot = None;
+                    continue;
+                }
+                let vertex = g.vertex(b).unwrap();
+                let (qubit, row) = (vertex.qubit, vertex.row);
+                let w = g.add_vertex(VertexType::Z, qubit, row + 0.5);
+                g.remove_edge(f, b);
+                g.add_edge(b, w, ty.toggle());
+                g.add_edge(w, f, EdgeType::Hadamard);
+            }
+            let rows: Vec<usize> = (0..qubits).filter(|&q| frontier[q].is_some()).collect();
+            if rows.is_empty() {
+                break;
+            }
+            let mut columns: Vec<usize> = vec![];
+            for &q in &rows {
+                for (n, _) in g.neighbours(frontier[q].unwrap()) {
+                    if n != g.outputs[q] && !columns.contains(&n) {
+                        columns.push(n);
+                    }
+                }
+            }
+            columns.sort_unstable();
+            let mut matrix: Vec<Vec<bool>> = rows
+                .iter()
+                .map(|&q| {
+                    let f = frontier[q].unwrap();
+                    columns.iter().map(|&n| g.edge(f, n).is_some()).collect()
+                })
+                .collect();
+            for (s, t) in row_reduce(&mut matrix) {
+                let (fs, ft) = (frontier[rows[s]].unwrap(), frontier[rows[t]].unwrap());
+                let neighbours: Vec<usize> = g
+                    .neighbours(fs)
+                    .map(|(n, _)| n)
+                    .filter(|n| columns.contains(n))
+                    .collect();
+                for n in neighbours {
+                    g.toggle_edge(ft, n);
+                }
+                gates.push(Gate::CX(rows[t], rows[s]));
+            }
+            let mut extracted = false;
+            for (r, row) in matrix.iter().enumerate() {
+                let mut ones = row.iter().enumerate().filter(|(_, x)| **x);
+                let (Some((c, _)), None) = (ones.next(), ones.next()) else {
+                    continue;
+                };
+                let q = rows[r];
+                let (f, w) = (frontier[q].unwrap(), columns[c]);
+                gates.push(Gate::H(q));
+                g.remove_vertex(f);
+                g.add_edge(w, g.outputs[q], EdgeType::Simple);
+                frontier[q] = Some(w);
+                extracted = true;
+            }
+            if !extracted {
+                return Err(ZxError::NoFlow);
+            }
+        }
+        // Each output is now joined through one spider to an input.
+        let mut source = vec![0; qubits];
+        for (q, &o) in g.outputs.iter().enumerate() {
+            let (f, _) = g.neighbours(o).next().ok_or(ZxError::NoFlow)?;
+            let input = g
+                .neighbours(f)
+                .find_map(|(n, _)| g.inputs.iter().position(|&i| i == n))
+                .ok_or(ZxError::NoFlow)?;
+            source[q] = input;
+        }
+        // Swaps moving input source[q] to wire q, in the order they are applied.
+        let mut holding: Vec<usize> = (0..qubits).collect();
+        let mut swaps = vec![];
+        for (q, &input) in source.iter().enumerate() {
+            let w = holding.iter().position(|&i| i == input).unwrap();
+            if w != q {
+                swaps.push(Gate::Swap(q, w));
+                holding.swap(q, w);
+            }
+        }
+        gates.extend(swaps.into_iter().rev());
+        let mut clauses = vec![];
+        for gate in gates.iter().rev() {
+            gate.push_clauses(qubits, &mut clauses);
+        }
+        Ok(TermC {
+            clauses,
+            ty: TermType(qubits),
+        })
+    }
+}
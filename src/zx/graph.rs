@@ -0,0 +1,396 @@
+//! Graphs representing ZX-diagrams.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    circuit_syntax::{TermC, term::ClauseC},
+    zx::ZxError,
+};
+
+/// Clauses matching on more wires than this are not converted to ZX-diagrams.
+const MAX_CONTROLS: usize = 10;
+
+/// Phases within this distance of each other, modulo 2, are treated as equal.
+const PHASE_TOLERANCE: f64 = 1e-9;
+
+/// Kinds of vertex of a ZX-diagram.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VertexType {
+    /// An input or output of the diagram.
+    Boundary,
+    /// A Z spider.
+    Z,
+    /// An X spider.
+    X,
+}
+
+/// Kinds of edge of a ZX-diagram.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeType {
+    /// A plain wire.
+    Simple,
+    /// A wire with a Hadamard gate on it.
+    Hadamard,
+}
+
+impl EdgeType {
+    /// Returns the other kind of edge.
+    pub fn toggle(self) -> Self {
+        match self {
+            EdgeType::Simple => EdgeType::Hadamard,
+            EdgeType::Hadamard => EdgeType::Simple,
+        }
+    }
+}
+
+/// A vertex of a ZX-diagram.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Vertex {
+    /// Kind of the vertex.
+    pub ty: VertexType,
+    /// Phase of a spider, divided by pi, in [0, 2).
+    pub phase: f64,
+    /// Position of the vertex across the wires, for drawing.
+    pub qubit: f64,
+    /// Position of the vertex along the wires, for drawing.
+    pub row: f64,
+}
+
+/// A ZX-diagram, as a graph of spiders and boundaries with at most one edge between any
+/// two vertices.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ZxGraph {
+    vertices: Vec<Option<Vertex>>,
+    edges: Vec<BTreeMap<usize, EdgeType>>,
+    pub(crate) inputs: Vec<usize>,
+    pub(crate) outputs: Vec<usize>,
+}
+
+/// Returns `phase` reduced to lie in [0, 2), rounding values close to 2 down to 0.
+pub(crate) fn normalise_phase(phase: f64) -> f64 {
+    let phase = phase.rem_euclid(2.0);
+    if 2.0 - phase < PHASE_TOLERANCE {
+        0.0
+    } else {
+        phase
+    }
+}
+
+/// Returns true if the phases are equal modulo 2.
+pub(crate) fn phase_is(phase: f64, value: f64) -> bool {
+    let d = (phase - value).rem_euclid(2.0);
+    d < PHASE_TOLERANCE || 2.0 - d < PHASE_TOLERANCE
+}
+
+impl ZxGraph {
+    /// Add a vertex of the given kind with phase zero.
+    pub fn add_vertex(&mut self, ty: VertexType, qubit: f64, row: f64) -> usize {
+        self.vertices.push(Some(Vertex {
+            ty,
+            phase: 0.0,
+            qubit,
+            row,
+        }));
+        self.edges.push(BTreeMap::new());
+        self.vertices.len() - 1
+    }
+
+    /// Remove a vertex and its edges.
+    pub fn remove_vertex(&mut self, v: usize) {
+        for n in std::mem::take(&mut self.edges[v]).into_keys() {
+            self.edges[n].remove(&v);
+        }
+        self.vertices[v] = None;
+    }
+
+    /// Returns the vertex with the given index, if it has not been removed.
+    pub fn vertex(&self, v: usize) -> Option<&Vertex> {
+        self.vertices.get(v)?.as_ref()
+    }
+
+    /// Returns the indices of the vertices of the diagram.
+    pub fn vertices(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.vertices.len()).filter(|&v| self.vertices[v].is_some())
+    }
+
+    /// Returns the number of vertices.
+    pub fn num_vertices(&self) -> usize {
+        self.vertices().count()
+    }
+
+    /// Returns the number of edges.
+    pub fn num_edges(&self) -> usize {
+        self.edges.iter().map(BTreeMap::len).sum::<usize>() / 2
+    }
+
+    /// Returns the input boundaries, in order.
+    pub fn inputs(&self) -> &[usize] {
+        &self.inputs
+    }
+
+    /// Returns the output boundaries, in order.
+    pub fn outputs(&self) -> &[usize] {
+        &self.outputs
+    }
+
+    /// Returns the kind of a vertex.
+    pub fn ty(&self, v: usize) -> VertexType {
+        self.vertices[v].as_ref().unwrap().ty
+    }
+
+    /// Returns the phase of a vertex, divided by pi.
+    pub fn phase(&self, v: usize) -> f64 {
+        self.vertices[v].as_ref().unwrap().phase
+    }
+
+    pub(crate) fn set_ty(&mut self, v: usize, ty: VertexType) {
+        self.vertices[v].as_mut().unwrap().ty = ty;
+    }
+
+    pub(crate) fn set_phase(&mut self, v: usize, phase: f64) {
+        self.vertices[v].as_mut().unwrap().phase = normalise_phase(phase);
+    }
+
+    pub(crate) fn add_to_phase(&mut self, v: usize, phase: f64) {
+        self.set_phase(v, self.phase(v) + phase);
+    }
+
+    /// Returns true if a vertex is an input or output.
+    pub fn is_boundary(&self, v: usize) -> bool {
+        self.ty(v) == VertexType::Boundary
+    }
+
+    /// Returns the neighbours of a vertex, with the kind of edge joining them.
+    pub fn neighbours(&self, v: usize) -> impl Iterator<Item = (usize, EdgeType)> + '_ {
+        self.edges[v].iter().map(|(&n, &ty)| (n, ty))
+    }
+
+    /// Returns the number of neighbours of a vertex.
+    pub fn degree(&self, v: usize) -> usize {
+        self.edges[v].len()
+    }
+
+    /// Returns the kind of the edge between two vertices, if there is one.
+    pub fn edge(&self, a: usize, b: usize) -> Option<EdgeType> {
+        self.edges[a].get(&b).copied()
+    }
+
+    /// Add an edge between two vertices, replacing any existing edge.
+    pub fn add_edge(&mut self, a: usize, b: usize, ty: EdgeType) {
+        self.edges[a].insert(b, ty);
+        self.edges[b].insert(a, ty);
+    }
+
+    /// Remove the edge between two vertices, if there is one.
+    pub fn remove_edge(&mut self, a: usize, b: usize) {
+        self.edges[a].remove(&b);
+        self.edges[b].remove(&a);
+    }
+
+    /// Add a Hadamard edge between two Z spiders, cancelling against an existing Hadamard
+    /// edge.
+    pub(crate) fn toggle_edge(&mut self, a: usize, b: usize) {
+        if self.edge(a, b).is_some() {
+            self.remove_edge(a, b);
+        } else {
+            self.add_edge(a, b, EdgeType::Hadamard);
+        }
+    }
+
+    /// Add an edge from a Z spider `a` to `n`, combining it with any existing edge between
+    /// them. Parallel simple edges between spiders of the same colour are equal to one, and
+    /// parallel Hadamard edges cancel. A simple edge parallel to a Hadamard edge becomes a
+    /// Hadamard self-loop once the spiders are fused, adding pi to the phase.
+    pub(crate) fn add_edge_smart(&mut self, a: usize, n: usize, ty: EdgeType) {
+        match (self.edge(a, n), ty) {
+            (None, _) => self.add_edge(a, n, ty),
+            (Some(EdgeType::Simple), EdgeType::Simple) => {}
+            (Some(EdgeType::Hadamard), EdgeType::Hadamard) => self.remove_edge(a, n),
+            (Some(_), _) => {
+                self.add_edge(a, n, EdgeType::Simple);
+                self.add_to_phase(a, 1.0);
+            }
+        }
+    }
+
+    /// Fuse the Z spider `b` into the Z spider `a`. The spiders must be joined by a simple
+    /// edge, or be otherwise known to be connected by a plain wire.
+    pub(crate) fn fuse(&mut self, a: usize, b: usize) {
+        self.add_to_phase(a, self.phase(b));
+        let neighbours: Vec<_> = self.neighbours(b).collect();
+        self.remove_vertex(b);
+        for (n, ty) in neighbours {
+            if n == a {
+                if ty == EdgeType::Hadamard {
+                    self.add_to_phase(a, 1.0);
+                }
+            } else {
+                self.add_edge_smart(a, n, ty);
+            }
+        }
+    }
+}
+
+/// Incremental construction of a ZX-diagram along the wires of a circuit.
+struct Builder {
+    graph: ZxGraph,
+    last: Vec<usize>,
+    hadamard: Vec<bool>,
+    row: Vec<f64>,
+    gadgets: bool,
+}
+
+impl Builder {
+    fn new(qubits: usize, gadgets: bool) -> Self {
+        let mut graph = ZxGraph::default();
+        let last: Vec<usize> = (0..qubits)
+            .map(|q| graph.add_vertex(VertexType::Boundary, q as f64, 0.0))
+            .collect();
+        graph.inputs = last.clone();
+        Builder {
+            graph,
+            last,
+            hadamard: vec![false; qubits],
+            row: vec![0.0; qubits],
+            gadgets,
+        }
+    }
+
+    /// Add a vertex to the end of a wire.
+    fn spider(&mut self, q: usize, ty: VertexType, phase: f64, row: f64) -> usize {
+        let v = self.graph.add_vertex(ty, q as f64, row);
+        self.graph.set_phase(v, phase);
+        let edge = if std::mem::take(&mut self.hadamard[q]) {
+            EdgeType::Hadamard
+        } else {
+            EdgeType::Simple
+        };
+        self.graph.add_edge(self.last[q], v, edge);
+        self.last[q] = v;
+        self.row[q] = row;
+        v
+    }
+
+    fn hadamard(&mut self, q: usize) {
+        self.hadamard[q] = !self.hadamard[q];
+    }
+
+    fn z_phase(&mut self, q: usize, phase: f64) {
+        self.spider(q, VertexType::Z, phase, self.row[q] + 1.0);
+    }
+
+    fn not(&mut self, q: usize) {
+        self.spider(q, VertexType::X, 1.0, self.row[q] + 1.0);
+    }
+
+    fn cnot(&mut self, c: usize, t: usize) {
+        let row = self.row[c].max(self.row[t]) + 1.0;
+        let a = self.spider(c, VertexType::Z, 0.0, row);
+        let b = self.spider(t, VertexType::X, 0.0, row);
+        self.graph.add_edge(a, b, EdgeType::Simple);
+    }
+
+    /// Apply the phase e^(i pi phase x), where x is the parity of the given wires, either
+    /// as a phase gadget or by computing the parity onto the last wire.
+    fn parity_phase(&mut self, wires: &[usize], phase: f64) {
+        if self.gadgets && wires.len() > 1 {
+            let row = wires.iter().map(|&q| self.row[q]).fold(0.0, f64::max) + 1.0;
+            let hub = self.graph.add_vertex(VertexType::Z, -1.0, row);
+            let leaf = self.graph.add_vertex(VertexType::Z, -2.0, row);
+            self.graph.set_phase(leaf, phase);
+            self.graph.add_edge(hub, leaf, EdgeType::Hadamard);
+            for &q in wires {
+                let v = self.spider(q, VertexType::Z, 0.0, row);
+                self.graph.add_edge(v, hub, EdgeType::Hadamard);
+            }
+            return;
+        }
+        let (&target, controls) = wires.split_last().unwrap();
+        for &c in controls {
+            self.cnot(c, target);
+        }
+        self.z_phase(target, phase);
+        for &c in controls.iter().rev() {
+            self.cnot(c, target);
+        }
+    }
+
+    fn clause(&mut self, clause: &ClauseC) -> Result<(), ZxError> {
+        if clause.power.is_some() {
+            return Err(ZxError::OpaquePower);
+        }
+        let matched: Vec<_> = clause
+            .pattern
+            .parts
+            .iter()
+            .enumerate()
+            .filter_map(|(wire, part)| Some((wire, (*part)?)))
+            .collect();
+        if matched.len() > MAX_CONTROLS {
+            return Err(ZxError::TooManyControls {
+                arity: matched.len(),
+                max: MAX_CONTROLS,
+            });
+        }
+        // Change basis so that the clause matches |1> on each wire.
+        for &(q, s) in &matched {
+            if s.is_hadamard_basis() {
+                self.hadamard(q);
+            }
+            if !s.bit() {
+                self.not(q);
+            }
+        }
+        // prod_i x_i = 2^(1-k) sum_{S nonempty} (-1)^(|S|+1) parity(S)
+        let k = matched.len();
+        for subset in 1..1usize << k {
+            let wires: Vec<usize> = (0..k)
+                .filter(|i| subset & (1 << i) != 0)
+                .map(|i| matched[i].0)
+                .collect();
+            let sign = if wires.len() % 2 == 1 { 1.0 } else { -1.0 };
+            let scale = (1u64 << (k - 1)) as f64;
+            self.parity_phase(&wires, sign * clause.phase / scale);
+        }
+        for &(q, s) in &matched {
+            if !s.bit() {
+                self.not(q);
+            }
+            if s.is_hadamard_basis() {
+                self.hadamard(q);
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> ZxGraph {
+        let row = self.row.iter().copied().fold(0.0, f64::max) + 1.0;
+        for q in 0..self.last.len() {
+            let o = self.spider(q, VertexType::Boundary, 0.0, row);
+            self.graph.outputs.push(o);
+        }
+        self.graph
+    }
+}
+
+impl TermC {
+    /// Build a ZX-diagram equal to this term up to a global phase.
+    pub fn to_zx(&self) -> Result<ZxGraph, ZxError> {
+        self.build_zx(false)
+    }
+
+    /// Build a ZX-diagram equal to this term up to a global phase, with the phase on each
+    /// parity of two or more wires given by a phase gadget. This makes phases on the same
+    /// parity easy to combine, but circuits can not be extracted from the result.
+    pub fn to_zx_with_gadgets(&self) -> Result<ZxGraph, ZxError> {
+        self.build_zx(true)
+    }
+
+    fn build_zx(&self, gadgets: bool) -> Result<ZxGraph, ZxError> {
+        let mut builder = Builder::new(self.ty.0, gadgets);
+        for clause in &self.clauses {
+            builder.clause(clause)?;
+        }
+        Ok(builder.finish())
+    }
+}
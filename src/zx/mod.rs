@@ -0,0 +1,52 @@
+//! ZX-diagrams of circuit-normal terms.
+//!
+//! Each clause of a circuit-normal term is a phase controlled on a pattern, which becomes a
+//! ZX-diagram by changing the basis of wires matched against |0>, |+> or |-> and expanding
+//! the remaining phase, controlled on |1> on every matched wire, as a sum of phases on the
+//! parities of the matched wires. Each parity is computed onto one wire with a ladder of
+//! controlled nots, so the diagram is built from Z and X spiders as a circuit.
+//!
+//! Diagrams can be exported to the `.qgraph` format read by PyZX, simplified by spider
+//! fusion, identity removal, local complementation and pivoting, and extracted back to a
+//! circuit-normal term. Diagrams are only considered up to a global phase and scalar.
+
+pub mod extract;
+pub mod graph;
+pub mod qgraph;
+pub mod simplify;
+
+use miette::Diagnostic;
+use thiserror::Error;
+
+pub use graph::ZxGraph;
+
+/// Errors that can occur when building or extracting a ZX-diagram.
+#[derive(Error, Diagnostic, Debug, Clone)]
+pub enum ZxError {
+    /// Error for a clause matching on too many wires to expand into parities.
+    #[error("Clause matches on {arity} wires, but at most {max} are supported.")]
+    #[diagnostic(
+        code("Clause too large for ZX-diagram."),
+        help("A clause matching on k wires expands to 2^k - 1 parities.")
+    )]
+    TooManyControls {
+        /// Number of wires matched by the clause
+        arity: usize,
+        /// Largest number of wires supported
+        max: usize,
+    },
+    /// Error for a diagram from which no circuit could be extracted.
+    #[error("Could not extract a circuit from the ZX-diagram.")]
+    #[diagnostic(
+        code("ZX extraction failed."),
+        help("Extraction needs the diagram to have a generalised flow.")
+    )]
+    NoFlow,
+    /// Error for a clause applying a numerical power of a term.
+    #[error("Clause applies a numerical power, which has no ZX-diagram.")]
+    #[diagnostic(
+        code("Opaque power in ZX-diagram."),
+        help("Powers of terms with compositions are computed numerically and kept opaque.")
+    )]
+    OpaquePower,
+}
@@ -0,0 +1,102 @@
+//! Export of ZX-diagrams in the `.qgraph` JSON format read by PyZX and Quantomatic.
+//!
+//! Boundaries are written as wire vertices and spiders as node vertices, with phases given
+//! as multiples of `\pi`. Hadamard edges are written as a Hadamard node vertex marked as an
+//! edge, joined to both ends by plain edges.
+
+use crate::{
+    diagram::format_angle,
+    zx::{
+        ZxGraph,
+        graph::{EdgeType, VertexType},
+    },
+};
+
+impl ZxGraph {
+    /// Render the diagram in the `.qgraph` JSON format.
+    pub fn to_qgraph(&self) -> String {
+        let name = |v: usize| {
+            if self.is_boundary(v) {
+                format!("b{v}")
+            } else {
+                format!("v{v}")
+            }
+        };
+        let coord = |v: usize| {
+            let vertex = self.vertex(v).unwrap();
+            (vertex.row, -vertex.qubit)
+        };
+        let mut wires = vec![];
+        let mut nodes = vec![];
+        let mut edges = vec![];
+        for v in self.vertices() {
+            let (x, y) = coord(v);
+            match self.ty(v) {
+                VertexType::Boundary => {
+                    let input = self.inputs.contains(&v);
+                    let output = self.outputs.contains(&v);
+                    wires.push(format!(
+                        "    \"{}\": {{\"annotation\": {{\"boundary\": true, \"coord\": [{x}, {y}], \
+                         \"input\": {input}, \"output\": {output}}}}}",
+                        name(v)
+                    ));
+                }
+                ty => {
+                    let ty = if ty == VertexType::Z { "Z" } else { "X" };
+                    let phase = self.phase(v);
+                    let value = if phase == 0.0 {
+                        String::new()
+                    } else {
+                        format!(", \"value\": \"{}\"", format_angle(phase, "\\\\pi"))
+                    };
+                    nodes.push(format!(
+                        "    \"{}\": {{\"annotation\": {{\"coord\": [{x}, {y}]}}, \
+                         \"data\": {{\"type\": \"{ty}\"{value}}}}}",
+                        name(v)
+                    ));
+                }
+            }
+        }
+        for a in self.vertices() {
+            for (b, ty) in self.neighbours(a) {
+                if b < a {
+                    continue;
+                }
+                let mut edge = |src: String, tgt: String| {
+                    edges.push(format!(
+                        "    \"e{}\": {{\"src\": \"{src}\", \"tgt\": \"{tgt}\"}}",
+                        edges.len()
+                    ));
+                };
+                match ty {
+                    EdgeType::Simple => edge(name(a), name(b)),
+                    EdgeType::Hadamard => {
+                        let ((xa, ya), (xb, yb)) = (coord(a), coord(b));
+                        let h = format!("h{a}_{b}");
+                        nodes.push(format!(
+                            "    \"{h}\": {{\"annotation\": {{\"coord\": [{}, {}]}}, \
+                             \"data\": {{\"type\": \"hadamard\", \"is_edge\": \"true\"}}}}",
+                            (xa + xb) / 2.0,
+                            (ya + yb) / 2.0
+                        ));
+                        edge(name(a), h.clone());
+                        edge(name(b), h);
+                    }
+                }
+            }
+        }
+        let section = |key: &str, items: &[String]| {
+            if items.is_empty() {
+                format!("  \"{key}\": {{}}")
+            } else {
+                format!("  \"{key}\": {{\n{}\n  }}", items.join(",\n"))
+            }
+        };
+        format!(
+            "{{\n{},\n{},\n{},\n  \"variables\": {{}}\n}}\n",
+            section("wire_vertices", &wires),
+            section("node_vertices", &nodes),
+            section("undir_edges", &edges)
+        )
+    }
+}
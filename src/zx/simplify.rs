@@ -0,0 +1,361 @@
+//! Simplification of ZX-diagrams.
+//!
+//! Diagrams are first brought into graph-like form, where every spider is a Z spider,
+//! spiders are joined by Hadamard edges, and each boundary is joined by a simple edge to a
+//! spider of its own. Interior spiders are then removed by identity removal, local
+//! complementation of spiders with phase ±pi/2, and pivoting on pairs of adjacent spiders
+//! with phase 0 or pi. These rules preserve the flow of the diagram, so a circuit can
+//! still be extracted from the result.
+
+use crate::{
+    circuit_syntax::{TermC, term::ClauseC},
+    zx::{
+        ZxError, ZxGraph,
+        graph::{EdgeType, VertexType, phase_is},
+    },
+};
+
+/// Summary of a simplification pass.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SimplifyReport {
+    /// Number of spiders before simplification.
+    pub before: usize,
+    /// Number of spiders after simplification.
+    pub after: usize,
+    /// Number of identity spiders removed.
+    pub identities: usize,
+    /// Number of local complementations.
+    pub local_complementations: usize,
+    /// Number of pivots.
+    pub pivots: usize,
+    /// Number of phase gadgets fused or removed.
+    pub gadgets: usize,
+}
+
+impl ZxGraph {
+    /// Returns the number of spiders.
+    pub fn num_spiders(&self) -> usize {
+        self.vertices().filter(|&v| !self.is_boundary(v)).count()
+    }
+
+    /// Returns true if a spider has no boundary neighbours.
+    fn is_interior(&self, v: usize) -> bool {
+        self.neighbours(v).all(|(n, _)| !self.is_boundary(n))
+    }
+
+    /// Returns a spider joined to `v` by a simple edge, if there is one.
+    fn simple_neighbour(&self, v: usize) -> Option<usize> {
+        self.neighbours(v)
+            .find(|&(n, ty)| ty == EdgeType::Simple && !self.is_boundary(n))
+            .map(|(n, _)| n)
+    }
+
+    /// Bring the diagram into graph-like form.
+    pub fn to_graph_like(&mut self) {
+        // Change the colour of each X spider, toggling the edges joining it to spiders of
+        // the other colour and to boundaries.
+        let xs: Vec<usize> = self
+            .vertices()
+            .filter(|&v| self.ty(v) == VertexType::X)
+            .collect();
+        for &v in &xs {
+            let neighbours: Vec<_> = self.neighbours(v).collect();
+            for (n, ty) in neighbours {
+                if self.ty(n) != VertexType::X {
+                    self.add_edge(v, n, ty.toggle());
+                }
+            }
+        }
+        for v in xs {
+            self.set_ty(v, VertexType::Z);
+        }
+        // Fuse spiders joined by simple edges.
+        let spiders: Vec<usize> = self.vertices().filter(|&v| !self.is_boundary(v)).collect();
+        for a in spiders {
+            while self.vertex(a).is_some()
+                && let Some(b) = self.simple_neighbour(a)
+            {
+                self.fuse(a, b);
+            }
+        }
+        // Join each boundary by a simple edge to a spider with no other input or output.
+        for boundaries in [self.inputs.clone(), self.outputs.clone()] {
+            let mut claimed = vec![];
+            for b in boundaries {
+                let (v, ty) = self.neighbours(b).next().unwrap();
+                if !self.is_boundary(v) && ty == EdgeType::Simple && !claimed.contains(&v) {
+                    claimed.push(v);
+                    continue;
+                }
+                let (qubit, row) = {
+                    let vertex = self.vertex(b).unwrap();
+                    (vertex.qubit, vertex.row)
+                };
+                let step = if self.inputs.contains(&b) { 0.5 } else { -0.5 };
+                self.remove_edge(b, v);
+                let w = self.add_vertex(VertexType::Z, qubit, row + step);
+                self.add_edge(b, w, EdgeType::Simple);
+                claimed.push(w);
+                match ty {
+                    EdgeType::Hadamard => self.add_edge(w, v, EdgeType::Hadamard),
+                    EdgeType::Simple => {
+                        let u = self.add_vertex(VertexType::Z, qubit, row + 2.0 * step);
+                        self.add_edge(w, u, EdgeType::Hadamard);
+                        self.add_edge(u, v, EdgeType::Hadamard);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns true if fusing two spiders leaves at most one input and one output joined to
+    /// the result.
+    fn can_share_boundaries(&self, a: usize, b: usize) -> bool {
+        let count = |list: &[usize]| {
+            [a, b]
+                .iter()
+                .flat_map(|&v| self.neighbours(v))
+                .filter(|(n, _)| list.contains(n))
+                .count()
+        };
+        count(&self.inputs) <= 1 && count(&self.outputs) <= 1
+    }
+
+    /// Remove a spider with phase 0 and two Hadamard edges, fusing its neighbours.
+    fn remove_identity(&mut self) -> bool {
+        let found = self.vertices().find_map(|v| {
+            if self.is_boundary(v) || !phase_is(self.phase(v), 0.0) || self.degree(v) != 2 {
+                return None;
+            }
+            let mut neighbours = self.neighbours(v);
+            let (a, ta) = neighbours.next()?;
+            let (b, tb) = neighbours.next()?;
+            let valid = ta == EdgeType::Hadamard
+                && tb == EdgeType::Hadamard
+                && !self.is_boundary(a)
+                && !self.is_boundary(b)
+                && self.can_share_boundaries(a, b);
+            valid.then_some((v, a, b))
+        });
+        let Some((v, a, b)) = found else {
+            return false;
+        };
+        self.remove_vertex(v);
+        self.fuse(a, b);
+        true
+    }
+
+    /// Remove an interior spider with phase ±pi/2 by local complementation.
+    fn local_complement(&mut self) -> bool {
+        let found = self.vertices().find(|&v| {
+            !self.is_boundary(v)
+                && self.is_interior(v)
+                && (phase_is(self.phase(v), 0.5) || phase_is(self.phase(v), 1.5))
+        });
+        let Some(v) = found else {
+            return false;
+        };
+        let phase = self.phase(v);
+        let neighbours: Vec<usize> = self.neighbours(v).map(|(n, _)| n).collect();
+        self.remove_vertex(v);
+        for (i, &a) in neighbours.iter().enumerate() {
+            self.add_to_phase(a, -phase);
+            for &b in &neighbours[i + 1..] {
+                self.toggle_edge(a, b);
+            }
+        }
+        true
+    }
+
+    /// Remove a pair of adjacent interior spiders with phases 0 or pi by pivoting.
+    fn pivot(&mut self) -> bool {
+        let pauli = |g: &Self, v: usize| {
+            !g.is_boundary(v)
+                && g.is_interior(v)
+                && (phase_is(g.phase(v), 0.0) || phase_is(g.phase(v), 1.0))
+        };
+        let found = self.vertices().find_map(|u| {
+            if !pauli(self, u) {
+                return None;
+            }
+            self.neighbours(u)
+                .find(|&(v, _)| v > u && pauli(self, v))
+                .map(|(v, _)| (u, v))
+        });
+        let Some((u, v)) = found else {
+            return false;
+        };
+        let (pu, pv) = (self.phase(u), self.phase(v));
+        let nu: Vec<usize> = self
+            .neighbours(u)
+            .map(|(n, _)| n)
+            .filter(|&n| n != v)
+            .collect();
+        let nv: Vec<usize> = self
+            .neighbours(v)
+            .map(|(n, _)| n)
+            .filter(|&n| n != u)
+            .collect();
+        let only_u: Vec<usize> = nu.iter().copied().filter(|n| !nv.contains(n)).collect();
+        let only_v: Vec<usize> = nv.iter().copied().filter(|n| !nu.contains(n)).collect();
+        let shared: Vec<usize> = nu.iter().copied().filter(|n| nv.contains(n)).collect();
+        self.remove_vertex(u);
+        self.remove_vertex(v);
+        for (xs, ys) in [(&only_u, &only_v), (&only_u, &shared), (&only_v, &shared)] {
+            for &x in xs {
+                for &y in ys {
+                    self.toggle_edge(x, y);
+                }
+            }
+        }
+        for &n in &only_u {
+            self.add_to_phase(n, pv);
+        }
+        for &n in &only_v {
+            self.add_to_phase(n, pu);
+        }
+        for &n in &shared {
+            self.add_to_phase(n, pu + pv + 1.0);
+        }
+        true
+    }
+
+    /// Returns the phase gadgets of the diagram, as their leaf and hub together with the
+    /// sorted neighbours of the hub other than the leaf.
+    fn gadgets(&self) -> Vec<(usize, usize, Vec<usize>)> {
+        self.vertices()
+            .filter_map(|leaf| {
+                if self.is_boundary(leaf) || self.degree(leaf) != 1 {
+                    return None;
+                }
+                let (hub, ty) = self.neighbours(leaf).next()?;
+                if ty != EdgeType::Hadamard || self.is_boundary(hub) || self.degree(hub) < 2 {
+                    return None;
+                }
+                let targets: Vec<usize> = self
+                    .neighbours(hub)
+                    .map(|(n, _)| n)
+                    .filter(|&n| n != leaf)
+                    .collect();
+                let valid = self.neighbours(hub).all(|(n, ty)| {
+                    ty == EdgeType::Hadamard
+                        && !self.is_boundary(n)
+                        && (n == leaf || self.degree(n) > 1)
+                });
+                valid.then_some((leaf, hub, targets))
+            })
+            .collect()
+    }
+
+    /// Fuse two phase gadgets on the same spiders, or remove a phase gadget with phase 0.
+    fn fuse_gadgets(&mut self) -> bool {
+        let gadgets = self.gadgets();
+        for (i, (leaf, hub, targets)) in gadgets.iter().enumerate() {
+            if phase_is(self.phase(*hub), 0.0) && phase_is(self.phase(*leaf), 0.0) {
+                self.remove_vertex(*leaf);
+                self.remove_vertex(*hub);
+                return true;
+            }
+            let Some((other_leaf, other_hub, _)) =
+                gadgets[i + 1..].iter().find(|(_, _, t)| t == targets)
+            else {
+                continue;
+            };
+            // A gadget whose hub has phase pi is a gadget with the opposite phase.
+            let phase = |g: &Self, leaf: usize, hub: usize| {
+                if phase_is(g.phase(hub), 1.0) {
+                    -g.phase(leaf)
+                } else {
+                    g.phase(leaf)
+                }
+            };
+            let total = phase(self, *leaf, *hub) + phase(self, *other_leaf, *other_hub);
+            self.set_phase(*hub, 0.0);
+            self.set_phase(*leaf, total);
+            self.remove_vertex(*other_leaf);
+            self.remove_vertex(*other_hub);
+            return true;
+        }
+        false
+    }
+
+    /// Simplify the diagram, bringing it into graph-like form and removing spiders until no
+    /// rule applies. A circuit can still be extracted from the result.
+    pub fn simplify(&mut self) -> SimplifyReport {
+        self.simplify_with(false)
+    }
+
+    /// Simplify the diagram as for `simplify`, also fusing phase gadgets on the same spiders.
+    /// This can remove more spiders, but extraction may fail on the result.
+    pub fn full_simplify(&mut self) -> SimplifyReport {
+        self.simplify_with(true)
+    }
+
+    fn simplify_with(&mut self, gadgets: bool) -> SimplifyReport {
+        let mut report = SimplifyReport {
+            before: self.num_spiders(),
+            ..SimplifyReport::default()
+        };
+        self.to_graph_like();
+        loop {
+            if self.remove_identity() {
+                report.identities += 1;
+            } else if self.local_complement() {
+                report.local_complementations += 1;
+            } else if self.pivot() {
+                report.pivots += 1;
+            } else if gadgets && self.fuse_gadgets() {
+                report.gadgets += 1;
+            } else {
+                break;
+            }
+        }
+        report.after = self.num_spiders();
+        report
+    }
+
+    /// Returns true if the diagram joins each input to the output with the same index by a
+    /// plain wire, possibly through a spider with phase 0.
+    pub fn is_identity(&self) -> bool {
+        let wire = |i: usize, o: usize| match self.neighbours(o).next() {
+            Some((n, EdgeType::Simple)) if n == i => true,
+            Some((n, EdgeType::Simple)) => {
+                !self.is_boundary(n)
+                    && phase_is(self.phase(n), 0.0)
+                    && self.degree(n) == 2
+                    && self.edge(n, i) == Some(EdgeType::Simple)
+            }
+            _ => false,
+        };
+        let spiders = self.num_spiders();
+        self.inputs.len() == self.outputs.len()
+            && self
+                .inputs
+                .iter()
+                .zip(&self.outputs)
+                .all(|(&i, &o)| wire(i, o))
+            && spiders
+                == self
+                    .outputs
+                    .iter()
+                    .filter(|&&o| self.neighbours(o).all(|(n, _)| !self.is_boundary(n)))
+                    .count()
+    }
+}
+
+impl TermC {
+    /// Returns true if simplifying the ZX-diagram of this term followed by the inverse of
+    /// `other` gives the identity, proving the terms equal up to a global phase.
+    /// A result of false is inconclusive.
+    pub fn zx_equivalent(&self, other: &TermC) -> Result<bool, ZxError> {
+        let mut clauses = self.clauses.clone();
+        clauses.extend(other.clauses.iter().rev().map(ClauseC::invert));
+        let mut graph = TermC {
+            clauses,
+            ty: self.ty,
+        }
+        .to_zx_with_gadgets()?;
+        graph.full_simplify();
+        Ok(graph.is_identity())
+    }
+}